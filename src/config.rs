@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 /* application window */
 pub const WINDOW_WIDTH: f32 = 640.0;
 pub const WINDOW_HEIGHT: f32 = 480.0;
@@ -41,3 +43,82 @@ pub fn window_settings() -> iced::window::Settings {
         // platform_specific: WINDOW_PLATFORM_SPECIFIC,
     }
 }
+
+/* terminal */
+/// Lines kept in each session's scrollback
+pub const TERMINAL_SCROLLBACK_LIMIT: usize = 50_000;
+/// Height of a display row, rows are fixed height so only visible ones are laid out
+pub const TERMINAL_ROW_HEIGHT: f32 = 20.0;
+/// A partial received line is shown after this long without more data
+pub const LINE_IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/* session logging */
+/// Directory session logs are written to, relative to the working directory
+pub const LOG_DIRECTORY: &str = "logs";
+/// `{port}`, `{date}` and `{time}` are replaced when a log is started
+pub const LOG_FILENAME_TEMPLATE: &str = "{port}_{date}_{time}.log";
+
+/* file sending */
+pub const FILE_CHUNK_SIZES: [usize; 6] = [64, 128, 256, 512, 1024, 4096];
+pub const FILE_CHUNK_SIZE: usize = 256;
+/// Pause between chunks in milliseconds, for devices without flow control
+pub const FILE_CHUNK_DELAYS_MS: [u64; 7] = [0, 5, 10, 20, 50, 100, 500];
+
+/* command history */
+/// Where submitted commands are saved, relative to the working directory
+pub const HISTORY_FILE: &str = "history.json";
+pub const HISTORY_LIMIT: usize = 500;
+
+/* macros */
+/// Where the macro buttons are saved, relative to the working directory
+pub const MACROS_FILE: &str = "macros.json";
+
+/* scripts */
+/// Timeout of `wait_for` and `read_line` when the script gives none
+pub const SCRIPT_DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a waiting script checks whether it was stopped
+pub const SCRIPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Received text kept for `wait_for` and `read_line`, older text is dropped
+pub const SCRIPT_BUFFER_LIMIT: usize = 64 * 1024;
+
+/* wait-and-respond sequences */
+/// Where sequence profiles are saved, relative to the working directory
+pub const SEQUENCES_FILE: &str = "sequences.json";
+pub const SEQUENCE_STEP_TIMEOUT_MS: u64 = 5000;
+pub const SEQUENCE_TIMEOUTS_MS: [u64; 8] = [500, 1000, 2000, 5000, 10000, 30000, 60000, 300000];
+/// How often running sequences check for step timeouts
+pub const SEQUENCE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Received text kept for matching, older text is dropped
+pub const SEQUENCE_BUFFER_LIMIT: usize = 16 * 1024;
+
+/* file transfer protocols */
+/// Directory files received with XMODEM, YMODEM or ZMODEM are saved to
+pub const TRANSFER_DIRECTORY: &str = "downloads";
+/// How long to wait for the other side before repeating the last packet
+pub const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+pub const TRANSFER_MAX_RETRIES: u32 = 10;
+pub const TRANSFER_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Data sent before waiting for a ZACK
+pub const ZMODEM_FRAME_SIZE: usize = 8192;
+pub const ZMODEM_SUBPACKET_SIZE: usize = 1024;
+
+/* terminal emulation */
+pub const VT_COLUMNS: usize = 80;
+pub const VT_ROWS: usize = 24;
+pub const VT_SCROLLBACK_LINES: usize = 1000;
+
+/* serial connection */
+pub const SERIAL_BAUD_RATES: [u32; 12] = [
+    300, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600,
+];
+pub const RECONNECT_ENABLED: bool = true;
+pub const RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+/// Choices for the first and the longest wait between reconnect attempts
+pub const RECONNECT_BACKOFFS_MS: [u64; 8] = [100, 250, 500, 1000, 2000, 5000, 10000, 30000];
+
+/* tcp connection */
+pub const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/* rfc 2217 connection */
+pub const RFC2217_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
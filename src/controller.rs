@@ -1,335 +1,592 @@
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::myserial::{DataBits, FlowControl, Parity, SerialPortInfo, StopBits};
+use crate::config::{
+    LINE_IDLE_TIMEOUT, RECONNECT_ENABLED, RECONNECT_INITIAL_BACKOFF, RECONNECT_MAX_BACKOFF,
+};
+use crate::line_assembler::{LineAssembler, ReceiveMode, ReceivedData};
+use crate::line_ending::LineEndings;
+use crate::myserial::{DataBits, FlowControl, LineSettings, Parity, SerialPortInfo, StopBits};
+use crate::transport::{Endpoint, ModemState, Transport};
 
 use anyhow::Error;
 use anyhow::{anyhow, Result};
-use bitcore::api as bitcore;
-use serialport::SerialPortBuilder;
+use iced::futures::channel::mpsc as ui_mpsc;
+use iced::futures::{SinkExt, Stream};
+use iced::Subscription;
+use serialport::SerialPortType;
 use std::result::Result::Ok;
-use std::thread::JoinHandle;
-use std::{thread, time::Duration};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Identifies each stream so reconnecting a session starts a fresh subscription
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+/// Tags each queued write so its outcome can be told apart from other writes
+static NEXT_WRITE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Requests a `TerminalController` sends to its running `TerminalRunner`
+#[derive(Debug, Clone)]
+pub enum RunnerCommand {
+    /// Data to send, with the id its `Written` or `WriteError` event carries
+    Write(u64, Vec<u8>),
+    SetReconnectPolicy(ReconnectPolicy),
+    /// Line settings to apply to the open port without reopening it
+    SetLineSettings(LineSettings),
+}
+
+/// Events reported by a `TerminalRunner` back to its `TerminalController`
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    /// The runner is up, outgoing data is accepted through this sender
+    Ready(UnboundedSender<RunnerCommand>),
+    /// The endpoint was opened at the given time
+    Connected(SystemTime),
+    ConnectFailed(String),
+    Data(Vec<u8>, SystemTime),
+    /// The queued write with this id went out
+    Written(u64),
+    WriteError(u64, String),
+    ReadError(String),
+    /// The open port rejected new line settings
+    LineSettingsError(String),
+    ModemState(ModemState),
+    VirtualPortReady(String),
+    DeviceLost(String),
+    Reconnected(String),
+    Disconnected(String),
+}
+
+/// Connection state as seen by the UI
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionStatus {
+    Disconnected,
+    Connecting(String),
+    Connected(String),
+    WaitingForDevice(String),
+    Failed(String),
+}
+
+impl ConnectionStatus {
+    /// Whether a controller is attached, either opening, open or waiting for the device
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            ConnectionStatus::Connecting(_)
+                | ConnectionStatus::Connected(_)
+                | ConnectionStatus::WaitingForDevice(_)
+        )
+    }
+}
+
+/// How the runner behaves when the device disappears
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: RECONNECT_ENABLED,
+            initial_backoff: RECONNECT_INITIAL_BACKOFF,
+            max_backoff: RECONNECT_MAX_BACKOFF,
+        }
+    }
+}
 
 pub struct TerminalController {
-    thread_id: u32,
-    thread_handle: Option<JoinHandle<()>>,
-    thread_transmitter: Option<Box<Sender<String>>>,
-    received_data_receiver: Option<Receiver<String>>,
+    id: u32,
+    stream_id: Option<u64>,
+    endpoint: Option<Endpoint>,
+    transmitter: Option<UnboundedSender<RunnerCommand>>,
+    reconnect_policy: ReconnectPolicy,
+    /// Line settings changed before the runner was ready to take them
+    pending_line_settings: Option<LineSettings>,
+    line_endings: LineEndings,
+    line_assembler: LineAssembler,
 }
 impl TerminalController {
-    pub fn new(thread_id: u32) -> Self {
+    pub fn new(id: u32) -> Self {
         TerminalController {
-            thread_id: thread_id,
-            thread_handle: None,
-            thread_transmitter: None,
-            received_data_receiver: None,
+            id,
+            stream_id: None,
+            endpoint: None,
+            transmitter: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            pending_line_settings: None,
+            line_endings: LineEndings::default(),
+            line_assembler: LineAssembler::new(
+                ReceiveMode::default(),
+                LineEndings::default().rx,
+                LINE_IDLE_TIMEOUT,
+            ),
         }
     }
 
-    pub fn create_stream(&mut self, sinfo: &SerialPortInfo) {
-        // Create stream should always be used in tandem with get_latest_thread_created
-        let sinfo_2 = sinfo.clone();
+    pub fn set_line_endings(&mut self, line_endings: LineEndings) {
+        if line_endings.rx != self.line_endings.rx {
+            self.line_assembler.set_newline(line_endings.rx);
+        }
+        self.line_endings = line_endings;
+    }
 
-        let thread_closure = move |thread_id: u32,
-                                   rx: Receiver<String>,
-                                   tx_received: Sender<String>,
-                                   sinfo: SerialPortInfo| {
-            let thread_controller = TerminalRunner::new(thread_id, rx, tx_received);
-            thread_controller.bitcore_action_loop(sinfo);
-        };
+    /// Switches between line assembly and raw chunks, returning any partial line
+    pub fn set_receive_mode(&mut self, mode: ReceiveMode) -> Option<ReceivedData> {
+        self.line_assembler.set_mode(mode)
+    }
 
-        let (tx_send, rx_send) = mpsc::channel(); // For sending data to serial port
-        let (tx_received, rx_received) = mpsc::channel(); // For receiving data from serial port
+    /// Takes effect on the running connection too, including while it waits
+    /// for the device to return
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect_policy = policy;
+        if let Some(sender) = &self.transmitter {
+            if sender
+                .send(RunnerCommand::SetReconnectPolicy(policy))
+                .is_err()
+            {
+                println!("Controller {}: Runner gone, policy not sent", self.id);
+            }
+        }
+    }
 
-        let thread_id = self.thread_id;
-        let handle =
-            thread::spawn(move || thread_closure(thread_id, rx_send, tx_received, sinfo_2));
+    /// Applies line settings to the running connection, reconnects after the
+    /// device was lost use them too
+    pub fn set_line_settings(&mut self, settings: LineSettings) {
+        if let Some(endpoint) = &mut self.endpoint {
+            endpoint.set_line_settings(&settings);
+        }
+        match &self.transmitter {
+            Some(sender) => {
+                if sender
+                    .send(RunnerCommand::SetLineSettings(settings))
+                    .is_err()
+                {
+                    println!(
+                        "Controller {}: Runner gone, line settings not sent",
+                        self.id
+                    );
+                }
+            }
+            None => self.pending_line_settings = Some(settings),
+        }
+    }
 
-        self.thread_transmitter = Some(Box::new(tx_send));
-        self.received_data_receiver = Some(rx_received);
-        self.thread_handle = Some(handle);
+    pub fn create_stream(&mut self, endpoint: &Endpoint) {
+        // The runner starts once the UI picks up `subscription`, it hands
+        // back its transmitter in a `ConnectionEvent::Ready`
+        self.endpoint = Some(endpoint.clone());
+        self.stream_id = Some(NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed));
 
         // print indication that stream has been created
-        println!("Stream created!");
-        println!("Controller: Transmitter stored, ready to send data");
+        println!("Controller {}: Stream created for {}", self.id, endpoint);
     }
 
     pub fn end_stream(&mut self) {
-        // Drop the transmitter first to signal the thread to exit
-        self.thread_transmitter = None;
-
-        // Don't wait for the thread to join to avoid blocking the UI
-        // The thread will exit when it detects the transmitter is disconnected
-        if let Some(handle) = self.thread_handle.take() {
-            // Spawn a separate task to handle the cleanup without blocking
-            std::thread::spawn(move || match handle.join() {
-                std::result::Result::Ok(_) => {
-                    println!("Thread successfully joined!");
-                }
-                Err(_) => {
-                    println!("Thread failed to join!");
-                }
-            });
-        }
+        // Dropping the transmitter tells the runner to close the connection,
+        // dropping the subscription afterwards cancels it outright
+        self.transmitter = None;
+        self.pending_line_settings = None;
+        self.endpoint = None;
+        self.stream_id = None;
+
+        println!("Controller {}: Stream disconnection initiated", self.id);
+    }
 
-        println!("Stream disconnection initiated");
+    /// Stores the transmitter announced by the runner
+    pub fn attach(&mut self, transmitter: UnboundedSender<RunnerCommand>) {
+        // The runner may have opened the port with the settings from before
+        if let Some(settings) = self.pending_line_settings.take() {
+            if transmitter
+                .send(RunnerCommand::SetLineSettings(settings))
+                .is_err()
+            {
+                println!(
+                    "Controller {}: Runner gone, line settings not sent",
+                    self.id
+                );
+            }
+        }
+        self.transmitter = Some(transmitter);
+        println!(
+            "Controller {}: Transmitter stored, ready to send data",
+            self.id
+        );
     }
 
-    pub fn push(&self, data: String) -> Result<()> {
-        println!("Controller: push() called with data: '{}'", data);
-        let tx = &self.thread_transmitter;
-        match tx {
+    /// Queues data for the runner, returning the id of the write
+    pub fn push(&self, data: Vec<u8>) -> Result<u64> {
+        println!(
+            "Controller {}: push() called with {} bytes: {:?}",
+            self.id,
+            data.len(),
+            data
+        );
+        match &self.transmitter {
             Some(sender) => {
-                println!("Controller: Sending data to thread via channel");
-                match sender.send(data.clone()) {
-                    std::result::Result::Ok(_) => {
-                        println!("Controller: Successfully sent data '{}' to thread", data);
-                        return Ok(());
+                let len = data.len();
+                let write_id = NEXT_WRITE_ID.fetch_add(1, Ordering::Relaxed);
+                match sender.send(RunnerCommand::Write(write_id, data)) {
+                    Ok(_) => {
+                        println!(
+                            "Controller {}: Queued {} bytes for the runner as write {}",
+                            self.id, len, write_id
+                        );
+                        Ok(write_id)
                     }
                     Err(e) => {
-                        println!("Controller: Failed to send data to thread: {:?}", e);
-                        return Err(anyhow!("Receiver disconnected!"));
+                        println!(
+                            "Controller {}: Failed to send data to runner: {:?}",
+                            self.id, e
+                        );
+                        Err(anyhow!("Receiver disconnected!"))
                     }
                 }
             }
             None => {
-                println!("Controller: No transmitter available!");
+                println!("Controller {}: No transmitter available!", self.id);
                 Err(anyhow!("Receiver does not exist!"))
             }
         }
     }
 
-    pub fn try_receive_data(&self) -> Option<String> {
-        if let Some(receiver) = &self.received_data_receiver {
-            match receiver.try_recv() {
-                Ok(data) => Some(data),
-                Err(_) => None,
-            }
-        } else {
-            None
+    /// Pushes a line of input followed by the configured TX line ending,
+    /// returning the bytes as queued
+    pub fn push_line(&self, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        data.extend_from_slice(self.line_endings.tx.as_bytes());
+        self.push(data.clone())?;
+        Ok(data)
+    }
+
+    /// Runs data read at `time` through the line assembler, returning what is
+    /// ready to display with every newline in the configured RX format as `\n`
+    pub fn receive(&mut self, data: &[u8], time: SystemTime) -> Vec<ReceivedData> {
+        self.line_assembler.push(data, time)
+    }
+
+    pub fn has_partial_line(&self) -> bool {
+        self.line_assembler.has_partial_line()
+    }
+
+    /// Partial line that has been waiting longer than the idle timeout
+    pub fn flush_idle_line(&mut self) -> Option<ReceivedData> {
+        self.line_assembler.flush_idle(SystemTime::now())
+    }
+
+    /// Partial line still buffered, for when the connection ends
+    pub fn flush_line(&mut self) -> Option<ReceivedData> {
+        self.line_assembler.flush()
+    }
+
+    /// Events of the running stream, tagged with the controller id
+    pub fn subscription(&self) -> Subscription<(u32, ConnectionEvent)> {
+        match (&self.endpoint, self.stream_id) {
+            (Some(endpoint), Some(stream_id)) => Subscription::run_with_id(
+                stream_id,
+                run_stream(self.id, endpoint.clone(), self.reconnect_policy),
+            ),
+            _ => Subscription::none(),
         }
     }
 }
 
+/// Drives a single connection, the I/O lives inside the returned stream and
+/// is dropped along with it
+fn run_stream(
+    id: u32,
+    endpoint: Endpoint,
+    reconnect_policy: ReconnectPolicy,
+) -> impl Stream<Item = (u32, ConnectionEvent)> {
+    iced::stream::channel(100, move |output| async move {
+        let (transmitter, receiver) = mpsc::unbounded_channel();
+        let mut runner = TerminalRunner::new(id, receiver, output, reconnect_policy);
+
+        if runner.report(ConnectionEvent::Ready(transmitter)).await {
+            runner.action_loop(endpoint).await;
+        }
+    })
+}
+
 struct TerminalRunner {
-    thread_id: u32,
-    receiver: Receiver<String>,
-    received_data_sender: Sender<String>,
+    id: u32,
+    receiver: UnboundedReceiver<RunnerCommand>,
+    event_sender: ui_mpsc::Sender<(u32, ConnectionEvent)>,
+    reconnect_policy: ReconnectPolicy,
 }
 impl TerminalRunner {
     fn new(
-        thread_id: u32,
-        receiver: Receiver<String>,
-        received_data_sender: Sender<String>,
+        id: u32,
+        receiver: UnboundedReceiver<RunnerCommand>,
+        event_sender: ui_mpsc::Sender<(u32, ConnectionEvent)>,
+        reconnect_policy: ReconnectPolicy,
     ) -> Self {
         TerminalRunner {
-            thread_id,
+            id,
             receiver,
-            received_data_sender,
+            event_sender,
+            reconnect_policy,
         }
     }
 
-    fn bitcore_action_loop(&self, sinfo: SerialPortInfo) {
-        // define shared connection
-        let connection: bitcore::SharedConnection = Arc::new(Mutex::new(None));
+    async fn report(&mut self, event: ConnectionEvent) -> bool {
+        match self.event_sender.send((self.id, event)).await {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Runner {}: Failed to report event to UI: {:?}", self.id, e);
+                false
+            }
+        }
+    }
 
-        let sinfo_b: SerialPortBuilder = sinfo.into();
-        // Fix timeout - use a reasonable timeout instead of u64::MAX
-        let sinfo_b = sinfo_b.timeout(Duration::from_millis(100));
+    async fn open(&self, endpoint: &Endpoint) -> Result<Box<dyn Transport>> {
+        println!("Runner {}: Attempting to connect to {}", self.id, endpoint);
+        match endpoint.open().await {
+            Ok(transport) => {
+                println!("Runner {}: Successfully connected", self.id);
+                Ok(transport)
+            }
+            Err(e) => {
+                println!("Runner {}: Failed to connect: {:?}", self.id, e);
+                Err(e)
+            }
+        }
+    }
 
-        // open connection
-        println!(
-            "Thread {}: Attempting to connect to serial port",
-            self.thread_id
-        );
-        match bitcore::connect(&connection, sinfo_b) {
+    async fn close(&self, transport: &mut Box<dyn Transport>) {
+        println!("Runner {}: Disconnecting", self.id);
+        match transport.close().await {
             Ok(_) => {
-                println!(
-                    "Thread {}: Successfully connected to serial port",
-                    self.thread_id
-                );
+                println!("Runner {}: Successfully disconnected", self.id);
             }
             Err(e) => {
-                println!(
-                    "Thread {}: Failed to connect to serial port: {:?}",
-                    self.thread_id, e
-                );
-                return;
+                println!("Runner {}: Failed to disconnect: {:?}", self.id, e);
             }
         }
+    }
 
-        println!(
-            "Starting serial communication loop for thread {}",
-            self.thread_id
-        );
-
-        // Small delay to ensure controller is ready
-        std::thread::sleep(Duration::from_millis(100));
-        println!("Thread {}: Ready to receive data", self.thread_id);
-
-        let mut loop_count = 0;
-        let mut last_status_report = std::time::Instant::now();
-
-        loop {
-            loop_count += 1;
-
-            // Report status every 5 seconds to ensure thread is alive
-            if last_status_report.elapsed() >= Duration::from_secs(5) {
-                println!(
-                    "Thread {}: Still running (loop {})",
-                    self.thread_id, loop_count
-                );
-                last_status_report = std::time::Instant::now();
+    async fn action_loop(&mut self, mut endpoint: Endpoint) {
+        // open connection
+        let mut transport = match self.open(&endpoint).await {
+            Ok(transport) => {
+                self.report(ConnectionEvent::Connected(SystemTime::now()))
+                    .await;
+                if let Some(peer_port) = transport.peer_port() {
+                    self.report(ConnectionEvent::VirtualPortReady(peer_port))
+                        .await;
+                }
+                transport
             }
-
-            if loop_count == 1 {
-                println!("Thread {}: First loop iteration started", self.thread_id);
+            Err(e) => {
+                self.report(ConnectionEvent::ConnectFailed(e.to_string()))
+                    .await;
+                return;
             }
+        };
 
-            // Check for data to send
-            let received_data = self.receiver.try_recv();
-            if loop_count <= 10 {
-                println!(
-                    "Thread {}: try_recv result: {:?}",
-                    self.thread_id, received_data
-                );
-            }
+        println!("Runner {}: Ready to receive data", self.id);
 
-            match received_data {
-                std::result::Result::Ok(data) => {
+        let disconnect_reason = loop {
+            match self.run_session(&mut transport, &mut endpoint).await {
+                SessionEnd::Closed(reason) => break reason,
+                // Remote hosts do not come back by being plugged in again
+                SessionEnd::DeviceLost(reason) if !endpoint.is_removable() => break reason,
+                SessionEnd::DeviceLost(reason) => {
+                    if !self.reconnect_policy.enabled {
+                        break format!("Device lost: {}", reason);
+                    }
                     println!(
-                        "Thread {}: Received data to send: '{}'",
-                        self.thread_id, data
+                        "Runner {}: {} lost, waiting for it to return",
+                        self.id, endpoint
                     );
-                    // Send data without adding extra characters for now
-                    match bitcore::write(&connection, data.as_bytes(), 1) {
-                        Ok(_) => {
-                            println!(
-                                "Thread {}: Successfully wrote data to serial port",
-                                self.thread_id
-                            );
+                    self.close(&mut transport).await;
+                    self.report(ConnectionEvent::DeviceLost(reason)).await;
+
+                    match self.wait_for_device(&mut endpoint).await {
+                        Ok((found, reopened)) => {
+                            self.report(ConnectionEvent::Reconnected(found.name()))
+                                .await;
+                            transport = reopened;
                         }
-                        Err(e) => {
-                            println!(
-                                "Thread {}: Failed to write data to serial port: {:?}",
-                                self.thread_id, e
-                            );
+                        Err(reason) => {
+                            self.report(ConnectionEvent::Disconnected(reason)).await;
+                            return;
                         }
                     }
                 }
-                Err(e) => match e {
-                    std::sync::mpsc::TryRecvError::Empty => {
-                        // No data to send, continue to read
-                    }
-                    std::sync::mpsc::TryRecvError::Disconnected => {
-                        println!(
-                            "Thread {}: Receiver disconnected, terminating",
-                            self.thread_id
-                        );
-                        break;
-                    }
-                },
-            }
-
-            // Read incoming data
-            if loop_count <= 5 {
-                println!("Thread {}: About to read from serial port", self.thread_id);
             }
+        };
 
-            // Try to catch any panics from the bitcore::read call
-            let read_data = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                let mut read_buf = vec![0; 1024]; // Increased buffer size
-                let result = bitcore::read(&connection, &mut read_buf, Duration::from_millis(10)); // Shorter timeout for non-blocking behavior
-                (result, read_buf)
-            }));
+        // close connection
+        self.close(&mut transport).await;
+        self.report(ConnectionEvent::Disconnected(disconnect_reason))
+            .await;
+    }
 
-            if loop_count <= 5 {
-                println!("Thread {}: Read operation completed", self.thread_id);
-            }
+    /// Shuttles data between the open transport and the controller until the
+    /// session is closed or the device goes away, waking only when either
+    /// side has something
+    async fn run_session(
+        &mut self,
+        transport: &mut Box<dyn Transport>,
+        endpoint: &mut Endpoint,
+    ) -> SessionEnd {
+        let mut read_buf = vec![0; 1024];
 
-            let (read_result, read_buf) = match read_data {
-                Ok((result, buf)) => (result, buf),
-                Err(e) => {
-                    println!(
-                        "Thread {}: Read operation panicked: {:?}",
-                        self.thread_id, e
-                    );
-                    // Small delay before continuing to prevent rapid panic loops
-                    std::thread::sleep(Duration::from_millis(10));
-                    continue; // Skip this iteration and try again
-                }
-            };
-
-            match read_result {
-                Ok(bytes_read) => {
-                    if bytes_read > 0 {
-                        let data_str = String::from_utf8_lossy(&read_buf[..bytes_read]);
+        loop {
+            tokio::select! {
+                outgoing = self.receiver.recv() => match outgoing {
+                    Some(RunnerCommand::SetReconnectPolicy(policy)) => {
+                        println!("Runner {}: Reconnect policy now {:?}", self.id, policy);
+                        self.reconnect_policy = policy;
+                    }
+                    Some(RunnerCommand::SetLineSettings(settings)) => {
+                        println!("Runner {}: Applying line settings {:?}", self.id, settings);
+                        endpoint.set_line_settings(&settings);
+                        if let Err(e) = transport.set_line_settings(&settings).await {
+                            println!("Runner {}: Failed to apply line settings: {:?}", self.id, e);
+                            self.report(ConnectionEvent::LineSettingsError(e.to_string()))
+                                .await;
+                        }
+                    }
+                    Some(RunnerCommand::Write(write_id, data)) => {
                         println!(
-                            "Thread {}: Raw received {} bytes: {:?}",
-                            self.thread_id, bytes_read, data_str
+                            "Runner {}: Received {} bytes to send: {:?}",
+                            self.id,
+                            data.len(),
+                            data
                         );
-
-                        // Send all received data, even if it contains control characters
-                        let message = format!("Received: {}", data_str);
-                        match self.received_data_sender.send(message) {
+                        match transport.write(&data).await {
                             Ok(_) => {
-                                println!(
-                                    "Thread {}: Successfully sent received data to UI",
-                                    self.thread_id
-                                );
+                                println!("Runner {}: Successfully wrote data", self.id);
+                                self.report(ConnectionEvent::Written(write_id)).await;
                             }
                             Err(e) => {
-                                println!(
-                                    "Thread {}: Failed to send received data to UI: {:?}",
-                                    self.thread_id, e
-                                );
-                                // If we can't send to UI, the receiver might be disconnected
-                                break;
+                                println!("Runner {}: Failed to write data: {:?}", self.id, e);
+                                if !transport.is_present() {
+                                    return SessionEnd::DeviceLost(e.to_string());
+                                }
+                                self.report(ConnectionEvent::WriteError(write_id, e.to_string()))
+                                    .await;
                             }
                         }
                     }
-                }
-                Err(e) => {
-                    // Log read errors occasionally for debugging
-                    if loop_count <= 10 || loop_count % 1000 == 0 {
-                        println!(
-                            "Thread {}: Read error (normal timeout): {:?}",
-                            self.thread_id, e
-                        );
+                    None => {
+                        println!("Runner {}: Receiver disconnected, terminating", self.id);
+                        return SessionEnd::Closed(String::from("Closed by user"));
                     }
-                }
-            }
+                },
 
-            // Small delay to prevent busy waiting
-            std::thread::sleep(Duration::from_millis(1));
+                read_result = transport.read(&mut read_buf) => {
+                    if let Some(modem_state) = transport.poll_modem_state() {
+                        println!("Runner {}: Modem state changed: {}", self.id, modem_state);
+                        self.report(ConnectionEvent::ModemState(modem_state)).await;
+                    }
 
-            if loop_count <= 10 {
-                println!(
-                    "Thread {}: End of loop iteration {}",
-                    self.thread_id, loop_count
-                );
+                    match read_result {
+                        Ok(0) => {
+                            // Only control traffic arrived
+                        }
+                        Ok(bytes_read) => {
+                            let data = read_buf[..bytes_read].to_vec();
+                            println!(
+                                "Runner {}: Raw received {} bytes: {:?}",
+                                self.id, bytes_read, data
+                            );
+
+                            // Forward the raw bytes untouched, decoding happens in the UI
+                            if !self.report(ConnectionEvent::Data(data, SystemTime::now())).await {
+                                // If we can't send to UI, the receiver might be disconnected
+                                return SessionEnd::Closed(String::from("UI stopped listening"));
+                            }
+                        }
+                        Err(e) => {
+                            println!("Runner {}: Read error: {:?}", self.id, e);
+                            if !transport.is_present() {
+                                return SessionEnd::DeviceLost(e.to_string());
+                            }
+                            self.report(ConnectionEvent::ReadError(e.to_string())).await;
+                            // Small delay to prevent a failing port from flooding the UI
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                    }
+                }
             }
         }
+    }
 
-        // close connection
-        println!("Thread {}: Disconnecting from serial port", self.thread_id);
-        match bitcore::disconnect(&connection) {
-            Ok(_) => {
-                println!("Thread {}: Successfully disconnected", self.thread_id);
+    /// Polls for the lost endpoint with exponential backoff and reopens it,
+    /// returns why it gave up if the controller ends the stream or turns
+    /// reconnecting off while waiting
+    async fn wait_for_device(
+        &mut self,
+        endpoint: &mut Endpoint,
+    ) -> Result<(Endpoint, Box<dyn Transport>), String> {
+        let mut backoff = self.reconnect_policy.initial_backoff;
+
+        loop {
+            let deadline = tokio::time::Instant::now() + backoff;
+
+            // Keep draining the outgoing channel so ending the stream interrupts the backoff
+            loop {
+                tokio::select! {
+                    outgoing = self.receiver.recv() => match outgoing {
+                        Some(RunnerCommand::SetReconnectPolicy(policy)) => {
+                            println!("Runner {}: Reconnect policy now {:?}", self.id, policy);
+                            self.reconnect_policy = policy;
+                            if !policy.enabled {
+                                return Err(String::from("Device lost, auto-reconnect turned off"));
+                            }
+                        }
+                        Some(RunnerCommand::SetLineSettings(settings)) => {
+                            // Used when the device is opened again
+                            endpoint.set_line_settings(&settings);
+                        }
+                        Some(RunnerCommand::Write(write_id, data)) => {
+                            println!(
+                                "Runner {}: Dropping {} bytes, device not available",
+                                self.id,
+                                data.len()
+                            );
+                            self.report(ConnectionEvent::WriteError(
+                                write_id,
+                                String::from("Device not available"),
+                            ))
+                            .await;
+                        }
+                        None => return Err(String::from("Closed by user")),
+                    },
+                    _ = tokio::time::sleep_until(deadline) => break,
+                }
             }
-            Err(e) => {
-                println!("Thread {}: Failed to disconnect: {:?}", self.thread_id, e);
+
+            if let Some(candidate) = endpoint.locate() {
+                if let Ok(transport) = self.open(&candidate).await {
+                    return Ok((candidate, transport));
+                }
             }
+
+            backoff = (backoff * 2)
+                .max(self.reconnect_policy.initial_backoff)
+                .min(self.reconnect_policy.max_backoff);
         }
     }
 }
 
+/// Why `TerminalRunner::run_session` returned
+enum SessionEnd {
+    Closed(String),
+    DeviceLost(String),
+}
+
 pub fn list_available_ports() -> Result<Vec<SerialPortInfo>, Error> {
     let ports = serialport::available_ports()?;
     let mut serial_ports = Vec::new();
 
     for port in ports {
+        let serial_number = match port.port_type {
+            SerialPortType::UsbPort(usb) => usb.serial_number,
+            _ => None,
+        };
         let serial_port = SerialPortInfo::new(
             port.port_name,
             9600,
@@ -337,10 +594,88 @@ pub fn list_available_ports() -> Result<Vec<SerialPortInfo>, Error> {
             Parity::from(Parity::None),
             StopBits::from(StopBits::One),
             FlowControl::from(FlowControl::None),
-        );
+        )
+        .with_serial_number(serial_number);
 
         serial_ports.push(serial_port);
     }
 
     Ok(serial_ports)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use iced::futures::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::timeout;
+
+    use super::*;
+
+    /// Polls the runner until an event `pick` accepts arrives
+    async fn next_matching<T>(
+        events: &mut (impl Stream<Item = (u32, ConnectionEvent)> + Unpin),
+        mut pick: impl FnMut(ConnectionEvent) -> Option<T>,
+    ) -> T {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let (_, event) = events.next().await.expect("runner stopped");
+                if let Some(value) = pick(event) {
+                    return value;
+                }
+            }
+        })
+        .await
+        .expect("no matching event")
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn virtual_port_round_trip() {
+        let mut controller = TerminalController::new(1);
+        let mut events = Box::pin(run_stream(1, Endpoint::Virtual, ReconnectPolicy::default()));
+
+        let transmitter = next_matching(&mut events, |event| match event {
+            ConnectionEvent::Ready(transmitter) => Some(transmitter),
+            _ => None,
+        })
+        .await;
+        controller.attach(transmitter);
+        let peer_path = next_matching(&mut events, |event| match event {
+            ConnectionEvent::VirtualPortReady(path) => Some(path),
+            _ => None,
+        })
+        .await;
+        let mut peer = tokio_serial::SerialStream::open(&tokio_serial::new(&peer_path, 115200))
+            .expect("peer opens");
+
+        peer.write_all(b"hello").await.unwrap();
+        let mut received = Vec::new();
+        while received.len() < 5 {
+            let data = next_matching(&mut events, |event| match event {
+                ConnectionEvent::Data(data, _) => Some(data),
+                _ => None,
+            })
+            .await;
+            received.extend(data);
+        }
+        assert_eq!(received, b"hello");
+
+        // Changing line settings keeps the same connection
+        controller.set_line_settings(LineSettings::default());
+        let write_id = controller.push(b"world".to_vec()).unwrap();
+        let written = next_matching(&mut events, |event| match event {
+            ConnectionEvent::Written(id) => Some(id),
+            _ => None,
+        })
+        .await;
+        assert_eq!(written, write_id);
+        let mut echoed = [0; 5];
+        timeout(Duration::from_secs(5), peer.read_exact(&mut echoed))
+            .await
+            .expect("peer receives")
+            .unwrap();
+        assert_eq!(&echoed, b"world");
+    }
+}
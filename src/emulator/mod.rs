@@ -0,0 +1,404 @@
+pub mod screen;
+
+use crate::config::{VT_COLUMNS, VT_ROWS, VT_SCROLLBACK_LINES};
+
+use screen::{Color, Screen};
+
+/// Where the parser is within an escape sequence
+#[derive(Debug)]
+enum State {
+    Ground,
+    Escape,
+    /// `ESC (` and friends, the next character picks a character set
+    Charset,
+    Csi,
+    Osc,
+    OscEscape,
+}
+
+/// VT100/xterm compatible terminal: parses escape sequences out of the
+/// received text and applies them to a `Screen`
+#[derive(Debug)]
+pub struct Emulator {
+    screen: Screen,
+    state: State,
+    params: Vec<u16>,
+    current_param: Option<u16>,
+    private_marker: Option<char>,
+    /// Answers to status queries, to be sent back to the device
+    responses: Vec<u8>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new(VT_COLUMNS, VT_ROWS, VT_SCROLLBACK_LINES)
+    }
+}
+
+impl Emulator {
+    pub fn new(columns: usize, rows: usize, scrollback_limit: usize) -> Self {
+        Self {
+            screen: Screen::new(columns, rows, scrollback_limit),
+            state: State::Ground,
+            params: Vec::new(),
+            current_param: None,
+            private_marker: None,
+            responses: Vec::new(),
+        }
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn reset(&mut self) {
+        self.screen.reset();
+        self.state = State::Ground;
+        self.responses.clear();
+    }
+
+    pub fn feed(&mut self, text: &str) {
+        for c in text.chars() {
+            self.advance(c);
+        }
+    }
+
+    /// Writes a status line of our own below the current output
+    pub fn feed_message(&mut self, message: &str) {
+        if self.screen.cursor().1 != 0 {
+            self.feed("\r\n");
+        }
+        self.feed(message);
+        self.feed("\r\n");
+    }
+
+    /// Takes the replies to status queries received since the last call
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    fn advance(&mut self, c: char) {
+        match self.state {
+            State::Ground => match c {
+                '\x1b' => self.state = State::Escape,
+                c if c.is_control() => self.control(c),
+                c => self.screen.print(c),
+            },
+            State::Escape => {
+                self.state = State::Ground;
+                self.escape_dispatch(c);
+            }
+            State::Charset => {
+                // Only the default character set is supported
+                self.state = State::Ground;
+            }
+            State::Csi => match c {
+                '0'..='9' => {
+                    let digit = c as u16 - '0' as u16;
+                    let param = self.current_param.unwrap_or(0);
+                    self.current_param = Some(param.saturating_mul(10).saturating_add(digit));
+                }
+                ';' | ':' => {
+                    self.params.push(self.current_param.take().unwrap_or(0));
+                }
+                '?' | '>' | '<' | '=' => self.private_marker = Some(c),
+                ' '..='/' => {
+                    // Intermediate bytes, none of the supported sequences use them
+                }
+                '@'..='~' => {
+                    if let Some(param) = self.current_param.take() {
+                        self.params.push(param);
+                    }
+                    self.state = State::Ground;
+                    self.csi_dispatch(c);
+                }
+                '\x1b' => self.state = State::Escape,
+                c if c.is_control() => self.control(c),
+                _ => self.state = State::Ground,
+            },
+            State::Osc => match c {
+                '\x07' => self.state = State::Ground,
+                '\x1b' => self.state = State::OscEscape,
+                _ => {}
+            },
+            State::OscEscape => {
+                // ESC \ ends the window title or other OSC string
+                self.state = State::Ground;
+            }
+        }
+    }
+
+    fn control(&mut self, c: char) {
+        match c {
+            '\x08' => self.screen.backspace(),
+            '\t' => self.screen.tab(),
+            '\n' | '\x0b' | '\x0c' => self.screen.line_feed(),
+            '\r' => self.screen.carriage_return(),
+            _ => {}
+        }
+    }
+
+    fn escape_dispatch(&mut self, c: char) {
+        match c {
+            '[' => {
+                self.params.clear();
+                self.current_param = None;
+                self.private_marker = None;
+                self.state = State::Csi;
+            }
+            ']' => self.state = State::Osc,
+            '(' | ')' | '*' | '+' => self.state = State::Charset,
+            '7' => self.screen.save_cursor(),
+            '8' => self.screen.restore_cursor(),
+            'D' => self.screen.line_feed(),
+            'E' => {
+                self.screen.carriage_return();
+                self.screen.line_feed();
+            }
+            'M' => self.screen.reverse_line_feed(),
+            'c' => self.screen.reset(),
+            _ => {}
+        }
+    }
+
+    /// Parameter `index`, with 0 or a missing value meaning `default`
+    fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params.get(index) {
+            Some(&0) | None => default,
+            Some(&value) => value,
+        }
+    }
+
+    fn csi_dispatch(&mut self, c: char) {
+        if self.private_marker.is_some() {
+            self.private_mode(c);
+            return;
+        }
+
+        let count = self.param(0, 1) as usize;
+        match c {
+            'A' => self.screen.move_by(-(count as isize), 0),
+            'B' | 'e' => self.screen.move_by(count as isize, 0),
+            'C' | 'a' => self.screen.move_by(0, count as isize),
+            'D' => self.screen.move_by(0, -(count as isize)),
+            'E' => {
+                self.screen.move_by(count as isize, 0);
+                self.screen.carriage_return();
+            }
+            'F' => {
+                self.screen.move_by(-(count as isize), 0);
+                self.screen.carriage_return();
+            }
+            'G' | '`' => self.screen.move_to_column(count - 1),
+            'd' => self.screen.move_to_row(count - 1),
+            'H' | 'f' => {
+                let row = self.param(0, 1) as usize;
+                let column = self.param(1, 1) as usize;
+                self.screen.move_to(row - 1, column - 1);
+            }
+            'J' => self
+                .screen
+                .erase_display(self.params.first().copied().unwrap_or(0)),
+            'K' => self
+                .screen
+                .erase_line(self.params.first().copied().unwrap_or(0)),
+            'X' => self.screen.erase_chars(count),
+            '@' => self.screen.insert_chars(count),
+            'P' => self.screen.delete_chars(count),
+            'L' => self.screen.insert_lines(count),
+            'M' => self.screen.delete_lines(count),
+            'S' => self.screen.scroll_up(count),
+            'T' => self.screen.scroll_down(count),
+            'r' => {
+                let top = self.param(0, 1) as usize;
+                let bottom = self.param(1, u16::MAX) as usize;
+                self.screen.set_scroll_region(top - 1, bottom - 1);
+            }
+            's' => self.screen.save_cursor(),
+            'u' => self.screen.restore_cursor(),
+            'm' => self.select_graphic_rendition(),
+            'n' => match self.param(0, 0) {
+                5 => self.responses.extend_from_slice(b"\x1b[0n"),
+                6 => {
+                    let (row, column) = self.screen.cursor();
+                    self.responses
+                        .extend(format!("\x1b[{};{}R", row + 1, column + 1).into_bytes());
+                }
+                _ => {}
+            },
+            // Identify as a VT100 with advanced video
+            'c' => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+            _ => {}
+        }
+    }
+
+    /// DEC private modes, only cursor visibility and the alternate screen matter here
+    fn private_mode(&mut self, c: char) {
+        let enable = match c {
+            'h' => true,
+            'l' => false,
+            _ => return,
+        };
+
+        for &mode in &self.params {
+            match mode {
+                25 => self.screen.cursor_visible = enable,
+                // Full screen programs switching screens expect a clean one
+                47 | 1047 | 1049 => self.screen.erase_display(2),
+                _ => {}
+            }
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        if self.params.is_empty() {
+            self.params.push(0);
+        }
+
+        let style = &mut self.screen.style;
+        let mut params = self.params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *style = Default::default(),
+                1 => style.bold = true,
+                2 => style.dim = true,
+                3 => style.italic = true,
+                4 => style.underline = true,
+                7 => style.inverse = true,
+                9 => style.strikethrough = true,
+                21 | 22 => {
+                    style.bold = false;
+                    style.dim = false;
+                }
+                23 => style.italic = false,
+                24 => style.underline = false,
+                27 => style.inverse = false,
+                29 => style.strikethrough = false,
+                30..=37 => style.foreground = Color::Indexed((param - 30) as u8),
+                38 => style.foreground = extended_color(&mut params),
+                39 => style.foreground = Color::Default,
+                40..=47 => style.background = Color::Indexed((param - 40) as u8),
+                48 => style.background = extended_color(&mut params),
+                49 => style.background = Color::Default,
+                90..=97 => style.foreground = Color::Indexed((param - 90 + 8) as u8),
+                100..=107 => style.background = Color::Indexed((param - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Reads the `5;n` or `2;r;g;b` following SGR 38 and 48
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Color {
+    match params.next() {
+        Some(5) => params
+            .next()
+            .map_or(Color::Default, |index| Color::Indexed(index as u8)),
+        Some(2) => {
+            let mut channel = || params.next().unwrap_or(0) as u8;
+            let (r, g, b) = (channel(), channel(), channel());
+            Color::Rgb(r, g, b)
+        }
+        _ => Color::Default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::screen::Cell;
+    use super::*;
+
+    /// Visible rows as text without trailing blanks
+    fn rows(emulator: &Emulator) -> Vec<String> {
+        let screen = emulator.screen();
+        screen
+            .lines()
+            .skip(screen.scrollback_len())
+            .map(|line| {
+                let text: String = line.iter().map(|cell| cell.c).collect();
+                text.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    fn cell(emulator: &Emulator, row: usize, column: usize) -> Cell {
+        let screen = emulator.screen();
+        screen.lines().nth(screen.scrollback_len() + row).unwrap()[column]
+    }
+
+    #[test]
+    fn csi_moves_the_cursor_and_erases() {
+        let mut emulator = Emulator::new(10, 4, 10);
+        emulator.feed("hello\x1b[2;3HX\x1b[A\x1b[3G\x1b[KY");
+        assert_eq!(rows(&emulator), ["heY", "  X", "", ""]);
+
+        emulator.feed("\x1b[4;10H\x1b[6n\x1b[5n");
+        assert_eq!(emulator.take_responses(), b"\x1b[4;10R\x1b[0n");
+        assert!(emulator.take_responses().is_empty());
+
+        emulator.feed("\x1b[2J");
+        assert_eq!(rows(&emulator), ["", "", "", ""]);
+    }
+
+    #[test]
+    fn sequences_split_across_feeds() {
+        let mut emulator = Emulator::new(10, 4, 10);
+        emulator.feed("\x1b");
+        emulator.feed("[3");
+        emulator.feed("1mA");
+        assert_eq!(rows(&emulator)[0], "A");
+        assert_eq!(cell(&emulator, 0, 0).style.foreground, Color::Indexed(1));
+    }
+
+    #[test]
+    fn sgr_sets_and_resets_attributes() {
+        let mut emulator = Emulator::new(10, 4, 10);
+        emulator.feed("\x1b[1;4;31;42mA\x1b[0mB\x1b[38;5;200;48;2;1;2;3mC\x1b[22;92;49mD\x1b[mE");
+
+        let a = cell(&emulator, 0, 0).style;
+        assert!(a.bold && a.underline);
+        assert_eq!(a.foreground, Color::Indexed(1));
+        assert_eq!(a.background, Color::Indexed(2));
+        assert_eq!(cell(&emulator, 0, 1).style, Default::default());
+
+        let c = cell(&emulator, 0, 2).style;
+        assert_eq!(c.foreground, Color::Indexed(200));
+        assert_eq!(c.background, Color::Rgb(1, 2, 3));
+
+        let d = cell(&emulator, 0, 3).style;
+        assert!(!d.bold);
+        assert_eq!(d.foreground, Color::Indexed(10));
+        assert_eq!(d.background, Color::Default);
+        assert_eq!(cell(&emulator, 0, 4).style, Default::default());
+    }
+
+    #[test]
+    fn scroll_region_keeps_lines_outside_it() {
+        let mut emulator = Emulator::new(10, 4, 10);
+        emulator.feed("top\r\nb\r\nc\r\nbottom");
+        emulator.feed("\x1b[2;3r");
+        // Setting the region homes the cursor
+        assert_eq!(emulator.screen().cursor(), (0, 0));
+
+        emulator.feed("\x1b[3;1H\nnew");
+        assert_eq!(rows(&emulator), ["top", "c", "new", "bottom"]);
+        // Only lines leaving the top of the screen go to the scrollback
+        assert_eq!(emulator.screen().scrollback_len(), 0);
+
+        emulator.feed("\x1b[2;1H\x1bM");
+        assert_eq!(rows(&emulator), ["top", "", "c", "bottom"]);
+    }
+
+    #[test]
+    fn full_screen_scrolls_into_the_scrollback() {
+        let mut emulator = Emulator::new(10, 2, 1);
+        emulator.feed("1\r\n2\r\n3\r\n4");
+        assert_eq!(rows(&emulator), ["3", "4"]);
+        assert_eq!(emulator.screen().scrollback_len(), 1);
+        let oldest: String = emulator.screen().lines().next().unwrap()[..1]
+            .iter()
+            .map(|cell| cell.c)
+            .collect();
+        assert_eq!(oldest, "2");
+    }
+}
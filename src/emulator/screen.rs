@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+
+/// Color of a cell as set by SGR
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Color {
+    /// The theme's text or background color
+    #[default]
+    Default,
+    /// Entry of the xterm 256 color palette
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// RGB value of the color, `None` for the theme default
+    pub fn to_rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Color::Default => None,
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+            Color::Indexed(index) => Some(indexed_rgb(index)),
+        }
+    }
+}
+
+/// The 16 ANSI colors followed by a 6x6x6 cube and a grayscale ramp
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+
+    match index {
+        0..=15 => ANSI[index as usize],
+        16..=231 => {
+            let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+            let index = index - 16;
+            (level(index / 36), level((index / 6) % 6), level(index % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Rendition of a cell
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CellStyle {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub c: char,
+    pub style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            c: ' ',
+            style: CellStyle::default(),
+        }
+    }
+}
+
+/// Fixed size grid of cells with a cursor, lines scrolled off the top are
+/// kept in a bounded scrollback
+#[derive(Debug)]
+pub struct Screen {
+    columns: usize,
+    rows: usize,
+    grid: Vec<Vec<Cell>>,
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_limit: usize,
+    row: usize,
+    column: usize,
+    /// The last column was written, the next character wraps first
+    wrap_pending: bool,
+    pub style: CellStyle,
+    saved_cursor: Option<(usize, usize, CellStyle)>,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    pub cursor_visible: bool,
+}
+
+impl Screen {
+    pub fn new(columns: usize, rows: usize, scrollback_limit: usize) -> Self {
+        Self {
+            columns,
+            rows,
+            grid: vec![vec![Cell::default(); columns]; rows],
+            scrollback: VecDeque::new(),
+            scrollback_limit,
+            row: 0,
+            column: 0,
+            wrap_pending: false,
+            style: CellStyle::default(),
+            saved_cursor: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            cursor_visible: true,
+        }
+    }
+
+    /// Clears the screen, scrollback and modes
+    pub fn reset(&mut self) {
+        *self = Self::new(self.columns, self.rows, self.scrollback_limit);
+    }
+
+    /// Zero based cursor row and column
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    /// Scrollback followed by the visible rows
+    pub fn lines(&self) -> impl Iterator<Item = &[Cell]> {
+        self.scrollback
+            .iter()
+            .chain(self.grid.iter())
+            .map(|line| line.as_slice())
+    }
+
+    /// Number of visible rows holding content or the cursor
+    pub fn used_rows(&self) -> usize {
+        let last_written = self
+            .grid
+            .iter()
+            .rposition(|line| line.iter().any(|cell| *cell != Cell::default()))
+            .map_or(0, |row| row + 1);
+        last_written.max(self.row + 1)
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// Blank cell carrying the current background, as erase operations leave
+    fn blank(&self) -> Cell {
+        Cell {
+            c: ' ',
+            style: CellStyle {
+                background: self.style.background,
+                ..CellStyle::default()
+            },
+        }
+    }
+
+    pub fn print(&mut self, c: char) {
+        if self.wrap_pending {
+            self.carriage_return();
+            self.line_feed();
+        }
+
+        self.grid[self.row][self.column] = Cell {
+            c,
+            style: self.style,
+        };
+
+        if self.column + 1 < self.columns {
+            self.column += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    pub fn carriage_return(&mut self) {
+        self.column = 0;
+        self.wrap_pending = false;
+    }
+
+    /// Moves down a line, scrolling at the bottom of the scroll region
+    pub fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    /// Moves up a line, scrolling at the top of the scroll region
+    pub fn reverse_line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.wrap_pending = false;
+        self.column = self.column.saturating_sub(1);
+    }
+
+    /// Advances to the next multiple of 8 columns
+    pub fn tab(&mut self) {
+        self.column = ((self.column / 8 + 1) * 8).min(self.columns - 1);
+    }
+
+    /// Places the cursor at a zero based position, clamped to the screen
+    pub fn move_to(&mut self, row: usize, column: usize) {
+        self.wrap_pending = false;
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns - 1);
+    }
+
+    pub fn move_by(&mut self, rows: isize, columns: isize) {
+        let row = self.row.saturating_add_signed(rows);
+        let column = self.column.saturating_add_signed(columns);
+        self.move_to(row, column);
+    }
+
+    pub fn move_to_column(&mut self, column: usize) {
+        self.move_to(self.row, column);
+    }
+
+    pub fn move_to_row(&mut self, row: usize) {
+        self.move_to(row, self.column);
+    }
+
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = Some((self.row, self.column, self.style));
+    }
+
+    pub fn restore_cursor(&mut self) {
+        if let Some((row, column, style)) = self.saved_cursor {
+            self.move_to(row, column);
+            self.style = style;
+        }
+    }
+
+    /// 0: cursor to end, 1: start to cursor, 2: whole screen, 3: also scrollback
+    pub fn erase_display(&mut self, mode: u16) {
+        let blank = self.blank();
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for line in &mut self.grid[self.row + 1..] {
+                    line.fill(blank);
+                }
+            }
+            1 => {
+                self.erase_line(1);
+                for line in &mut self.grid[..self.row] {
+                    line.fill(blank);
+                }
+            }
+            2 | 3 => {
+                for line in &mut self.grid {
+                    line.fill(blank);
+                }
+                if mode == 3 {
+                    self.scrollback.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 0: cursor to end, 1: start to cursor, 2: whole line
+    pub fn erase_line(&mut self, mode: u16) {
+        let blank = self.blank();
+        let line = &mut self.grid[self.row];
+        match mode {
+            0 => line[self.column..].fill(blank),
+            1 => line[..=self.column].fill(blank),
+            2 => line.fill(blank),
+            _ => {}
+        }
+    }
+
+    /// Blanks `count` cells from the cursor without moving the rest of the line
+    pub fn erase_chars(&mut self, count: usize) {
+        let blank = self.blank();
+        let end = (self.column + count).min(self.columns);
+        self.grid[self.row][self.column..end].fill(blank);
+    }
+
+    pub fn insert_chars(&mut self, count: usize) {
+        let blank = self.blank();
+        let count = count.min(self.columns - self.column);
+        let line = &mut self.grid[self.row];
+        line[self.column..].rotate_right(count);
+        line[self.column..self.column + count].fill(blank);
+    }
+
+    pub fn delete_chars(&mut self, count: usize) {
+        let blank = self.blank();
+        let count = count.min(self.columns - self.column);
+        let line = &mut self.grid[self.row];
+        line[self.column..].rotate_left(count);
+        let columns = self.columns;
+        line[columns - count..].fill(blank);
+    }
+
+    /// Inserts blank lines at the cursor, pushing lines below down within the scroll region
+    pub fn insert_lines(&mut self, count: usize) {
+        if (self.scroll_top..=self.scroll_bottom).contains(&self.row) {
+            let blank = self.blank();
+            for _ in 0..count.min(self.scroll_bottom - self.row + 1) {
+                self.grid.remove(self.scroll_bottom);
+                self.grid.insert(self.row, vec![blank; self.columns]);
+            }
+            self.column = 0;
+        }
+    }
+
+    /// Removes lines at the cursor, pulling lines below up within the scroll region
+    pub fn delete_lines(&mut self, count: usize) {
+        if (self.scroll_top..=self.scroll_bottom).contains(&self.row) {
+            let blank = self.blank();
+            for _ in 0..count.min(self.scroll_bottom - self.row + 1) {
+                self.grid.remove(self.row);
+                self.grid
+                    .insert(self.scroll_bottom, vec![blank; self.columns]);
+            }
+            self.column = 0;
+        }
+    }
+
+    /// Scrolls the region up, lines leaving the top of the screen go to the scrollback
+    pub fn scroll_up(&mut self, count: usize) {
+        let blank = self.blank();
+        for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+            let line = self.grid.remove(self.scroll_top);
+            self.grid
+                .insert(self.scroll_bottom, vec![blank; self.columns]);
+
+            if self.scroll_top == 0 && self.scrollback_limit > 0 {
+                if self.scrollback.len() >= self.scrollback_limit {
+                    self.scrollback.pop_front();
+                }
+                self.scrollback.push_back(line);
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, count: usize) {
+        let blank = self.blank();
+        for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+            self.grid.remove(self.scroll_bottom);
+            self.grid.insert(self.scroll_top, vec![blank; self.columns]);
+        }
+    }
+
+    /// Sets the zero based, inclusive scroll region and homes the cursor
+    pub fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows - 1);
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.move_to(0, 0);
+        }
+    }
+}
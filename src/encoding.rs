@@ -0,0 +1,192 @@
+use anyhow::{anyhow, Result};
+
+/// Character encoding used to decode received bytes and encode typed input
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// 7-bit ASCII, other bytes shown as `\xNN` and typed escapes sent as bytes
+    AsciiEscaped,
+    Latin1,
+    Cp437,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 4] = [
+        Encoding::Utf8,
+        Encoding::AsciiEscaped,
+        Encoding::Latin1,
+        Encoding::Cp437,
+    ];
+
+    pub fn decode(&self, data: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            Encoding::AsciiEscaped => data
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii() {
+                        (byte as char).to_string()
+                    } else {
+                        format!("\\x{:02X}", byte)
+                    }
+                })
+                .collect(),
+            Encoding::Latin1 => data.iter().map(|&byte| byte as char).collect(),
+            Encoding::Cp437 => data
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii() {
+                        byte as char
+                    } else {
+                        CP437_HIGH[(byte - 0x80) as usize]
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Encodes typed input, failing on characters the encoding cannot represent
+    pub fn encode(&self, input: &str) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Ok(input.as_bytes().to_vec()),
+            Encoding::AsciiEscaped => encode_escaped(input),
+            Encoding::Latin1 => input
+                .chars()
+                .map(|c| u8::try_from(c).map_err(|_| unencodable(c, self)))
+                .collect(),
+            Encoding::Cp437 => input
+                .chars()
+                .map(|c| {
+                    if c.is_ascii() {
+                        Ok(c as u8)
+                    } else {
+                        CP437_HIGH
+                            .iter()
+                            .position(|&high| high == c)
+                            .map(|index| index as u8 + 0x80)
+                            .ok_or_else(|| unencodable(c, self))
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "UTF-8"),
+            Encoding::AsciiEscaped => write!(f, "ASCII (escaped)"),
+            Encoding::Latin1 => write!(f, "ISO-8859-1"),
+            Encoding::Cp437 => write!(f, "CP437"),
+        }
+    }
+}
+
+fn unencodable(c: char, encoding: &Encoding) -> anyhow::Error {
+    anyhow!("'{}' cannot be encoded as {}", c, encoding)
+}
+
+/// ASCII with `\xNN`, `\r`, `\n`, `\t`, `\0` and `\\` escapes
+fn encode_escaped(input: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            if !c.is_ascii() {
+                return Err(unencodable(c, &Encoding::AsciiEscaped));
+            }
+            bytes.push(c as u8);
+            continue;
+        }
+
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let byte = Some(&digits)
+                    .filter(|digits| {
+                        digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit())
+                    })
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| anyhow!("'\\x{}' is not a valid byte escape", digits))?;
+                bytes.push(byte);
+            }
+            Some(other) => return Err(anyhow!("Unknown escape '\\{}'", other)),
+            None => return Err(anyhow!("Input ends with a lone '\\'")),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Characters for bytes 0x80 to 0xFF in code page 437
+#[rustfmt::skip]
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cp437_table() {
+        let decoded = Encoding::Cp437.decode(&[0x41, 0x80, 0x82, 0xB0, 0xC4, 0xDB, 0xE1, 0xFE]);
+        assert_eq!(decoded, "AÇé░─█ß■");
+        assert_eq!(Encoding::Cp437.decode(&[0xFF]), "\u{A0}");
+
+        // Every byte maps to its own character, so all of them round-trip
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = Encoding::Cp437.decode(&bytes);
+        assert_eq!(text.chars().count(), 256);
+        assert_eq!(Encoding::Cp437.encode(&text).unwrap(), bytes);
+        assert!(Encoding::Cp437.encode("€").is_err());
+    }
+
+    #[test]
+    fn latin1_table() {
+        assert_eq!(Encoding::Latin1.decode(&[0x41, 0xA3, 0xE9, 0xFF]), "A£éÿ");
+
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = Encoding::Latin1.decode(&bytes);
+        assert_eq!(Encoding::Latin1.encode(&text).unwrap(), bytes);
+        assert!(Encoding::Latin1.encode("€").is_err());
+    }
+
+    #[test]
+    fn ascii_escapes() {
+        assert_eq!(Encoding::AsciiEscaped.decode(b"a\x80\xFF"), "a\\x80\\xFF");
+        assert_eq!(
+            Encoding::AsciiEscaped
+                .encode("a\\r\\n\\t\\0\\\\\\x1B")
+                .unwrap(),
+            b"a\r\n\t\0\\\x1B"
+        );
+        for input in ["é", "\\q", "\\x1", "\\xZZ", "end\\"] {
+            assert!(Encoding::AsciiEscaped.encode(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn utf8_replaces_invalid_bytes() {
+        assert_eq!(
+            Encoding::Utf8.decode(b"ok \xE2\x9C\x93 \xFF"),
+            "ok ✓ \u{FFFD}"
+        );
+        assert_eq!(Encoding::Utf8.encode("✓").unwrap(), "✓".as_bytes());
+    }
+}
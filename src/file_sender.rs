@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use crate::config::FILE_CHUNK_SIZE;
+
+/// What to send and how fast, as chosen in the sidebar
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendFileSettings {
+    pub path: String,
+    pub chunk_size: usize,
+    /// Pause after each chunk went out
+    pub delay_ms: u64,
+}
+
+impl Default for SendFileSettings {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            chunk_size: FILE_CHUNK_SIZE,
+            delay_ms: 0,
+        }
+    }
+}
+
+/// Streams a file through the controller one chunk at a time, the next chunk
+/// is only queued once the runner wrote the previous one
+#[derive(Debug)]
+pub struct FileSender {
+    pub path: String,
+    data: Vec<u8>,
+    chunk_size: usize,
+    pub delay: Duration,
+    /// Bytes the runner confirmed as written
+    written: usize,
+    /// Write id and size of the chunk queued but not yet written
+    in_flight: Option<(u64, usize)>,
+}
+
+impl FileSender {
+    pub fn new(path: String, data: Vec<u8>, settings: &SendFileSettings) -> Self {
+        Self {
+            path,
+            data,
+            chunk_size: settings.chunk_size.max(1),
+            delay: Duration::from_millis(settings.delay_ms),
+            written: 0,
+            in_flight: None,
+        }
+    }
+
+    /// The next chunk to queue, `None` while one is in flight or when done
+    pub fn next_chunk(&self) -> Option<Vec<u8>> {
+        if self.in_flight.is_some() || self.is_done() {
+            return None;
+        }
+        let end = (self.written + self.chunk_size).min(self.data.len());
+        Some(self.data[self.written..end].to_vec())
+    }
+
+    /// Takes note of the chunk from `next_chunk` being queued as write `id`
+    pub fn queued(&mut self, id: u64, len: usize) {
+        self.in_flight = Some((id, len));
+    }
+
+    pub fn is_in_flight(&self, id: u64) -> bool {
+        matches!(self.in_flight, Some((in_flight, _)) if in_flight == id)
+    }
+
+    /// Takes note of a write reported by the runner, returns whether it was
+    /// the chunk in flight rather than other data sent meanwhile
+    pub fn written(&mut self, id: u64) -> bool {
+        match self.in_flight {
+            Some((in_flight, len)) if in_flight == id => {
+                self.in_flight = None;
+                self.written += len;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.written >= self.data.len()
+    }
+
+    /// Bytes written and the file size
+    pub fn progress(&self) -> (usize, usize) {
+        (self.written, self.data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender(data: &[u8], chunk_size: usize) -> FileSender {
+        let settings = SendFileSettings {
+            chunk_size,
+            ..SendFileSettings::default()
+        };
+        FileSender::new(String::from("file"), data.to_vec(), &settings)
+    }
+
+    #[test]
+    fn other_writes_of_the_same_size_are_not_the_chunk() {
+        let mut sender = sender(b"abcdef", 4);
+        let chunk = sender.next_chunk().unwrap();
+        assert_eq!(chunk, b"abcd");
+        sender.queued(7, chunk.len());
+        assert_eq!(sender.next_chunk(), None);
+
+        // A macro of the same length written meanwhile
+        assert!(!sender.written(6));
+        assert_eq!(sender.progress(), (0, 6));
+
+        assert!(sender.written(7));
+        assert_eq!(sender.progress(), (4, 6));
+        assert_eq!(sender.next_chunk().unwrap(), b"ef");
+    }
+
+    #[test]
+    fn done_once_every_chunk_is_written() {
+        let mut sender = sender(b"abc", 2);
+        for id in 1..=2 {
+            let chunk = sender.next_chunk().unwrap();
+            sender.queued(id, chunk.len());
+            assert!(sender.written(id));
+        }
+        assert!(sender.is_done());
+        assert_eq!(sender.next_chunk(), None);
+    }
+}
@@ -0,0 +1,290 @@
+pub mod crc;
+pub mod xmodem;
+pub mod zmodem;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+
+use crate::config::TRANSFER_DIRECTORY;
+
+/// File transfer protocol offered in the sidebar
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Protocol {
+    /// 128 byte blocks with an arithmetic checksum
+    Xmodem,
+    /// 128 byte blocks with CRC-16
+    #[default]
+    XmodemCrc,
+    /// 1024 byte blocks with CRC-16
+    Xmodem1k,
+    /// XMODEM-1K with a header block carrying the file name and size, several
+    /// files per session
+    Ymodem,
+    Zmodem,
+}
+
+impl Protocol {
+    pub const ALL: [Protocol; 5] = [
+        Protocol::Xmodem,
+        Protocol::XmodemCrc,
+        Protocol::Xmodem1k,
+        Protocol::Ymodem,
+        Protocol::Zmodem,
+    ];
+
+    /// Whether more than one file can be sent in one transfer
+    pub fn is_batch(&self) -> bool {
+        matches!(self, Protocol::Ymodem | Protocol::Zmodem)
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Xmodem => write!(f, "XMODEM"),
+            Protocol::XmodemCrc => write!(f, "XMODEM-CRC"),
+            Protocol::Xmodem1k => write!(f, "XMODEM-1K"),
+            Protocol::Ymodem => write!(f, "YMODEM"),
+            Protocol::Zmodem => write!(f, "ZMODEM"),
+        }
+    }
+}
+
+/// Protocol and files to send, as chosen in the sidebar
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransferSettings {
+    pub protocol: Protocol,
+    /// Files to send, separated by `;`
+    pub paths: String,
+}
+
+impl TransferSettings {
+    pub fn paths(&self) -> Vec<String> {
+        self.paths
+            .split(';')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// A file to send or one that was received
+#[derive(Debug, Clone)]
+pub struct TransferFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// What a transfer asks of its session after being fed
+#[derive(Debug, Default)]
+pub struct Output {
+    /// Bytes to write to the connection
+    pub send: Vec<u8>,
+    /// Lines for the transfer log in the terminal
+    pub log: Vec<String>,
+    /// Files completely received, to be saved
+    pub received: Vec<TransferFile>,
+    /// Set once the transfer is over, with why it failed if it did
+    pub finished: Option<Result<(), String>>,
+}
+
+impl Output {
+    pub fn log(&mut self, line: impl Into<String>) {
+        self.log.push(line.into());
+    }
+
+    pub fn fail(&mut self, reason: impl Into<String>) {
+        self.finished = Some(Err(reason.into()));
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = Some(Ok(()));
+    }
+}
+
+/// Where a transfer stands, shown in the sidebar
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub file: Option<String>,
+    pub bytes: usize,
+    /// Size of the current file, when the protocol tells
+    pub total: Option<usize>,
+    pub retries: u32,
+}
+
+/// Protocol state machine fed with the session's received data. It never
+/// touches the connection itself, everything it wants done is put in `Output`
+pub trait Transfer: std::fmt::Debug {
+    /// Label for the transfer log, such as "YMODEM send"
+    fn name(&self) -> String;
+
+    fn start(&mut self, now: Instant, out: &mut Output);
+
+    fn receive(&mut self, data: &[u8], now: Instant, out: &mut Output);
+
+    /// Retries or gives up once the other side has been quiet for too long
+    fn poll(&mut self, now: Instant, out: &mut Output);
+
+    /// Tells the other side to stop
+    fn cancel(&mut self, out: &mut Output);
+
+    fn progress(&self) -> Progress;
+}
+
+/// Transfer sending `files`, several are only accepted by batch protocols
+pub fn sender(protocol: Protocol, files: Vec<TransferFile>) -> Box<dyn Transfer> {
+    match protocol {
+        Protocol::Zmodem => Box::new(zmodem::ZmodemSender::new(files, true)),
+        _ => Box::new(xmodem::XmodemSender::new(protocol, files)),
+    }
+}
+
+/// Transfer receiving into memory, files are handed out through `Output::received`
+pub fn receiver(protocol: Protocol) -> Box<dyn Transfer> {
+    match protocol {
+        Protocol::Zmodem => Box::new(zmodem::ZmodemReceiver::new()),
+        _ => Box::new(xmodem::XmodemReceiver::new(protocol)),
+    }
+}
+
+/// Reads the files to send, named after the last component of their path
+pub async fn read_files(paths: Vec<String>) -> Result<Vec<TransferFile>, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        let data = tokio::fs::read(&path)
+            .await
+            .map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        let name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(path);
+        files.push(TransferFile { name, data });
+    }
+    Ok(files)
+}
+
+/// Saves a received file to the transfer directory without overwriting an
+/// earlier one of the same name
+pub fn save_file(file: &TransferFile) -> Result<PathBuf> {
+    let directory = Path::new(TRANSFER_DIRECTORY);
+    fs::create_dir_all(directory)
+        .with_context(|| format!("Cannot create {}", directory.display()))?;
+
+    // The name comes from the other side, only its last component is used
+    let name = file.name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() || c == ':' { '_' } else { c })
+        .collect();
+    let name = match name.as_str() {
+        "" | "." | ".." => String::from("received.bin"),
+        _ => name,
+    };
+
+    let mut path = directory.join(&name);
+    let mut copy = 1;
+    while path.exists() {
+        path = directory.join(format!("{}.{}", name, copy));
+        copy += 1;
+    }
+    fs::write(&path, &file.data).with_context(|| format!("Cannot write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    pub fn file(name: &str, len: usize) -> TransferFile {
+        TransferFile {
+            name: String::from(name),
+            data: (0..len).map(|i| (i * 7 % 256) as u8).collect(),
+        }
+    }
+
+    /// What came out of a loopback transfer
+    pub struct Loopback {
+        pub received: Vec<TransferFile>,
+        pub sender: Progress,
+        pub receiver: Progress,
+    }
+
+    /// Runs a sender straight into a receiver, flipping one byte at `corrupt_at`
+    /// of the sender's output if given. Data is fed in odd sized pieces so
+    /// packets are split across reads, and time jumps ahead whenever both
+    /// sides are waiting so timeouts fire
+    pub fn loopback(
+        protocol: Protocol,
+        files: Vec<TransferFile>,
+        corrupt_at: Option<usize>,
+    ) -> Loopback {
+        let mut sender = sender(protocol, files);
+        let mut receiver = receiver(protocol);
+        let mut now = Instant::now();
+        let mut sender_out = Output::default();
+        let mut receiver_out = Output::default();
+        sender.start(now, &mut sender_out);
+        receiver.start(now, &mut receiver_out);
+
+        let mut sent = 0;
+        let mut received = Vec::new();
+        for _ in 0..100_000 {
+            let mut to_receiver = std::mem::take(&mut sender_out.send);
+            let to_sender = std::mem::take(&mut receiver_out.send);
+            if let Some(offset) = corrupt_at {
+                if (sent..sent + to_receiver.len()).contains(&offset) {
+                    to_receiver[offset - sent] ^= 0x55;
+                }
+            }
+            sent += to_receiver.len();
+
+            for piece in to_receiver.chunks(37) {
+                receiver.receive(piece, now, &mut receiver_out);
+            }
+            for piece in to_sender.chunks(5) {
+                sender.receive(piece, now, &mut sender_out);
+            }
+            received.append(&mut receiver_out.received);
+
+            if sender_out.send.is_empty() && receiver_out.send.is_empty() {
+                if sender_out.finished.is_some() && receiver_out.finished.is_some() {
+                    break;
+                }
+                now += Duration::from_secs(11);
+                sender.poll(now, &mut sender_out);
+                receiver.poll(now, &mut receiver_out);
+            }
+        }
+
+        assert_eq!(sender_out.finished, Some(Ok(())), "{:?}", sender_out.log);
+        assert_eq!(
+            receiver_out.finished,
+            Some(Ok(())),
+            "{:?}",
+            receiver_out.log
+        );
+        Loopback {
+            received,
+            sender: sender.progress(),
+            receiver: receiver.progress(),
+        }
+    }
+
+    /// Checks every file arrived intact, under its own name when the protocol
+    /// carries one
+    pub fn assert_received(protocol: Protocol, received: &[TransferFile], files: &[TransferFile]) {
+        assert_eq!(received.len(), files.len());
+        for (received, file) in received.iter().zip(files) {
+            if protocol.is_batch() {
+                assert_eq!(received.name, file.name);
+            }
+            assert_eq!(received.data, file.data);
+        }
+    }
+}
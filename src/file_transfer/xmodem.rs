@@ -0,0 +1,632 @@
+use std::time::Instant;
+
+use chrono::Local;
+
+use super::crc::{checksum, crc16};
+use super::{Output, Progress, Protocol, Transfer, TransferFile};
+use crate::config::{TRANSFER_MAX_RETRIES, TRANSFER_TIMEOUT};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Requests CRC-16 instead of checksums
+const CRC: u8 = b'C';
+/// Pads the last block of a file
+const SUB: u8 = 0x1A;
+
+/// Two CANs in a row abort the transfer, a few more make sure they arrive
+const CANCEL: [u8; 5] = [CAN; 5];
+
+fn is_ymodem(protocol: Protocol) -> bool {
+    protocol == Protocol::Ymodem
+}
+
+/// A block with its header and check bytes
+fn block(number: u8, data: &[u8], size: usize, use_crc: bool) -> Vec<u8> {
+    let mut payload = data.to_vec();
+    payload.resize(size, SUB);
+
+    let mut block = vec![if size == 1024 { STX } else { SOH }, number, !number];
+    block.extend_from_slice(&payload);
+    if use_crc {
+        block.extend_from_slice(&crc16(&payload).to_be_bytes());
+    } else {
+        block.push(checksum(&payload));
+    }
+    block
+}
+
+/// YMODEM block 0 with the file name and size, or the empty one ending the batch
+fn header_block(file: Option<&TransferFile>) -> Vec<u8> {
+    let mut payload = Vec::new();
+    if let Some(file) = file {
+        payload.extend_from_slice(file.name.as_bytes());
+        payload.push(0);
+        payload.extend_from_slice(file.data.len().to_string().as_bytes());
+        payload.push(0);
+    }
+    let size = if payload.len() > 128 { 1024 } else { 128 };
+    let mut padded = payload;
+    padded.resize(size, 0);
+    block(0, &padded, size, true)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Awaiting {
+    /// Waiting for `C` or NAK before the first block of a file or header
+    Receiver,
+    Ack,
+    EotAck,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SendPhase {
+    /// YMODEM block 0 for the current file
+    Header,
+    Data,
+    /// YMODEM empty block 0 after the last file
+    End,
+}
+
+/// Sends files with XMODEM or a YMODEM batch
+#[derive(Debug)]
+pub struct XmodemSender {
+    protocol: Protocol,
+    files: Vec<TransferFile>,
+    file_index: usize,
+    phase: SendPhase,
+    state: Awaiting,
+    use_crc: bool,
+    /// Bytes of the current file acknowledged by the receiver
+    offset: usize,
+    block_number: u8,
+    /// Size of the block in flight
+    block_len: usize,
+    last_packet: Vec<u8>,
+    retries: u32,
+    total_retries: u32,
+    deadline: Instant,
+    /// The previous byte was a CAN
+    cancel_pending: bool,
+}
+
+impl XmodemSender {
+    pub fn new(protocol: Protocol, files: Vec<TransferFile>) -> Self {
+        Self {
+            protocol,
+            files,
+            file_index: 0,
+            phase: if is_ymodem(protocol) {
+                SendPhase::Header
+            } else {
+                SendPhase::Data
+            },
+            state: Awaiting::Receiver,
+            use_crc: protocol != Protocol::Xmodem,
+            offset: 0,
+            block_number: 1,
+            block_len: 0,
+            last_packet: Vec::new(),
+            retries: 0,
+            total_retries: 0,
+            deadline: Instant::now(),
+            cancel_pending: false,
+        }
+    }
+
+    fn file(&self) -> Option<&TransferFile> {
+        self.files.get(self.file_index)
+    }
+
+    fn send(&mut self, packet: Vec<u8>, now: Instant, out: &mut Output) {
+        out.send.extend_from_slice(&packet);
+        self.last_packet = packet;
+        self.deadline = now + TRANSFER_TIMEOUT;
+    }
+
+    /// Counts a failed attempt, giving up once there were too many
+    fn retry(&mut self, reason: &str, now: Instant, out: &mut Output) -> bool {
+        self.retries += 1;
+        self.total_retries += 1;
+        self.deadline = now + TRANSFER_TIMEOUT;
+        if self.retries > TRANSFER_MAX_RETRIES {
+            out.send.extend_from_slice(&CANCEL);
+            out.fail(format!(
+                "{}, giving up after {} retries",
+                reason, TRANSFER_MAX_RETRIES
+            ));
+            false
+        } else {
+            out.log(format!(
+                "{}, retry {}/{}",
+                reason, self.retries, TRANSFER_MAX_RETRIES
+            ));
+            true
+        }
+    }
+
+    /// Sends what the current phase calls for once the receiver is ready
+    fn send_next(&mut self, now: Instant, out: &mut Output) {
+        match self.phase {
+            SendPhase::Header => {
+                let file = self.file().cloned();
+                if let Some(file) = &file {
+                    out.log(format!("Sending {} ({} bytes)", file.name, file.data.len()));
+                }
+                let packet = header_block(file.as_ref());
+                self.send(packet, now, out);
+                self.state = Awaiting::Ack;
+            }
+            SendPhase::End => {
+                let packet = header_block(None);
+                self.send(packet, now, out);
+                self.state = Awaiting::Ack;
+            }
+            SendPhase::Data => {
+                let Some(file) = self.file() else {
+                    out.finish();
+                    return;
+                };
+                let remaining = file.data.len() - self.offset;
+                if remaining == 0 {
+                    self.send(vec![EOT], now, out);
+                    self.state = Awaiting::EotAck;
+                    return;
+                }
+
+                // Short tails go in 128 byte blocks even with 1K blocks, to pad less
+                let size = match self.protocol {
+                    Protocol::Xmodem1k | Protocol::Ymodem if remaining > 128 => 1024,
+                    _ => 128,
+                };
+                let block_len = remaining.min(size);
+                let data = &file.data[self.offset..self.offset + block_len];
+                let packet = block(self.block_number, data, size, self.use_crc);
+                self.block_len = block_len;
+                self.send(packet, now, out);
+                self.state = Awaiting::Ack;
+            }
+        }
+    }
+
+    fn handle(&mut self, byte: u8, now: Instant, out: &mut Output) {
+        if byte == CAN {
+            if self.cancel_pending {
+                out.fail("Cancelled by receiver");
+                return;
+            }
+            self.cancel_pending = true;
+            return;
+        }
+        self.cancel_pending = false;
+
+        match (self.state, byte) {
+            (Awaiting::Receiver, CRC | NAK) => {
+                // The receiver picks the check for the whole transfer with its first request
+                if self.file_index == 0 && self.offset == 0 && self.phase != SendPhase::End {
+                    self.use_crc = byte == CRC;
+                    out.log(if self.use_crc {
+                        "Receiver ready, using CRC-16"
+                    } else {
+                        "Receiver ready, using checksums"
+                    });
+                }
+                self.retries = 0;
+                self.send_next(now, out);
+            }
+            (Awaiting::Ack, ACK) => {
+                self.retries = 0;
+                match self.phase {
+                    SendPhase::Header => {
+                        // The receiver asks for the data with another `C`
+                        self.phase = SendPhase::Data;
+                        self.block_number = 1;
+                        self.offset = 0;
+                        self.state = Awaiting::Receiver;
+                    }
+                    SendPhase::Data => {
+                        self.offset += self.block_len;
+                        self.block_number = self.block_number.wrapping_add(1);
+                        self.send_next(now, out);
+                    }
+                    SendPhase::End => out.finish(),
+                }
+            }
+            // A receiver that missed the first block keeps asking to start
+            (Awaiting::Ack, NAK | CRC) if self.retry("Block rejected", now, out) => {
+                let packet = self.last_packet.clone();
+                self.send(packet, now, out);
+            }
+            (Awaiting::EotAck, ACK) => {
+                self.retries = 0;
+                if let Some(file) = self.file() {
+                    out.log(format!("Sent {}", file.name));
+                }
+                if is_ymodem(self.protocol) {
+                    self.file_index += 1;
+                    self.offset = 0;
+                    self.phase = if self.file_index < self.files.len() {
+                        SendPhase::Header
+                    } else {
+                        SendPhase::End
+                    };
+                    self.state = Awaiting::Receiver;
+                } else {
+                    out.finish();
+                }
+            }
+            (Awaiting::EotAck, NAK) => {
+                // YMODEM receivers NAK the first EOT to be sure it was no line noise
+                self.send(vec![EOT], now, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Transfer for XmodemSender {
+    fn name(&self) -> String {
+        format!("{} send", self.protocol)
+    }
+
+    fn start(&mut self, now: Instant, out: &mut Output) {
+        self.deadline = now + TRANSFER_TIMEOUT;
+        out.log("Waiting for the receiver to start");
+    }
+
+    fn receive(&mut self, data: &[u8], now: Instant, out: &mut Output) {
+        for &byte in data {
+            if out.finished.is_some() {
+                return;
+            }
+            self.handle(byte, now, out);
+        }
+    }
+
+    fn poll(&mut self, now: Instant, out: &mut Output) {
+        if now < self.deadline {
+            return;
+        }
+        // Retransmissions are left to the receiver's NAKs, resending here as
+        // well could answer one NAK twice and let a stray ACK skip a block
+        self.retry("No response from the receiver", now, out);
+    }
+
+    fn cancel(&mut self, out: &mut Output) {
+        out.send.extend_from_slice(&CANCEL);
+        out.fail("Cancelled");
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            file: self.file().map(|file| file.name.clone()),
+            bytes: self.offset,
+            total: self.file().map(|file| file.data.len()),
+            retries: self.total_retries,
+        }
+    }
+}
+
+/// Receives a file with XMODEM, or a batch of them with YMODEM
+#[derive(Debug)]
+pub struct XmodemReceiver {
+    protocol: Protocol,
+    buffer: Vec<u8>,
+    /// Block number expected next, 0 for a YMODEM header
+    expected: u8,
+    /// Any block arrived, until then the start request is repeated
+    started: bool,
+    file_name: Option<String>,
+    file_size: Option<usize>,
+    data: Vec<u8>,
+    /// A YMODEM EOT was NAKed once already
+    eot_pending: bool,
+    retries: u32,
+    total_retries: u32,
+    deadline: Instant,
+}
+
+impl XmodemReceiver {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            buffer: Vec::new(),
+            expected: if is_ymodem(protocol) { 0 } else { 1 },
+            started: false,
+            file_name: None,
+            file_size: None,
+            data: Vec::new(),
+            eot_pending: false,
+            retries: 0,
+            total_retries: 0,
+            deadline: Instant::now(),
+        }
+    }
+
+    fn use_crc(&self) -> bool {
+        self.protocol != Protocol::Xmodem
+    }
+
+    /// `C` or NAK, asking the sender to begin
+    fn start_request(&self) -> u8 {
+        if self.use_crc() {
+            CRC
+        } else {
+            NAK
+        }
+    }
+
+    fn reply(&mut self, byte: u8, now: Instant, out: &mut Output) {
+        out.send.push(byte);
+        self.deadline = now + TRANSFER_TIMEOUT;
+    }
+
+    fn retry(&mut self, reason: &str, now: Instant, out: &mut Output) -> bool {
+        self.retries += 1;
+        self.total_retries += 1;
+        if self.retries > TRANSFER_MAX_RETRIES {
+            out.send.extend_from_slice(&CANCEL);
+            out.fail(format!(
+                "{}, giving up after {} retries",
+                reason, TRANSFER_MAX_RETRIES
+            ));
+            return false;
+        }
+        if self.started {
+            out.log(format!(
+                "{}, retry {}/{}",
+                reason, self.retries, TRANSFER_MAX_RETRIES
+            ));
+        }
+        let request = if self.started {
+            NAK
+        } else {
+            self.start_request()
+        };
+        self.reply(request, now, out);
+        true
+    }
+
+    /// Hands over the file received so far
+    fn deliver(&mut self, out: &mut Output) {
+        let mut data = std::mem::take(&mut self.data);
+        match self.file_size {
+            Some(size) => data.truncate(size),
+            None => {
+                // Plain XMODEM has no size, so the padding of the last block is dropped
+                while data.last() == Some(&SUB) {
+                    data.pop();
+                }
+            }
+        }
+        let name = self.file_name.take().unwrap_or_else(|| {
+            Local::now()
+                .format("xmodem_%Y-%m-%d_%H-%M-%S.bin")
+                .to_string()
+        });
+        out.log(format!("Received {} ({} bytes)", name, data.len()));
+        out.received.push(TransferFile { name, data });
+        self.file_size = None;
+    }
+
+    /// Parses YMODEM block 0, returns false for the empty one ending the batch
+    fn read_header(&mut self, payload: &[u8], out: &mut Output) -> bool {
+        let mut fields = payload.split(|&byte| byte == 0);
+        let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+        if name.is_empty() {
+            return false;
+        }
+        let size = fields
+            .next()
+            .map(String::from_utf8_lossy)
+            .and_then(|info| info.split(' ').next()?.parse().ok());
+
+        match size {
+            Some(size) => out.log(format!("Receiving {} ({} bytes)", name, size)),
+            None => out.log(format!("Receiving {}", name)),
+        }
+        self.file_name = Some(name);
+        self.file_size = size;
+        self.data.clear();
+        true
+    }
+
+    /// Consumes complete blocks and control bytes from the buffer
+    fn process(&mut self, now: Instant, out: &mut Output) {
+        while let Some(&first) = self.buffer.first() {
+            if out.finished.is_some() {
+                return;
+            }
+
+            let size = match first {
+                SOH => 128,
+                STX => 1024,
+                EOT => {
+                    self.buffer.remove(0);
+                    self.end_of_file(now, out);
+                    continue;
+                }
+                CAN => {
+                    if self.buffer.get(1) == Some(&CAN) {
+                        out.fail("Cancelled by sender");
+                        return;
+                    }
+                    if self.buffer.len() < 2 {
+                        return;
+                    }
+                    self.buffer.remove(0);
+                    continue;
+                }
+                _ => {
+                    // Noise between blocks
+                    self.buffer.remove(0);
+                    continue;
+                }
+            };
+
+            let check_len = if self.use_crc() { 2 } else { 1 };
+            let block_len = 3 + size + check_len;
+            if self.buffer.len() < block_len {
+                return;
+            }
+            let block: Vec<u8> = self.buffer.drain(..block_len).collect();
+            self.block(&block, size, now, out);
+        }
+    }
+
+    fn block(&mut self, block: &[u8], size: usize, now: Instant, out: &mut Output) {
+        let number = block[1];
+        let payload = &block[3..3 + size];
+        let check = &block[3 + size..];
+        let valid_check = if self.use_crc() {
+            check == crc16(payload).to_be_bytes()
+        } else {
+            check[0] == checksum(payload)
+        };
+
+        if number != !block[2] || !valid_check {
+            self.buffer.clear();
+            self.retry(&format!("Block {} damaged", number), now, out);
+            return;
+        }
+
+        self.started = true;
+        self.eot_pending = false;
+        if number == self.expected.wrapping_sub(1) && !(self.expected == 0 && self.data.is_empty())
+        {
+            // Our ACK got lost, the sender repeated the block
+            self.reply(ACK, now, out);
+            return;
+        }
+        if number != self.expected {
+            out.send.extend_from_slice(&CANCEL);
+            out.fail(format!(
+                "Expected block {} but got {}",
+                self.expected, number
+            ));
+            return;
+        }
+
+        self.retries = 0;
+        if is_ymodem(self.protocol) && self.expected == 0 && self.file_name.is_none() {
+            let more = self.read_header(payload, out);
+            self.reply(ACK, now, out);
+            if more {
+                self.expected = 1;
+                self.reply(CRC, now, out);
+            } else {
+                out.finish();
+            }
+            return;
+        }
+
+        self.data.extend_from_slice(payload);
+        self.expected = self.expected.wrapping_add(1);
+        self.reply(ACK, now, out);
+    }
+
+    fn end_of_file(&mut self, now: Instant, out: &mut Output) {
+        if !is_ymodem(self.protocol) {
+            self.reply(ACK, now, out);
+            self.deliver(out);
+            out.finish();
+            return;
+        }
+
+        if !self.eot_pending {
+            self.eot_pending = true;
+            self.reply(NAK, now, out);
+            return;
+        }
+        self.eot_pending = false;
+        self.reply(ACK, now, out);
+        self.deliver(out);
+        // Ask for the header of the next file
+        self.expected = 0;
+        self.reply(CRC, now, out);
+    }
+}
+
+impl Transfer for XmodemReceiver {
+    fn name(&self) -> String {
+        format!("{} receive", self.protocol)
+    }
+
+    fn start(&mut self, now: Instant, out: &mut Output) {
+        out.log("Waiting for the sender");
+        let request = self.start_request();
+        self.reply(request, now, out);
+    }
+
+    fn receive(&mut self, data: &[u8], now: Instant, out: &mut Output) {
+        self.buffer.extend_from_slice(data);
+        self.process(now, out);
+    }
+
+    fn poll(&mut self, now: Instant, out: &mut Output) {
+        if now >= self.deadline {
+            self.buffer.clear();
+            self.retry("Timed out waiting for data", now, out);
+        }
+    }
+
+    fn cancel(&mut self, out: &mut Output) {
+        out.send.extend_from_slice(&CANCEL);
+        out.fail("Cancelled");
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            file: self.file_name.clone(),
+            bytes: self.data.len(),
+            total: self.file_size,
+            retries: self.total_retries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::tests::{assert_received, file, loopback};
+
+    const PROTOCOLS: [Protocol; 4] = [
+        Protocol::Xmodem,
+        Protocol::XmodemCrc,
+        Protocol::Xmodem1k,
+        Protocol::Ymodem,
+    ];
+
+    #[test]
+    fn loopback_delivers_the_file() {
+        for protocol in PROTOCOLS {
+            let files = vec![file("a.bin", 3000)];
+            let result = loopback(protocol, files.clone(), None);
+            assert_received(protocol, &result.received, &files);
+            assert_eq!(result.sender.retries, 0, "{}", protocol);
+            assert_eq!(result.receiver.retries, 0, "{}", protocol);
+        }
+    }
+
+    #[test]
+    fn damaged_block_is_rejected_and_sent_again() {
+        for protocol in PROTOCOLS {
+            let files = vec![file("a.bin", 3000)];
+            let result = loopback(protocol, files.clone(), Some(2000));
+            assert_received(protocol, &result.received, &files);
+            // One NAK from the receiver, one repeated block from the sender
+            assert_eq!(result.receiver.retries, 1, "{}", protocol);
+            assert_eq!(result.sender.retries, 1, "{}", protocol);
+        }
+    }
+
+    #[test]
+    fn ymodem_sends_a_batch_including_an_empty_file() {
+        let files = vec![file("a.bin", 3000), file("b.bin", 20000), file("empty", 0)];
+        let result = loopback(Protocol::Ymodem, files.clone(), None);
+        assert_received(Protocol::Ymodem, &result.received, &files);
+    }
+}
@@ -0,0 +1,976 @@
+use std::time::Instant;
+
+use super::crc::{crc16, crc32};
+use super::{Output, Progress, Transfer, TransferFile};
+use crate::config::{
+    TRANSFER_MAX_RETRIES, TRANSFER_TIMEOUT, ZMODEM_FRAME_SIZE, ZMODEM_SUBPACKET_SIZE,
+};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+const XON: u8 = 0x11;
+
+/* frame types */
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCAN: u8 = 16;
+const ZCOMMAND: u8 = 18;
+
+/* data subpacket ends */
+/// End of frame, a header follows
+const ZCRCE: u8 = b'h';
+/// More data follows without a reply
+const ZCRCG: u8 = b'i';
+/// More data follows, the receiver answers with ZACK
+const ZCRCQ: u8 = b'j';
+/// End of frame, the receiver answers with ZACK
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+/* ZRINIT capabilities, in ZF0 */
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+
+/// Eight CANs abort the peer, the backspaces erase them from a terminal
+const ABORT: [u8; 16] = [
+    ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, ZDLE, 8, 8, 8, 8, 8, 8, 8, 8,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    kind: u8,
+    /// ZP0 to ZP3, a little endian file position or the flags ZF3 to ZF0
+    data: [u8; 4],
+}
+
+impl Header {
+    fn new(kind: u8) -> Self {
+        Self { kind, data: [0; 4] }
+    }
+
+    fn with_position(kind: u8, position: usize) -> Self {
+        Self {
+            kind,
+            data: (position as u32).to_le_bytes(),
+        }
+    }
+
+    fn with_flags(kind: u8, zf0: u8) -> Self {
+        Self {
+            kind,
+            data: [0, 0, 0, zf0],
+        }
+    }
+
+    fn position(&self) -> usize {
+        u32::from_le_bytes(self.data) as usize
+    }
+
+    fn zf0(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn bytes(&self) -> [u8; 5] {
+        let [p0, p1, p2, p3] = self.data;
+        [self.kind, p0, p1, p2, p3]
+    }
+
+    /// Whether a data subpacket follows the header
+    fn has_data(&self) -> bool {
+        matches!(self.kind, ZSINIT | ZFILE | ZDATA | ZCOMMAND)
+    }
+}
+
+fn escape(byte: u8, out: &mut Vec<u8>) {
+    match byte {
+        ZDLE | 0x10 | 0x90 | 0x11 | 0x91 | 0x13 | 0x93 | 0x0d | 0x8d => {
+            out.push(ZDLE);
+            out.push(byte ^ 0x40);
+        }
+        _ => out.push(byte),
+    }
+}
+
+fn escape_all(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        escape(byte, out);
+    }
+}
+
+/// Header in hex, used for replies and by the receiver
+fn hex_header(header: Header) -> Vec<u8> {
+    let bytes = header.bytes();
+    let mut out = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+    for byte in bytes.iter().chain(crc16(&bytes).to_be_bytes().iter()) {
+        out.extend_from_slice(format!("{:02x}", byte).as_bytes());
+    }
+    out.extend_from_slice(&[b'\r', b'\n' | 0x80]);
+    if header.kind != ZFIN && header.kind != ZACK {
+        out.push(XON);
+    }
+    out
+}
+
+/// Header in binary with CRC-16 or CRC-32, used before data subpackets
+fn binary_header(header: Header, use_crc32: bool) -> Vec<u8> {
+    let bytes = header.bytes();
+    let mut out = vec![ZPAD, ZDLE, if use_crc32 { ZBIN32 } else { ZBIN }];
+    escape_all(&bytes, &mut out);
+    if use_crc32 {
+        escape_all(&crc32(&bytes).to_le_bytes(), &mut out);
+    } else {
+        escape_all(&crc16(&bytes).to_be_bytes(), &mut out);
+    }
+    out
+}
+
+fn data_subpacket(data: &[u8], end: u8, use_crc32: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    escape_all(data, &mut out);
+    out.extend_from_slice(&[ZDLE, end]);
+
+    let mut checked = data.to_vec();
+    checked.push(end);
+    if use_crc32 {
+        escape_all(&crc32(&checked).to_le_bytes(), &mut out);
+    } else {
+        escape_all(&crc16(&checked).to_be_bytes(), &mut out);
+    }
+    if end == ZCRCW {
+        out.push(XON);
+    }
+    out
+}
+
+/// What the decoder made of the received bytes
+#[derive(Debug)]
+enum Frame {
+    Header(Header),
+    Data {
+        data: Vec<u8>,
+        end: u8,
+    },
+    /// A header or subpacket failed its check
+    Damaged,
+    /// The peer sent the abort sequence
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DecodeState {
+    /// Skipping anything that is not the start of a header
+    Idle,
+    Pad,
+    PadZdle,
+    Hex,
+    Binary {
+        use_crc32: bool,
+    },
+    Data,
+    DataCrc {
+        end: u8,
+    },
+}
+
+/// Splits the received byte stream into headers and data subpackets
+#[derive(Debug)]
+struct Decoder {
+    state: DecodeState,
+    buffer: Vec<u8>,
+    data: Vec<u8>,
+    /// The previous byte was a ZDLE
+    escaped: bool,
+    /// Check used by the latest binary header, data subpackets use the same
+    use_crc32: bool,
+    /// Run of ZDLE/CAN bytes, five in a row abort
+    cancels: usize,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            state: DecodeState::Idle,
+            buffer: Vec::new(),
+            data: Vec::new(),
+            escaped: false,
+            use_crc32: false,
+            cancels: 0,
+        }
+    }
+
+    /// Resolves ZDLE escapes, `Err` carries a frame end
+    fn unescape(&mut self, byte: u8) -> Option<Result<u8, u8>> {
+        if self.escaped {
+            self.escaped = false;
+            return Some(match byte {
+                ZCRCE | ZCRCG | ZCRCQ | ZCRCW => Err(byte),
+                ZRUB0 => Ok(0x7f),
+                ZRUB1 => Ok(0xff),
+                _ => Ok(byte ^ 0x40),
+            });
+        }
+        match byte {
+            ZDLE => {
+                self.escaped = true;
+                None
+            }
+            // Flow control characters are never part of the data
+            0x11 | 0x13 | 0x91 | 0x93 => None,
+            _ => Some(Ok(byte)),
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<Frame> {
+        if byte == ZDLE {
+            self.cancels += 1;
+            if self.cancels >= 5 {
+                self.cancels = 0;
+                self.state = DecodeState::Idle;
+                return Some(Frame::Cancelled);
+            }
+        } else {
+            self.cancels = 0;
+        }
+
+        match self.state {
+            DecodeState::Idle => {
+                if byte == ZPAD {
+                    self.state = DecodeState::Pad;
+                }
+                None
+            }
+            DecodeState::Pad => {
+                self.state = match byte {
+                    ZPAD => DecodeState::Pad,
+                    ZDLE => DecodeState::PadZdle,
+                    _ => DecodeState::Idle,
+                };
+                None
+            }
+            DecodeState::PadZdle => {
+                self.buffer.clear();
+                self.escaped = false;
+                self.state = match byte {
+                    ZHEX => DecodeState::Hex,
+                    ZBIN => DecodeState::Binary { use_crc32: false },
+                    ZBIN32 => DecodeState::Binary { use_crc32: true },
+                    _ => DecodeState::Idle,
+                };
+                None
+            }
+            DecodeState::Hex => {
+                if !byte.is_ascii_hexdigit() {
+                    self.state = DecodeState::Idle;
+                    return Some(Frame::Damaged);
+                }
+                self.buffer.push(byte);
+                if self.buffer.len() < 14 {
+                    return None;
+                }
+                let bytes: Option<Vec<u8>> = self
+                    .buffer
+                    .chunks(2)
+                    .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+                    .collect();
+                self.state = DecodeState::Idle;
+                match bytes {
+                    Some(bytes) if crc16(&bytes[..5]).to_be_bytes() == bytes[5..7] => {
+                        self.header(&bytes[..5], false)
+                    }
+                    _ => Some(Frame::Damaged),
+                }
+            }
+            DecodeState::Binary { use_crc32 } => {
+                let byte = match self.unescape(byte)? {
+                    Ok(byte) => byte,
+                    Err(_) => {
+                        self.state = DecodeState::Idle;
+                        return Some(Frame::Damaged);
+                    }
+                };
+                self.buffer.push(byte);
+                let check_len = if use_crc32 { 4 } else { 2 };
+                if self.buffer.len() < 5 + check_len {
+                    return None;
+                }
+                self.state = DecodeState::Idle;
+                let bytes = std::mem::take(&mut self.buffer);
+                let valid = if use_crc32 {
+                    crc32(&bytes[..5]).to_le_bytes() == bytes[5..9]
+                } else {
+                    crc16(&bytes[..5]).to_be_bytes() == bytes[5..7]
+                };
+                if valid {
+                    self.header(&bytes[..5], use_crc32)
+                } else {
+                    Some(Frame::Damaged)
+                }
+            }
+            DecodeState::Data => {
+                match self.unescape(byte)? {
+                    Ok(byte) => {
+                        self.data.push(byte);
+                        if self.data.len() > ZMODEM_SUBPACKET_SIZE * 8 {
+                            self.state = DecodeState::Idle;
+                            self.data.clear();
+                            return Some(Frame::Damaged);
+                        }
+                    }
+                    Err(end) => {
+                        self.buffer.clear();
+                        self.state = DecodeState::DataCrc { end };
+                    }
+                }
+                None
+            }
+            DecodeState::DataCrc { end } => {
+                let byte = match self.unescape(byte)? {
+                    Ok(byte) => byte,
+                    Err(_) => {
+                        self.state = DecodeState::Idle;
+                        return Some(Frame::Damaged);
+                    }
+                };
+                self.buffer.push(byte);
+                let check_len = if self.use_crc32 { 4 } else { 2 };
+                if self.buffer.len() < check_len {
+                    return None;
+                }
+
+                let mut data = std::mem::take(&mut self.data);
+                data.push(end);
+                let valid = if self.use_crc32 {
+                    crc32(&data).to_le_bytes()[..] == self.buffer[..]
+                } else {
+                    crc16(&data).to_be_bytes()[..] == self.buffer[..]
+                };
+                data.pop();
+                if !valid {
+                    self.state = DecodeState::Idle;
+                    return Some(Frame::Damaged);
+                }
+
+                self.state = match end {
+                    ZCRCG | ZCRCQ => DecodeState::Data,
+                    _ => DecodeState::Idle,
+                };
+                Some(Frame::Data { data, end })
+            }
+        }
+    }
+
+    fn header(&mut self, bytes: &[u8], use_crc32: bool) -> Option<Frame> {
+        let header = Header {
+            kind: bytes[0],
+            data: [bytes[1], bytes[2], bytes[3], bytes[4]],
+        };
+        if header.has_data() {
+            self.use_crc32 = use_crc32;
+            self.data.clear();
+            self.state = DecodeState::Data;
+        }
+        Some(Frame::Header(header))
+    }
+}
+
+/// File name and size from the ZFILE subpacket
+fn parse_file_info(data: &[u8]) -> (String, Option<usize>) {
+    let mut fields = data.split(|&byte| byte == 0);
+    let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+    let size = fields
+        .next()
+        .map(String::from_utf8_lossy)
+        .and_then(|info| info.split(' ').next()?.parse().ok());
+    (name, size)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Awaiting {
+    /// ZRQINIT sent, waiting for ZRINIT
+    Receiver,
+    /// ZFILE sent, waiting for ZRPOS or ZSKIP
+    Position,
+    /// A frame ending in ZCRCW was sent, waiting for ZACK
+    Ack,
+    /// ZEOF sent, waiting for ZRINIT
+    EofAck,
+    /// ZFIN sent, waiting for the receiver's ZFIN
+    Fin,
+}
+
+/// Sends files with ZMODEM, one frame of data per ZACK
+#[derive(Debug)]
+pub struct ZmodemSender {
+    files: Vec<TransferFile>,
+    file_index: usize,
+    /// Sends `rz` first to start the receiver on a remote shell
+    announce: bool,
+    decoder: Decoder,
+    state: Awaiting,
+    use_crc32: bool,
+    /// Position the current frame starts at and the position after it
+    offset: usize,
+    frame_end: usize,
+    last_packet: Vec<u8>,
+    retries: u32,
+    total_retries: u32,
+    deadline: Instant,
+}
+
+impl ZmodemSender {
+    pub fn new(files: Vec<TransferFile>, announce: bool) -> Self {
+        Self {
+            files,
+            file_index: 0,
+            announce,
+            decoder: Decoder::new(),
+            state: Awaiting::Receiver,
+            use_crc32: false,
+            offset: 0,
+            frame_end: 0,
+            last_packet: Vec::new(),
+            retries: 0,
+            total_retries: 0,
+            deadline: Instant::now(),
+        }
+    }
+
+    fn file(&self) -> Option<&TransferFile> {
+        self.files.get(self.file_index)
+    }
+
+    fn send(&mut self, packet: Vec<u8>, state: Awaiting, now: Instant, out: &mut Output) {
+        out.send.extend_from_slice(&packet);
+        self.last_packet = packet;
+        self.state = state;
+        self.deadline = now + TRANSFER_TIMEOUT;
+    }
+
+    fn retry(&mut self, reason: &str, now: Instant, out: &mut Output) -> bool {
+        self.retries += 1;
+        self.total_retries += 1;
+        self.deadline = now + TRANSFER_TIMEOUT;
+        if self.retries > TRANSFER_MAX_RETRIES {
+            out.send.extend_from_slice(&ABORT);
+            out.fail(format!(
+                "{}, giving up after {} retries",
+                reason, TRANSFER_MAX_RETRIES
+            ));
+            false
+        } else {
+            out.log(format!(
+                "{}, retry {}/{}",
+                reason, self.retries, TRANSFER_MAX_RETRIES
+            ));
+            true
+        }
+    }
+
+    /// Offers the current file, or ends the session when all were sent
+    fn send_file_header(&mut self, now: Instant, out: &mut Output) {
+        let Some(file) = self.file().cloned() else {
+            self.send(hex_header(Header::new(ZFIN)), Awaiting::Fin, now, out);
+            return;
+        };
+
+        out.log(format!(
+            "Offering {} ({} bytes)",
+            file.name,
+            file.data.len()
+        ));
+        let mut info = file.name.into_bytes();
+        info.push(0);
+        info.extend_from_slice(file.data.len().to_string().as_bytes());
+        info.push(0);
+
+        let mut packet = binary_header(Header::new(ZFILE), self.use_crc32);
+        packet.extend(data_subpacket(&info, ZCRCW, self.use_crc32));
+        self.send(packet, Awaiting::Position, now, out);
+    }
+
+    /// Sends up to a frame of data from `offset`, ending the file after the last one
+    fn send_frame(&mut self, now: Instant, out: &mut Output) {
+        let Some(file) = self.file() else {
+            return;
+        };
+        let len = file.data.len();
+        let end = (self.offset + ZMODEM_FRAME_SIZE).min(len);
+
+        let mut packet = binary_header(Header::with_position(ZDATA, self.offset), self.use_crc32);
+        let chunks: Vec<&[u8]> = if self.offset == end {
+            vec![&[]]
+        } else {
+            file.data[self.offset..end]
+                .chunks(ZMODEM_SUBPACKET_SIZE)
+                .collect()
+        };
+        let last = chunks.len() - 1;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let frame_end = match (index == last, end == len) {
+                (false, _) => ZCRCG,
+                (true, true) => ZCRCE,
+                (true, false) => ZCRCW,
+            };
+            packet.extend(data_subpacket(chunk, frame_end, self.use_crc32));
+        }
+
+        self.frame_end = end;
+        if end == len {
+            packet.extend(binary_header(
+                Header::with_position(ZEOF, len),
+                self.use_crc32,
+            ));
+            self.send(packet, Awaiting::EofAck, now, out);
+        } else {
+            self.send(packet, Awaiting::Ack, now, out);
+        }
+    }
+
+    fn handle(&mut self, frame: Frame, now: Instant, out: &mut Output) {
+        let header = match frame {
+            Frame::Header(header) => header,
+            Frame::Cancelled => {
+                out.fail("Cancelled by receiver");
+                return;
+            }
+            Frame::Damaged | Frame::Data { .. } => return,
+        };
+
+        match (self.state, header.kind) {
+            (_, ZCAN | ZABORT | ZFERR) => out.fail("Aborted by receiver"),
+            (Awaiting::Receiver, ZRINIT) => {
+                self.use_crc32 = header.zf0() & CANFC32 != 0;
+                self.retries = 0;
+                self.send_file_header(now, out);
+            }
+            // The receiver missed the offer
+            (Awaiting::Position, ZRINIT) if self.retry("File offer not answered", now, out) => {
+                self.send_file_header(now, out);
+            }
+            (Awaiting::Position, ZSKIP) | (Awaiting::EofAck, ZSKIP) => {
+                if let Some(file) = self.file() {
+                    out.log(format!("Receiver skipped {}", file.name));
+                }
+                self.file_index += 1;
+                self.retries = 0;
+                self.send_file_header(now, out);
+            }
+            (Awaiting::Position | Awaiting::Ack | Awaiting::EofAck, ZRPOS) => {
+                let position = header
+                    .position()
+                    .min(self.file().map_or(0, |f| f.data.len()));
+                // Part of the frame got through, only repeated requests for
+                // the same data count against the limit
+                if position > self.offset {
+                    self.retries = 0;
+                }
+                if self.state != Awaiting::Position
+                    && !self.retry(
+                        &format!("Receiver asked to resend from {}", position),
+                        now,
+                        out,
+                    )
+                {
+                    return;
+                }
+                self.offset = position;
+                self.send_frame(now, out);
+            }
+            (Awaiting::Ack, ZACK) => {
+                self.retries = 0;
+                self.offset = self.frame_end;
+                self.send_frame(now, out);
+            }
+            (Awaiting::EofAck, ZRINIT) => {
+                self.retries = 0;
+                if let Some(file) = self.file() {
+                    out.log(format!("Sent {}", file.name));
+                }
+                self.file_index += 1;
+                self.offset = 0;
+                self.frame_end = 0;
+                self.send_file_header(now, out);
+            }
+            (Awaiting::Fin, ZFIN) => {
+                out.send.extend_from_slice(b"OO");
+                out.finish();
+            }
+            (_, ZNAK) if self.retry("Receiver reported a damaged header", now, out) => {
+                let packet = self.last_packet.clone();
+                let state = self.state;
+                self.send(packet, state, now, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Transfer for ZmodemSender {
+    fn name(&self) -> String {
+        String::from("ZMODEM send")
+    }
+
+    fn start(&mut self, now: Instant, out: &mut Output) {
+        if self.announce {
+            out.send.extend_from_slice(b"rz\r");
+        }
+        out.log("Waiting for the receiver to start");
+        self.send(
+            hex_header(Header::new(ZRQINIT)),
+            Awaiting::Receiver,
+            now,
+            out,
+        );
+    }
+
+    fn receive(&mut self, data: &[u8], now: Instant, out: &mut Output) {
+        for &byte in data {
+            if out.finished.is_some() {
+                return;
+            }
+            if let Some(frame) = self.decoder.push(byte) {
+                self.handle(frame, now, out);
+            }
+        }
+    }
+
+    fn poll(&mut self, now: Instant, out: &mut Output) {
+        if now >= self.deadline && self.retry("Timed out waiting for the receiver", now, out) {
+            let packet = self.last_packet.clone();
+            let state = self.state;
+            self.send(packet, state, now, out);
+        }
+    }
+
+    fn cancel(&mut self, out: &mut Output) {
+        out.send.extend_from_slice(&ABORT);
+        out.fail("Cancelled");
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            file: self.file().map(|file| file.name.clone()),
+            bytes: self.frame_end,
+            total: self.file().map(|file| file.data.len()),
+            retries: self.total_retries,
+        }
+    }
+}
+
+/// Receives ZMODEM files into memory
+#[derive(Debug)]
+pub struct ZmodemReceiver {
+    decoder: Decoder,
+    /// Kind of the header whose data subpackets are arriving
+    data_for: Option<u8>,
+    file_name: Option<String>,
+    file_size: Option<usize>,
+    data: Vec<u8>,
+    /// Subpackets are dropped after an error until a ZDATA at the right position
+    discarding: bool,
+    last_packet: Vec<u8>,
+    retries: u32,
+    total_retries: u32,
+    deadline: Instant,
+}
+
+impl ZmodemReceiver {
+    pub fn new() -> Self {
+        Self {
+            decoder: Decoder::new(),
+            data_for: None,
+            file_name: None,
+            file_size: None,
+            data: Vec::new(),
+            discarding: false,
+            last_packet: Vec::new(),
+            retries: 0,
+            total_retries: 0,
+            deadline: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, packet: Vec<u8>, now: Instant, out: &mut Output) {
+        out.send.extend_from_slice(&packet);
+        self.last_packet = packet;
+        self.deadline = now + TRANSFER_TIMEOUT;
+    }
+
+    fn send_init(&mut self, now: Instant, out: &mut Output) {
+        let init = Header::with_flags(ZRINIT, CANFDX | CANOVIO | CANFC32);
+        self.send(hex_header(init), now, out);
+    }
+
+    /// Asks the sender to continue from what was received so far
+    fn resend_from_here(&mut self, reason: &str, now: Instant, out: &mut Output) {
+        self.retries += 1;
+        self.total_retries += 1;
+        if self.retries > TRANSFER_MAX_RETRIES {
+            out.send.extend_from_slice(&ABORT);
+            out.fail(format!(
+                "{}, giving up after {} retries",
+                reason, TRANSFER_MAX_RETRIES
+            ));
+            return;
+        }
+        out.log(format!(
+            "{}, retry {}/{}",
+            reason, self.retries, TRANSFER_MAX_RETRIES
+        ));
+        self.discarding = true;
+        let position = Header::with_position(ZRPOS, self.data.len());
+        self.send(hex_header(position), now, out);
+    }
+
+    fn handle(&mut self, frame: Frame, now: Instant, out: &mut Output) {
+        match frame {
+            Frame::Cancelled => out.fail("Cancelled by sender"),
+            Frame::Damaged => {
+                if self.file_name.is_some() {
+                    self.resend_from_here("Damaged data", now, out);
+                } else {
+                    let header = Header::new(ZNAK);
+                    out.send.extend_from_slice(&hex_header(header));
+                }
+            }
+            Frame::Header(header) => self.header(header, now, out),
+            Frame::Data { data, end } => self.data(data, end, now, out),
+        }
+    }
+
+    fn header(&mut self, header: Header, now: Instant, out: &mut Output) {
+        if header.has_data() {
+            self.data_for = Some(header.kind);
+        }
+        match header.kind {
+            ZRQINIT => self.send_init(now, out),
+            ZDATA => {
+                if header.position() == self.data.len() {
+                    self.discarding = false;
+                } else if !self.discarding {
+                    self.resend_from_here("Data out of order", now, out);
+                }
+            }
+            ZEOF if self.file_name.is_some() && header.position() == self.data.len() => {
+                let name = self.file_name.take().unwrap_or_default();
+                let data = std::mem::take(&mut self.data);
+                out.log(format!("Received {} ({} bytes)", name, data.len()));
+                out.received.push(TransferFile { name, data });
+                self.file_size = None;
+                self.retries = 0;
+                self.send_init(now, out);
+            }
+            ZFIN => {
+                out.send.extend_from_slice(&hex_header(Header::new(ZFIN)));
+                out.finish();
+            }
+            ZCAN | ZABORT => out.fail("Aborted by sender"),
+            _ => {}
+        }
+    }
+
+    fn data(&mut self, data: Vec<u8>, end: u8, now: Instant, out: &mut Output) {
+        match self.data_for {
+            Some(ZFILE) => {
+                let (name, size) = parse_file_info(&data);
+                match size {
+                    Some(size) => out.log(format!("Receiving {} ({} bytes)", name, size)),
+                    None => out.log(format!("Receiving {}", name)),
+                }
+                self.file_name = Some(name);
+                self.file_size = size;
+                self.data.clear();
+                self.discarding = false;
+                self.retries = 0;
+                self.send(hex_header(Header::with_position(ZRPOS, 0)), now, out);
+            }
+            Some(ZDATA) if !self.discarding => {
+                self.data.extend_from_slice(&data);
+                self.retries = 0;
+                self.deadline = now + TRANSFER_TIMEOUT;
+                if end == ZCRCQ || end == ZCRCW {
+                    let ack = Header::with_position(ZACK, self.data.len());
+                    out.send.extend_from_slice(&hex_header(ack));
+                }
+            }
+            Some(ZSINIT) | Some(ZCOMMAND) => {
+                // Nothing is configured or run on request, it is only acknowledged
+                out.send.extend_from_slice(&hex_header(Header::new(ZACK)));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Transfer for ZmodemReceiver {
+    fn name(&self) -> String {
+        String::from("ZMODEM receive")
+    }
+
+    fn start(&mut self, now: Instant, out: &mut Output) {
+        out.log("Waiting for the sender");
+        self.send_init(now, out);
+    }
+
+    fn receive(&mut self, data: &[u8], now: Instant, out: &mut Output) {
+        for &byte in data {
+            if out.finished.is_some() {
+                return;
+            }
+            if let Some(frame) = self.decoder.push(byte) {
+                self.handle(frame, now, out);
+            }
+        }
+    }
+
+    fn poll(&mut self, now: Instant, out: &mut Output) {
+        if now < self.deadline {
+            return;
+        }
+        self.retries += 1;
+        self.total_retries += 1;
+        if self.retries > TRANSFER_MAX_RETRIES {
+            out.send.extend_from_slice(&ABORT);
+            out.fail(format!(
+                "Sender stopped responding, giving up after {} retries",
+                TRANSFER_MAX_RETRIES
+            ));
+            return;
+        }
+        out.log(format!(
+            "Timed out waiting for the sender, retry {}/{}",
+            self.retries, TRANSFER_MAX_RETRIES
+        ));
+        let packet = self.last_packet.clone();
+        self.send(packet, now, out);
+    }
+
+    fn cancel(&mut self, out: &mut Output) {
+        out.send.extend_from_slice(&ABORT);
+        out.fail("Cancelled");
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            file: self.file_name.clone(),
+            bytes: self.data.len(),
+            total: self.file_size,
+            retries: self.total_retries,
+        }
+    }
+}
+
+/// A remote ZMODEM program announcing itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutoStart {
+    /// `sz` sent ZRQINIT, it has files for us
+    Download,
+    /// `rz` sent ZRINIT, it waits for files
+    Upload,
+}
+
+/// Watches received data for the hex headers `sz` and `rz` open with
+#[derive(Debug, Default)]
+pub struct AutoStartDetector {
+    /// End of the previous chunk, in case a header is split across reads
+    tail: Vec<u8>,
+}
+
+impl AutoStartDetector {
+    const PREFIX: &'static [u8] = b"**\x18B0";
+
+    /// Finds a ZRQINIT or ZRINIT header, returning it with where it starts
+    /// in `data`, 0 when it began in an earlier chunk
+    pub fn detect(&mut self, data: &[u8]) -> Option<(AutoStart, usize)> {
+        let mut window = std::mem::take(&mut self.tail);
+        let carried = window.len();
+        window.extend_from_slice(data);
+
+        let len = Self::PREFIX.len() + 1;
+        let found = window.windows(len).enumerate().find_map(|(index, bytes)| {
+            if &bytes[..len - 1] != Self::PREFIX {
+                return None;
+            }
+            match bytes[len - 1] {
+                b'0' => Some((AutoStart::Download, index)),
+                b'1' => Some((AutoStart::Upload, index)),
+                _ => None,
+            }
+        });
+
+        match found {
+            Some((request, index)) => Some((request, index.saturating_sub(carried))),
+            None => {
+                self.tail = window[window.len().saturating_sub(len - 1)..].to_vec();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::tests::{assert_received, file, loopback};
+    use crate::file_transfer::Protocol;
+
+    #[test]
+    fn loopback_delivers_a_batch() {
+        let files = vec![file("a.bin", 3000), file("b.bin", 20000), file("empty", 0)];
+        let result = loopback(Protocol::Zmodem, files.clone(), None);
+        assert_received(Protocol::Zmodem, &result.received, &files);
+        assert_eq!(result.receiver.retries, 0);
+    }
+
+    #[test]
+    fn damaged_subpacket_is_resent_from_the_reported_position() {
+        let files = vec![file("a.bin", 20000)];
+        let result = loopback(Protocol::Zmodem, files.clone(), Some(5000));
+        assert_received(Protocol::Zmodem, &result.received, &files);
+        // The receiver answered the bad CRC with a ZRPOS
+        assert!(result.receiver.retries > 0);
+    }
+
+    #[test]
+    fn auto_start_header_split_across_reads() {
+        let mut detector = AutoStartDetector::default();
+        assert_eq!(detector.detect(b"sz x\r\n**\x18B0"), None);
+        assert_eq!(
+            detector.detect(b"0000000000000\r\n"),
+            Some((AutoStart::Download, 0))
+        );
+
+        let mut detector = AutoStartDetector::default();
+        assert_eq!(detector.detect(b"rz\r**"), None);
+        assert_eq!(
+            detector.detect(b"\x18B0100000023be50\r\n"),
+            Some((AutoStart::Upload, 0))
+        );
+    }
+
+    #[test]
+    fn auto_start_reports_where_the_header_begins() {
+        let mut detector = AutoStartDetector::default();
+        assert_eq!(
+            detector.detect(b"abc**\x18B0100000023be50\r\n"),
+            Some((AutoStart::Upload, 3))
+        );
+        assert_eq!(AutoStartDetector::default().detect(b"**\x18B09"), None);
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Result};
+
+/// Bytes per hex dump line
+const DUMP_WIDTH: usize = 16;
+
+/// Parses hex input such as `AA 55 01 00 FE`, `0xAA,0x55` or `AA5501`,
+/// bytes may be separated by whitespace, commas or colons
+pub fn parse(input: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for token in input
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+        .filter(|token| !token.is_empty())
+    {
+        let digits = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+
+        if let Some(bad) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(anyhow!("'{}' is not a hex digit in '{}'", bad, token));
+        }
+        if digits.is_empty() {
+            return Err(anyhow!("'{}' has no hex digits", token));
+        }
+        if digits.len() % 2 != 0 {
+            return Err(anyhow!("'{}' has an odd number of hex digits", token));
+        }
+
+        for pair in digits.as_bytes().chunks(2) {
+            // Only ASCII hex digits remain, so the pair is valid UTF-8
+            let pair = std::str::from_utf8(pair)?;
+            bytes.push(u8::from_str_radix(pair, 16)?);
+        }
+    }
+
+    if bytes.is_empty() {
+        return Err(anyhow!("No hex bytes entered"));
+    }
+
+    Ok(bytes)
+}
+
+/// Formats bytes as space separated uppercase hex pairs
+pub fn format(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Renders `data` as hex dump lines of 16 bytes: offset, hex bytes and an
+/// ASCII gutter, `offset` being the position of the first byte
+pub fn dump(offset: usize, data: &[u8]) -> Vec<String> {
+    data.chunks(DUMP_WIDTH)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let hex_bytes = format(chunk);
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08X}  {:<width$}  |{}|",
+                offset + index * DUMP_WIDTH,
+                hex_bytes,
+                ascii,
+                width = DUMP_WIDTH * 3 - 1
+            )
+        })
+        .collect()
+}
+
+/// Shows printable ASCII as text and every other byte as `<XX>`
+pub fn escape_non_printable(data: &[u8]) -> String {
+    data.iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                (byte as char).to_string()
+            } else {
+                format!("<{:02X}>", byte)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_accepted_separator() {
+        let expected = vec![0xAA, 0x55, 0x01, 0x00, 0xFE];
+        assert_eq!(parse("AA 55 01 00 FE").unwrap(), expected);
+        assert_eq!(parse("0xAA,0x55,0X01, 00:fe").unwrap(), expected);
+        assert_eq!(parse("AA5501\t00FE\n").unwrap(), expected);
+    }
+
+    #[test]
+    fn format_round_trips() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(parse(&format(&data)).unwrap(), data);
+        assert_eq!(format(&[0x0A, 0xFF]), "0A FF");
+    }
+
+    #[test]
+    fn dump_lines_have_offset_hex_and_ascii() {
+        let data: Vec<u8> = (0x3C..0x3C + 20).chain([0x00, 0x7F]).collect();
+        let lines = dump(0x100, &data);
+        assert_eq!(
+            lines,
+            [
+                "00000100  3C 3D 3E 3F 40 41 42 43 44 45 46 47 48 49 4A 4B  |<=>?@ABCDEFGHIJK|",
+                "00000110  4C 4D 4E 4F 00 7F                                |LMNO..|",
+            ]
+        );
+        assert!(dump(0, &[]).is_empty());
+    }
+
+    #[test]
+    fn escapes_only_non_printable_bytes() {
+        assert_eq!(escape_non_printable(b"ok \r\n\x1b~"), "ok <0D><0A><1B>~");
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        for input in ["", " , ", "GG", "0x", "ABC", "AA 5", "12 zz"] {
+            assert!(parse(input).is_err(), "{:?} accepted", input);
+        }
+        assert_eq!(
+            parse("AA 5").unwrap_err().to_string(),
+            "'5' has an odd number of hex digits"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use crate::config::{HISTORY_FILE, HISTORY_LIMIT};
+use crate::util::{load_json, save_json};
+
+/// Commands submitted in a session's input line, oldest first. New sessions
+/// start from the saved file, the last session to submit a command decides
+/// what it holds
+#[derive(Debug)]
+pub struct History {
+    entries: Vec<String>,
+    /// Entry shown while browsing with Up and Down, `entries.len()` when back
+    /// at what was being typed
+    position: usize,
+    /// Input as it was before browsing started
+    draft: String,
+    /// The saved file could not be read, saving would overwrite it
+    load_failed: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let (entries, load_failed) = match load_json(Path::new(HISTORY_FILE)) {
+            Ok(entries) => (entries, false),
+            Err(e) => {
+                println!("Cannot load command history: {:?}", e);
+                (Vec::new(), true)
+            }
+        };
+        Self {
+            position: entries.len(),
+            entries,
+            draft: String::new(),
+            load_failed,
+        }
+    }
+}
+
+impl History {
+    /// Remembers a submitted command, moving an earlier copy of it to the end
+    pub fn push(&mut self, command: &str) {
+        if !command.trim().is_empty() {
+            add(&mut self.entries, command);
+            if !self.load_failed {
+                if let Err(e) = save_json(Path::new(HISTORY_FILE), &self.entries) {
+                    println!("Cannot save command history: {:?}", e);
+                }
+            }
+        }
+        self.stop_browsing();
+    }
+
+    /// Older entry for the Up key, `input` is kept to come back to
+    pub fn previous(&mut self, input: &str) -> Option<String> {
+        if self.position == 0 {
+            return None;
+        }
+        if self.position == self.entries.len() {
+            self.draft = input.to_string();
+        }
+        self.position -= 1;
+        Some(self.entries[self.position].clone())
+    }
+
+    /// Newer entry for the Down key, ending with the input from before browsing
+    pub fn next(&mut self) -> Option<String> {
+        if self.position >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        match self.entries.get(self.position) {
+            Some(entry) => Some(entry.clone()),
+            None => Some(self.draft.clone()),
+        }
+    }
+
+    /// Called when the input is edited, Up starts again from the newest entry
+    pub fn stop_browsing(&mut self) {
+        self.position = self.entries.len();
+    }
+
+    /// Index of the newest entry containing `query`, only looking at entries
+    /// older than `before` when given
+    pub fn find(&self, query: &str, before: Option<usize>) -> Option<usize> {
+        let before = before.unwrap_or(self.entries.len()).min(self.entries.len());
+        self.entries[..before]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+}
+
+fn add(entries: &mut Vec<String>, command: &str) {
+    entries.retain(|entry| entry != command);
+    entries.push(command.to_string());
+    if entries.len() > HISTORY_LIMIT {
+        entries.drain(..entries.len() - HISTORY_LIMIT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> History {
+        History {
+            entries: entries.iter().map(|entry| entry.to_string()).collect(),
+            position: entries.len(),
+            draft: String::new(),
+            // Keeps the tests from writing the user's history file
+            load_failed: true,
+        }
+    }
+
+    #[test]
+    fn add_moves_a_repeated_command_to_the_end() {
+        let mut entries = vec![String::from("a"), String::from("b")];
+        add(&mut entries, "a");
+        assert_eq!(entries, ["b", "a"]);
+    }
+
+    #[test]
+    fn add_drops_the_oldest_past_the_limit() {
+        let mut entries = Vec::new();
+        for number in 0..HISTORY_LIMIT + 2 {
+            add(&mut entries, &number.to_string());
+        }
+        assert_eq!(entries.len(), HISTORY_LIMIT);
+        assert_eq!(entries[0], "2");
+        assert_eq!(entries[HISTORY_LIMIT - 1], (HISTORY_LIMIT + 1).to_string());
+    }
+
+    #[test]
+    fn push_ignores_blank_commands() {
+        let mut history = history(&["a"]);
+        history.push("  ");
+        history.push("b");
+        assert_eq!(history.entries, ["a", "b"]);
+    }
+
+    #[test]
+    fn up_and_down_stop_at_both_ends() {
+        let mut history = history(&["old", "new"]);
+        assert_eq!(history.next(), None);
+
+        assert_eq!(history.previous("typed").as_deref(), Some("new"));
+        assert_eq!(history.previous("new").as_deref(), Some("old"));
+        assert_eq!(history.previous("old"), None);
+
+        assert_eq!(history.next().as_deref(), Some("new"));
+        assert_eq!(history.next().as_deref(), Some("typed"));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn up_on_an_empty_history_keeps_the_input() {
+        let mut history = history(&[]);
+        assert_eq!(history.previous("typed"), None);
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn find_steps_back_from_the_newest_match() {
+        let history = history(&["ls", "cat a", "cat b"]);
+        assert_eq!(history.find("cat", None), Some(2));
+        assert_eq!(history.find("cat", Some(2)), Some(1));
+        assert_eq!(history.find("cat", Some(1)), None);
+    }
+}
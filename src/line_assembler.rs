@@ -0,0 +1,233 @@
+use std::time::{Duration, SystemTime};
+
+use crate::line_ending::{NewlineNormalizer, RxNewline};
+
+/// How received reads are grouped before they reach the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReceiveMode {
+    /// Buffer partial reads until a newline or the idle timeout
+    #[default]
+    Lines,
+    /// Show every read as it arrived
+    Chunks,
+}
+
+impl ReceiveMode {
+    pub const ALL: [ReceiveMode; 2] = [ReceiveMode::Lines, ReceiveMode::Chunks];
+}
+
+impl std::fmt::Display for ReceiveMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReceiveMode::Lines => write!(f, "Assemble lines"),
+            ReceiveMode::Chunks => write!(f, "Raw chunks"),
+        }
+    }
+}
+
+/// A line or chunk ready for display
+#[derive(Debug)]
+pub struct ReceivedData {
+    /// Bytes as read from the port
+    pub raw: Vec<u8>,
+    /// Bytes with newlines normalized to `\n`, without the final one for lines
+    pub text: Vec<u8>,
+    /// Read time of the first byte
+    pub time: SystemTime,
+}
+
+/// Joins partial reads into complete lines using the RX newline setting
+#[derive(Debug)]
+pub struct LineAssembler {
+    mode: ReceiveMode,
+    normalizer: NewlineNormalizer,
+    idle_timeout: Duration,
+    raw: Vec<u8>,
+    text: Vec<u8>,
+    started_at: Option<SystemTime>,
+    last_data_at: Option<SystemTime>,
+}
+
+impl LineAssembler {
+    pub fn new(mode: ReceiveMode, newline: RxNewline, idle_timeout: Duration) -> Self {
+        Self {
+            mode,
+            normalizer: NewlineNormalizer::new(newline),
+            idle_timeout,
+            raw: Vec::new(),
+            text: Vec::new(),
+            started_at: None,
+            last_data_at: None,
+        }
+    }
+
+    pub fn set_newline(&mut self, newline: RxNewline) {
+        self.normalizer.set_mode(newline);
+    }
+
+    /// Changes the mode, returning the partial line so it is not lost
+    pub fn set_mode(&mut self, mode: ReceiveMode) -> Option<ReceivedData> {
+        self.mode = mode;
+        self.flush()
+    }
+
+    /// Feeds bytes read at `time`, returns the lines or chunk now complete
+    pub fn push(&mut self, data: &[u8], time: SystemTime) -> Vec<ReceivedData> {
+        if self.mode == ReceiveMode::Chunks {
+            return vec![ReceivedData {
+                raw: data.to_vec(),
+                text: self.normalizer.normalize(data),
+                time,
+            }];
+        }
+
+        let mut complete = Vec::new();
+        for &byte in data {
+            let mut normalized = self.normalizer.normalize(&[byte]);
+            // A held back CR comes out as a newline ahead of the byte after it
+            if normalized.len() > 1 && normalized[0] == b'\n' {
+                complete.extend(self.take());
+                normalized.remove(0);
+            }
+
+            if self.raw.is_empty() {
+                self.started_at = Some(time);
+            }
+            self.raw.push(byte);
+            if normalized == b"\n" {
+                complete.extend(self.take());
+            } else {
+                self.text.extend(normalized);
+            }
+        }
+        self.last_data_at = Some(time);
+
+        complete
+    }
+
+    /// Whether a partial line is waiting for more data, including a CR held
+    /// back to see whether an LF follows
+    pub fn has_partial_line(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Returns the partial line once no data arrived for the idle timeout
+    pub fn flush_idle(&mut self, now: SystemTime) -> Option<ReceivedData> {
+        let last_data_at = self.last_data_at?;
+        let idle = now.duration_since(last_data_at).unwrap_or_default();
+        if self.has_partial_line() && idle >= self.idle_timeout {
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the partial line regardless of the timeout
+    pub fn flush(&mut self) -> Option<ReceivedData> {
+        if self.has_partial_line() {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    fn take(&mut self) -> Option<ReceivedData> {
+        let time = self.started_at.take()?;
+        Some(ReceivedData {
+            raw: std::mem::take(&mut self.raw),
+            text: std::mem::take(&mut self.text),
+            time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[ReceivedData]) -> Vec<&[u8]> {
+        lines.iter().map(|line| line.text.as_slice()).collect()
+    }
+
+    #[test]
+    fn held_cr_starts_a_blank_line_before_the_next_text() {
+        let mut assembler =
+            LineAssembler::new(ReceiveMode::Lines, RxNewline::Auto, Duration::from_secs(1));
+        let now = SystemTime::now();
+
+        let lines = assembler.push(b"a\r\rb\r\r\nc\n", now);
+        assert_eq!(texts(&lines), [&b"a"[..], b"", b"b", b"c"]);
+        assert_eq!(lines[1].raw, b"\r");
+        assert_eq!(lines[2].raw, b"b\r");
+        assert!(!assembler.has_partial_line());
+    }
+
+    #[test]
+    fn joins_a_line_split_across_reads() {
+        let mut assembler =
+            LineAssembler::new(ReceiveMode::Lines, RxNewline::CrLf, Duration::from_secs(1));
+        let first = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let second = first + Duration::from_millis(10);
+
+        assert!(assembler.push(b"hel", first).is_empty());
+        assert!(assembler.push(b"lo\r", second).is_empty());
+        let lines = assembler.push(b"\nwor", second);
+        assert_eq!(texts(&lines), [&b"hello"[..]]);
+        assert_eq!(lines[0].raw, b"hello\r\n");
+        // Stamped with the read the line started in
+        assert_eq!(lines[0].time, first);
+        assert!(assembler.has_partial_line());
+    }
+
+    #[test]
+    fn partial_line_is_flushed_after_the_idle_timeout() {
+        let timeout = Duration::from_millis(200);
+        let mut assembler = LineAssembler::new(ReceiveMode::Lines, RxNewline::Lf, timeout);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        assert!(assembler.flush_idle(start).is_none());
+
+        assembler.push(b"prompt> ", start);
+        assert!(assembler
+            .flush_idle(start + timeout - Duration::from_millis(1))
+            .is_none());
+        let line = assembler.flush_idle(start + timeout).unwrap();
+        assert_eq!(line.text, b"prompt> ");
+        assert_eq!(line.time, start);
+        assert!(!assembler.has_partial_line());
+        assert!(assembler.flush_idle(start + timeout * 2).is_none());
+    }
+
+    #[test]
+    fn chunk_mode_passes_reads_through() {
+        let mut assembler =
+            LineAssembler::new(ReceiveMode::Lines, RxNewline::Lf, Duration::from_secs(1));
+        let now = SystemTime::now();
+        assembler.push(b"part", now);
+
+        // The partial line is handed back rather than lost
+        let partial = assembler.set_mode(ReceiveMode::Chunks).unwrap();
+        assert_eq!(partial.text, b"part");
+        let chunks = assembler.push(b"a\nb", now);
+        assert_eq!(texts(&chunks), [&b"a\nb"[..]]);
+    }
+
+    #[test]
+    fn lone_cr_is_flushed_after_the_idle_timeout() {
+        let timeout = Duration::from_millis(200);
+        let mut assembler = LineAssembler::new(ReceiveMode::Lines, RxNewline::CrLf, timeout);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+
+        assert!(assembler.push(b"\r", start).is_empty());
+        assert!(assembler.has_partial_line());
+        let held = assembler.flush_idle(start + timeout).unwrap();
+        assert_eq!(held.raw, b"\r");
+        assert!(held.text.is_empty());
+
+        // The next line neither carries the CR nor its time
+        let later = start + Duration::from_secs(5);
+        let lines = assembler.push(b"next\r\n", later);
+        assert_eq!(texts(&lines), [&b"next"[..]]);
+        assert_eq!(lines[0].raw, b"next\r\n");
+        assert_eq!(lines[0].time, later);
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+
+/// Terminator appended to each line of submitted input
+#[derive(Clone, Serialize, Deserialize, Debug, Copy, PartialEq)]
+pub enum TxLineEnding {
+    None,
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl TxLineEnding {
+    pub const ALL: [TxLineEnding; 4] = [
+        TxLineEnding::None,
+        TxLineEnding::Cr,
+        TxLineEnding::Lf,
+        TxLineEnding::CrLf,
+    ];
+
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            TxLineEnding::None => b"",
+            TxLineEnding::Cr => b"\r",
+            TxLineEnding::Lf => b"\n",
+            TxLineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
+impl std::fmt::Display for TxLineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TxLineEnding::None => write!(f, "Send: no line ending"),
+            TxLineEnding::Cr => write!(f, "Send: CR"),
+            TxLineEnding::Lf => write!(f, "Send: LF"),
+            TxLineEnding::CrLf => write!(f, "Send: CR+LF"),
+        }
+    }
+}
+
+/// Sequence that ends a line in received data
+#[derive(Clone, Serialize, Deserialize, Debug, Copy, PartialEq)]
+pub enum RxNewline {
+    Cr,
+    Lf,
+    CrLf,
+    /// Any of CR, LF or CR+LF
+    Auto,
+}
+
+impl RxNewline {
+    pub const ALL: [RxNewline; 4] = [
+        RxNewline::Cr,
+        RxNewline::Lf,
+        RxNewline::CrLf,
+        RxNewline::Auto,
+    ];
+}
+
+impl std::fmt::Display for RxNewline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RxNewline::Cr => write!(f, "Receive: CR"),
+            RxNewline::Lf => write!(f, "Receive: LF"),
+            RxNewline::CrLf => write!(f, "Receive: CR+LF"),
+            RxNewline::Auto => write!(f, "Receive: auto"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Copy, PartialEq)]
+pub struct LineEndings {
+    pub tx: TxLineEnding,
+    pub rx: RxNewline,
+}
+
+impl Default for LineEndings {
+    fn default() -> Self {
+        Self {
+            tx: TxLineEnding::Lf,
+            rx: RxNewline::Auto,
+        }
+    }
+}
+
+/// Carriage returns just before the data normalized so far
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PendingCr {
+    None,
+    One,
+    /// A second CR after one that already ended a line, held back until the
+    /// next byte shows whether it starts a CR+LF
+    Two,
+}
+
+/// Rewrites the received newline sequence to a plain `\n`, dropping stray
+/// CR and LF bytes that do not form one. Keeps state so a CR+LF split across
+/// two reads still counts as a single newline
+#[derive(Debug)]
+pub struct NewlineNormalizer {
+    mode: RxNewline,
+    pending: PendingCr,
+}
+
+impl NewlineNormalizer {
+    pub fn new(mode: RxNewline) -> Self {
+        Self {
+            mode,
+            pending: PendingCr::None,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: RxNewline) {
+        self.mode = mode;
+        self.pending = PendingCr::None;
+    }
+
+    pub fn normalize(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len());
+
+        for &byte in data {
+            match (self.mode, byte) {
+                (RxNewline::Cr, CR) | (RxNewline::Lf, LF) => output.push(LF),
+                (RxNewline::Cr, LF) | (RxNewline::Lf, CR) => {}
+                (RxNewline::CrLf, CR) => self.pending = PendingCr::One,
+                (RxNewline::CrLf, LF) => {
+                    if std::mem::replace(&mut self.pending, PendingCr::None) != PendingCr::None {
+                        output.push(LF);
+                    }
+                }
+                (RxNewline::Auto, CR) => {
+                    // CR CR LF, as sent through a tty adding CR to CR+LF, is
+                    // one line break, so only a lone first CR ends a line at once
+                    self.pending = match self.pending {
+                        PendingCr::None => {
+                            output.push(LF);
+                            PendingCr::One
+                        }
+                        PendingCr::One => PendingCr::Two,
+                        PendingCr::Two => {
+                            output.push(LF);
+                            PendingCr::Two
+                        }
+                    };
+                }
+                (RxNewline::Auto, LF) => {
+                    // The LF of a CR+LF pair was already counted with its CR
+                    if std::mem::replace(&mut self.pending, PendingCr::None) == PendingCr::None {
+                        output.push(LF);
+                    }
+                }
+                (_, other) => {
+                    // A held CR not followed by LF was a blank line after all
+                    if std::mem::replace(&mut self.pending, PendingCr::None) == PendingCr::Two {
+                        output.push(LF);
+                    }
+                    output.push(other);
+                }
+            }
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(mode: RxNewline, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut normalizer = NewlineNormalizer::new(mode);
+        chunks
+            .iter()
+            .flat_map(|chunk| normalizer.normalize(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn auto_accepts_every_newline_style() {
+        assert_eq!(
+            normalize(RxNewline::Auto, &[b"a\rb\nc\r\nd"]),
+            b"a\nb\nc\nd"
+        );
+    }
+
+    #[test]
+    fn auto_counts_cr_cr_lf_once() {
+        assert_eq!(normalize(RxNewline::Auto, &[b"a\r\r\nb"]), b"a\nb");
+        assert_eq!(
+            normalize(RxNewline::Auto, &[b"a\r", b"\r", b"\nb"]),
+            b"a\nb"
+        );
+    }
+
+    #[test]
+    fn auto_keeps_blank_lines_from_cr() {
+        assert_eq!(normalize(RxNewline::Auto, &[b"a\r\rb"]), b"a\n\nb");
+        assert_eq!(normalize(RxNewline::Auto, &[b"a\r\r\rb"]), b"a\n\n\nb");
+        assert_eq!(normalize(RxNewline::Auto, &[b"a\r\n\r\nb"]), b"a\n\nb");
+    }
+
+    #[test]
+    fn crlf_split_across_reads() {
+        assert_eq!(normalize(RxNewline::CrLf, &[b"a\r", b"\nb\nc\r"]), b"a\nbc");
+    }
+
+    #[test]
+    fn single_byte_modes_drop_the_other_byte() {
+        assert_eq!(normalize(RxNewline::Cr, &[b"a\r\nb"]), b"a\nb");
+        assert_eq!(normalize(RxNewline::Lf, &[b"a\r\nb\r"]), b"a\nb");
+    }
+}
@@ -0,0 +1,357 @@
+use std::path::Path;
+
+use anyhow::Result;
+use iced::widget::{button, column, container, pick_list, row, text, text_input};
+use iced::{Alignment, Element};
+use serde::{Deserialize, Serialize};
+
+use crate::config::MACROS_FILE;
+use crate::encoding::Encoding;
+use crate::hex;
+use crate::line_ending::TxLineEnding;
+use crate::util::{load_json, save_json};
+use crate::Message;
+
+/// How a macro's payload is written
+#[derive(Clone, Serialize, Deserialize, Debug, Copy, PartialEq, Default)]
+pub enum PayloadKind {
+    /// Encoded like typed input
+    #[default]
+    Text,
+    /// Bytes such as `AA 55 01`
+    Hex,
+}
+
+impl PayloadKind {
+    pub const ALL: [PayloadKind; 2] = [PayloadKind::Text, PayloadKind::Hex];
+}
+
+impl std::fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadKind::Text => write!(f, "Text"),
+            PayloadKind::Hex => write!(f, "Hex"),
+        }
+    }
+}
+
+/// Function key a macro is sent with
+#[derive(Clone, Serialize, Deserialize, Debug, Copy, PartialEq, Default)]
+pub enum Shortcut {
+    #[default]
+    None,
+    F(u8),
+}
+
+impl Shortcut {
+    pub const ALL: [Shortcut; 13] = [
+        Shortcut::None,
+        Shortcut::F(1),
+        Shortcut::F(2),
+        Shortcut::F(3),
+        Shortcut::F(4),
+        Shortcut::F(5),
+        Shortcut::F(6),
+        Shortcut::F(7),
+        Shortcut::F(8),
+        Shortcut::F(9),
+        Shortcut::F(10),
+        Shortcut::F(11),
+        Shortcut::F(12),
+    ];
+}
+
+impl std::fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shortcut::None => write!(f, "No key"),
+            Shortcut::F(number) => write!(f, "F{}", number),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub payload: String,
+    pub kind: PayloadKind,
+    pub line_ending: TxLineEnding,
+    #[serde(default)]
+    pub shortcut: Shortcut,
+}
+
+impl Default for Macro {
+    fn default() -> Self {
+        Self {
+            name: String::from("New macro"),
+            payload: String::new(),
+            kind: PayloadKind::default(),
+            line_ending: TxLineEnding::CrLf,
+            shortcut: Shortcut::default(),
+        }
+    }
+}
+
+impl Macro {
+    /// Bytes to send, including the line ending, and how they are shown in
+    /// the terminal
+    pub fn bytes(&self, encoding: Encoding) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (mut raw, text) = match self.kind {
+            PayloadKind::Text => {
+                let bytes = encoding.encode(&self.payload)?;
+                (bytes.clone(), bytes)
+            }
+            PayloadKind::Hex => {
+                let bytes = hex::parse(&self.payload)?;
+                let text = hex::format(&bytes).into_bytes();
+                (bytes, text)
+            }
+        };
+        raw.extend_from_slice(self.line_ending.as_bytes());
+        Ok((raw, text))
+    }
+}
+
+/// Column of quick-send buttons next to the theme picker
+pub struct MacroPanel {
+    macros: Vec<Macro>,
+    /// Macro whose settings are shown for editing
+    editing: Option<usize>,
+    error: Option<String>,
+    /// The saved file could not be read, saving would overwrite it
+    load_failed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum MacroMessage {
+    /// Sends the macro with this index
+    Pressed(usize),
+    AddPressed,
+    EditPressed(usize),
+    EditDone,
+    RemovePressed(usize),
+    Changed(usize, Macro),
+}
+
+impl Default for MacroPanel {
+    fn default() -> Self {
+        let (macros, error) = match load_json(Path::new(MACROS_FILE)) {
+            Ok(macros) => (macros, None),
+            Err(e) => {
+                println!("Cannot load macros: {:?}", e);
+                (Vec::new(), Some(format!("{:#}, changes are not saved", e)))
+            }
+        };
+        Self {
+            macros,
+            editing: None,
+            load_failed: error.is_some(),
+            error,
+        }
+    }
+}
+
+impl MacroPanel {
+    pub fn update(&mut self, message: MacroMessage) {
+        match message {
+            MacroMessage::Pressed(index) => {
+                println!("Macro {} pressed", index);
+                return;
+            }
+            MacroMessage::AddPressed => {
+                self.macros.push(Macro::default());
+                self.editing = Some(self.macros.len() - 1);
+            }
+            MacroMessage::EditPressed(index) => {
+                // Switching macros keeps the edits made to the previous one
+                if self.editing.replace(index).is_none() {
+                    return;
+                }
+            }
+            MacroMessage::EditDone => {
+                self.editing = None;
+            }
+            MacroMessage::RemovePressed(index) => {
+                if index < self.macros.len() {
+                    self.macros.remove(index);
+                }
+                self.editing = None;
+            }
+            MacroMessage::Changed(index, changed) => {
+                // Saved with Done rather than on every key typed
+                if let Some(existing) = self.macros.get_mut(index) {
+                    *existing = changed;
+                }
+                return;
+            }
+        }
+
+        if self.load_failed {
+            // Keep the unreadable file for the user to repair
+            return;
+        }
+        self.error = match save_json(Path::new(MACROS_FILE), &self.macros) {
+            Ok(()) => None,
+            Err(e) => {
+                println!("Cannot save macros: {:?}", e);
+                Some(format!("{:#}", e))
+            }
+        };
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Macro> {
+        self.macros.get(index)
+    }
+
+    /// Macro bound to function key `number`
+    pub fn for_function_key(&self, number: u8) -> Option<&Macro> {
+        self.macros
+            .iter()
+            .find(|m| m.shortcut == Shortcut::F(number))
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let mut macros = column![text("macros:")].spacing(5);
+
+        for (index, m) in self.macros.iter().enumerate() {
+            let label = match m.shortcut {
+                Shortcut::None => m.name.clone(),
+                shortcut => format!("{} ({})", m.name, shortcut),
+            };
+            macros = macros.push(
+                row![
+                    button(text(label))
+                        .on_press(Message::MacroMessage(MacroMessage::Pressed(index))),
+                    button("Edit")
+                        .style(button::secondary)
+                        .on_press(Message::MacroMessage(MacroMessage::EditPressed(index))),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center),
+            );
+
+            if self.editing == Some(index) {
+                macros = macros.push(self.editor(index, m));
+            }
+        }
+
+        macros = macros
+            .push(button("Add macro").on_press(Message::MacroMessage(MacroMessage::AddPressed)));
+        if let Some(error) = &self.error {
+            macros = macros.push(text(error.clone()).style(text::danger));
+        }
+
+        container(macros).into()
+    }
+
+    fn editor<'a>(&self, index: usize, m: &'a Macro) -> Element<'a, Message> {
+        let changed = move |m| Message::MacroMessage(MacroMessage::Changed(index, m));
+        let placeholder = match m.kind {
+            PayloadKind::Text => "Text to send",
+            PayloadKind::Hex => "AA 55 01",
+        };
+
+        column![
+            text_input("Name", &m.name).on_input(move |name| changed(Macro { name, ..m.clone() })),
+            text_input(placeholder, &m.payload).on_input(move |payload| changed(Macro {
+                payload,
+                ..m.clone()
+            })),
+            pick_list(PayloadKind::ALL, Some(m.kind), move |kind| changed(Macro {
+                kind,
+                ..m.clone()
+            })),
+            pick_list(TxLineEnding::ALL, Some(m.line_ending), move |line_ending| {
+                changed(Macro {
+                    line_ending,
+                    ..m.clone()
+                })
+            }),
+            pick_list(Shortcut::ALL, Some(m.shortcut), move |shortcut| {
+                changed(Macro {
+                    shortcut,
+                    ..m.clone()
+                })
+            }),
+            row![
+                button("Done").on_press(Message::MacroMessage(MacroMessage::EditDone)),
+                button("Remove")
+                    .style(button::danger)
+                    .on_press(Message::MacroMessage(MacroMessage::RemovePressed(index))),
+            ]
+            .spacing(5),
+        ]
+        .spacing(5)
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panel(macros: Vec<Macro>) -> MacroPanel {
+        MacroPanel {
+            macros,
+            editing: None,
+            error: None,
+            // Keeps the tests from writing the user's macros file
+            load_failed: true,
+        }
+    }
+
+    #[test]
+    fn macros_round_trip_with_their_function_key() {
+        let path = std::env::temp_dir().join(format!("macros_test_{}.json", std::process::id()));
+        let macros = vec![
+            Macro {
+                name: String::from("reset"),
+                payload: String::from("AA 55 01"),
+                kind: PayloadKind::Hex,
+                line_ending: TxLineEnding::None,
+                shortcut: Shortcut::F(5),
+            },
+            Macro::default(),
+        ];
+        save_json(&path, &macros).unwrap();
+        let loaded: Vec<Macro> = load_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, macros);
+    }
+
+    #[test]
+    fn macros_saved_before_shortcuts_have_no_key() {
+        let json = r#"{"name": "AT", "payload": "AT", "kind": "Text", "line_ending": "Cr"}"#;
+        let loaded: Macro = serde_json::from_str(json).unwrap();
+        assert_eq!(loaded.shortcut, Shortcut::None);
+    }
+
+    #[test]
+    fn for_function_key_finds_the_bound_macro() {
+        let bound = Macro {
+            name: String::from("status"),
+            shortcut: Shortcut::F(2),
+            ..Macro::default()
+        };
+        let panel = panel(vec![Macro::default(), bound.clone()]);
+        assert_eq!(panel.for_function_key(2), Some(&bound));
+        assert_eq!(panel.for_function_key(3), None);
+    }
+
+    #[test]
+    fn edits_apply_to_the_macro_being_edited() {
+        let mut panel = panel(vec![Macro::default()]);
+        panel.update(MacroMessage::EditPressed(0));
+        let changed = Macro {
+            payload: String::from("ATZ"),
+            ..Macro::default()
+        };
+        panel.update(MacroMessage::Changed(0, changed.clone()));
+        panel.update(MacroMessage::EditDone);
+        assert_eq!(panel.get(0), Some(&changed));
+        assert_eq!(panel.editing, None);
+
+        panel.update(MacroMessage::RemovePressed(0));
+        assert_eq!(panel.get(0), None);
+    }
+}
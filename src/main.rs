@@ -1,45 +1,78 @@
 mod config;
 mod controller;
+mod emulator;
+mod encoding;
+mod file_sender;
+mod file_transfer;
+mod hex;
+mod history;
+mod line_assembler;
+mod line_ending;
+mod macros;
 mod myserial;
+mod script;
+mod scrollback;
+mod search;
+mod sequence;
+mod session;
+mod session_log;
 mod sidebar;
 mod terminal;
 mod theme;
+mod timestamp;
+mod transport;
+mod util;
 
 use tracing_subscriber;
 
-use config::{window_settings, APP_SETTINGS, WINDOW_TITLE};
-use controller::TerminalController;
+use config::{
+    window_settings, APP_SETTINGS, LINE_IDLE_TIMEOUT, SEQUENCE_POLL_INTERVAL,
+    TRANSFER_POLL_INTERVAL, WINDOW_TITLE,
+};
+use controller::ConnectionEvent;
 use iced::application;
-use iced::widget::{container, row, Container, Rule};
-use iced::{Alignment, Element, Length, Subscription};
-use myserial::SerialPortInfo;
-use std::time::Duration;
+use iced::keyboard::{self, key, Key, Modifiers};
+use iced::widget::{button, column, container, row, text, Container, Rule};
+use iced::{Alignment, Element, Length, Subscription, Task};
+use myserial::{DataBits, FlowControl, Parity, SerialPortInfo, StopBits};
 
 struct State {
-    terminal: terminal::TerminalPane,
+    sessions: Vec<session::Session>,
+    active_session: usize,
+    next_session_id: u32,
     left_sidebar: sidebar::Sidebar,
+    macros: macros::MacroPanel,
+    sequences: sequence::SequencePanel,
     style: theme::theme::Style,
     available_ports: Vec<SerialPortInfo>,
-    selected_port: Option<SerialPortInfo>,
-    terminal_controller: Option<TerminalController>,
-    is_connected: bool,
 }
 
 impl Default for State {
     fn default() -> Self {
         let available_ports = controller::list_available_ports().unwrap_or_default();
         Self {
-            terminal: terminal::TerminalPane::default(),
+            sessions: vec![session::Session::new(1)],
+            active_session: 0,
+            next_session_id: 2,
             left_sidebar: sidebar::Sidebar::default(),
+            macros: macros::MacroPanel::default(),
+            sequences: sequence::SequencePanel::default(),
             style: theme::theme::Style::default(),
             available_ports,
-            selected_port: None,
-            terminal_controller: None,
-            is_connected: false,
         }
     }
 }
 
+impl State {
+    fn session(&self) -> &session::Session {
+        &self.sessions[self.active_session]
+    }
+
+    fn session_mut(&mut self) -> &mut session::Session {
+        &mut self.sessions[self.active_session]
+    }
+}
+
 #[derive(Default)]
 struct App {
     state: State,
@@ -50,181 +83,329 @@ enum Message {
     TerminalPaneMessage(terminal::TerminalPaneMessage),
     SidebarMessage(sidebar::SidebarMessage),
     StyleMessage(theme::theme::StyleMessage),
+    MacroMessage(macros::MacroMessage),
+    /// F1 to F12, sending the macro bound to the key
+    FunctionKeyPressed(u8),
     PortSelected(SerialPortInfo),
     ConnectToPort,
     DisconnectFromPort,
-    SendData(String),
+    SendData(Vec<u8>),
     RefreshPorts,
-    ReceivedData(String),
-    CheckForReceivedData,
+    ConnectionEvent(u32, ConnectionEvent),
+    NewSession,
+    SessionSelected(u32),
+    CloseSession(u32),
+    FlushIdleLines,
+    /// A file to send was read for the session with this id
+    FileLoaded(u32, String, Result<Vec<u8>, String>),
+    /// The delay after a file chunk passed for the session with this id
+    FileChunkDue(u32),
+    /// The files for a protocol transfer were read for the session with this
+    /// id, the flag is set when answering a remote `rz`
+    TransferFilesLoaded(
+        u32,
+        file_transfer::Protocol,
+        bool,
+        Result<Vec<file_transfer::TransferFile>, String>,
+    ),
+    TransferTick,
+    /// A script was read for the session with this id
+    ScriptLoaded(u32, String, Result<String, String>),
+    ScriptEvent(u32, script::ScriptEvent),
+    SequenceMessage(sequence::SequenceMessage),
+    SequenceTick,
 }
 
 impl App {
-    fn update(&mut self, message: Message) {
+    fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::TerminalPaneMessage(msg) => {
-                match &msg {
-                    terminal::TerminalPaneMessage::InputSubmit => {
-                        // Send data to connected port if available
-                        if let Some(controller) = &self.state.terminal_controller {
-                            let data_to_send = self.state.terminal.input_value.clone();
-                            println!("UI: Attempting to send data: '{}'", data_to_send);
-
-                            if !data_to_send.trim().is_empty() {
-                                match controller.push(data_to_send.clone()) {
-                                    Ok(_) => {
-                                        println!("UI: Successfully queued data for transmission");
-                                        self.state
-                                            .terminal
-                                            .add_message(&format!("Sent: {}", data_to_send));
-                                    }
-                                    Err(e) => {
-                                        println!("UI: Failed to queue data: {:?}", e);
-                                        self.state
-                                            .terminal
-                                            .add_message(&format!("Error sending data: {}", e));
-                                    }
-                                }
-                            } else {
-                                println!("UI: Not sending empty data");
-                            }
-                        } else {
-                            println!("UI: No controller available for sending data");
-                            self.state.terminal.add_message("Not connected to any port");
-                        }
-                    }
-                    terminal::TerminalPaneMessage::InputChanged(_) => {
-                        // Check for received data on every input change
-                        if let Some(controller) = &self.state.terminal_controller {
-                            while let Some(data) = controller.try_receive_data() {
-                                println!("UI received data: {}", data);
-                                self.state.terminal.add_message(&data);
-                            }
-                        }
-                    }
-                }
-                self.state.terminal.update(msg);
+                return self.state.session_mut().update(msg);
             }
 
             Message::SidebarMessage(msg) => {
-                match &msg {
-                    sidebar::SidebarMessage::ConnectPressed => {
-                        self.update(Message::ConnectToPort);
-                    }
+                let task = match &msg {
+                    sidebar::SidebarMessage::ConnectPressed => self.update(Message::ConnectToPort),
                     sidebar::SidebarMessage::DisconnectPressed => {
-                        self.update(Message::DisconnectFromPort);
+                        self.update(Message::DisconnectFromPort)
                     }
-                    sidebar::SidebarMessage::RefreshPressed => {
-                        self.update(Message::RefreshPorts);
+                    sidebar::SidebarMessage::RefreshPressed => self.update(Message::RefreshPorts),
+                    sidebar::SidebarMessage::ReconnectPolicyChanged(policy) => {
+                        self.state.session_mut().set_reconnect_policy(*policy);
+                        Task::none()
                     }
-                }
-                self.state.left_sidebar.update(msg);
-
-                // Check for received data on sidebar interactions
-                if let Some(controller) = &self.state.terminal_controller {
-                    while let Some(data) = controller.try_receive_data() {
-                        self.state.terminal.add_message(&data);
+                    sidebar::SidebarMessage::TransportSelected(kind) => {
+                        self.state.session_mut().transport_kind = *kind;
+                        Task::none()
                     }
-                }
+                    sidebar::SidebarMessage::TcpAddressChanged(address) => {
+                        self.state.session_mut().tcp_address = address.clone();
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::LineSettingsChanged(settings) => {
+                        self.state.session_mut().set_line_settings(*settings);
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::LineEndingsChanged(line_endings) => {
+                        self.state.session_mut().set_line_endings(*line_endings);
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::ReceiveModeSelected(receive_mode) => {
+                        self.state.session_mut().set_receive_mode(*receive_mode);
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::EncodingSelected(encoding) => {
+                        self.state.session_mut().set_encoding(*encoding);
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::LoggingToggled(enabled) => {
+                        self.state.session_mut().set_logging(*enabled);
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::LogSettingsChanged(settings) => {
+                        self.state.session_mut().set_log_settings(settings.clone());
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::SendFileSettingsChanged(settings) => {
+                        self.state.session_mut().send_file_settings = settings.clone();
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::SendFilePressed => {
+                        self.state.session_mut().send_file()
+                    }
+                    sidebar::SidebarMessage::CancelSendFilePressed => {
+                        self.state.session_mut().stop_file_send("cancelled");
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::TransferSettingsChanged(settings) => {
+                        self.state.session_mut().transfer_settings = settings.clone();
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::TransferSendPressed => {
+                        self.state.session_mut().send_transfer_files(false)
+                    }
+                    sidebar::SidebarMessage::TransferReceivePressed => {
+                        self.state.session_mut().receive_transfer();
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::ScriptPathChanged(path) => {
+                        self.state.session_mut().script_path = path.clone();
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::RunScriptPressed => {
+                        self.state.session_mut().run_script()
+                    }
+                    sidebar::SidebarMessage::StopScriptPressed => {
+                        self.state.session_mut().stop_script();
+                        Task::none()
+                    }
+                    sidebar::SidebarMessage::TransferCancelPressed => {
+                        self.state.session_mut().cancel_transfer();
+                        Task::none()
+                    }
+                };
+                self.state.left_sidebar.update(msg);
+                return task;
             }
 
             Message::StyleMessage(e) => {
                 self.state.style.update(e);
             }
 
-            Message::PortSelected(port) => {
-                self.state.selected_port = Some(port);
+            Message::MacroMessage(msg) => {
+                if let macros::MacroMessage::Pressed(index) = msg {
+                    if let Some(m) = self.state.macros.get(index).cloned() {
+                        self.state.session_mut().send_macro(&m);
+                    }
+                }
+                self.state.macros.update(msg);
             }
 
-            Message::ConnectToPort => {
-                if let Some(port) = &self.state.selected_port {
-                    if !self.state.is_connected {
-                        println!("UI: Attempting to connect to port: {}", port.name);
-                        let mut controller = TerminalController::new(1);
-
-                        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                            controller.create_stream(port);
-                        })) {
-                            Ok(_) => {
-                                self.state.terminal_controller = Some(controller);
-                                self.state.is_connected = true;
-                                self.state
-                                    .terminal
-                                    .add_message(&format!("Connected to {}", port.name));
-                                println!("UI: Successfully connected to {}", port.name);
-                            }
-                            Err(_) => {
-                                self.state
-                                    .terminal
-                                    .add_message(&format!("Failed to connect to {}", port.name));
-                                println!("UI: Failed to connect to {}", port.name);
-                            }
+            Message::SequenceMessage(msg) => {
+                match msg {
+                    sequence::SequenceMessage::RunPressed(index) => {
+                        if let Some(sequence) = self.state.sequences.get(index).cloned() {
+                            self.state.session_mut().run_sequence(sequence);
                         }
-                    } else {
-                        println!("UI: Already connected");
                     }
-                } else {
-                    self.state.terminal.add_message("No port selected");
-                    println!("UI: No port selected for connection");
+                    sequence::SequenceMessage::StopPressed => {
+                        self.state.session_mut().stop_sequence("stopped by user");
+                    }
+                    _ => {}
                 }
+                self.state.sequences.update(msg);
             }
 
-            Message::DisconnectFromPort => {
-                if self.state.is_connected {
-                    if let Some(mut controller) = self.state.terminal_controller.take() {
-                        controller.end_stream();
-                    }
-                    self.state.is_connected = false;
-                    self.state.terminal.add_message("Disconnected");
+            Message::SequenceTick => {
+                for session in &mut self.state.sessions {
+                    session.poll_sequence();
                 }
             }
 
-            Message::SendData(data) => {
-                if let Some(controller) = &self.state.terminal_controller {
-                    if let Err(e) = controller.push(data.clone()) {
-                        self.state
-                            .terminal
-                            .add_message(&format!("Error sending data: {}", e));
-                    } else {
-                        self.state.terminal.add_message(&format!("Sent: {}", data));
-                    }
+            Message::FunctionKeyPressed(number) => {
+                if let Some(m) = self.state.macros.for_function_key(number).cloned() {
+                    self.state.session_mut().send_macro(&m);
                 }
             }
 
+            Message::PortSelected(port) => {
+                self.state.session_mut().selected_port = Some(port);
+            }
+
+            Message::ConnectToPort => {
+                self.state.session_mut().connect();
+            }
+
+            Message::DisconnectFromPort => {
+                self.state.session_mut().disconnect();
+            }
+
+            Message::SendData(data) => {
+                self.state.session_mut().send(data);
+            }
+
             Message::RefreshPorts => {
                 self.state.available_ports = controller::list_available_ports().unwrap_or_default();
             }
 
-            Message::ReceivedData(data) => {
-                self.state.terminal.add_message(&data);
+            Message::ConnectionEvent(id, event) => {
+                if let Some(session) = self.state.sessions.iter_mut().find(|s| s.id == id) {
+                    return session.handle_connection_event(event);
+                }
+            }
+
+            Message::FileLoaded(id, path, data) => {
+                if let Some(session) = self.state.sessions.iter_mut().find(|s| s.id == id) {
+                    session.start_file_send(path, data);
+                }
+            }
+
+            Message::FileChunkDue(id) => {
+                if let Some(session) = self.state.sessions.iter_mut().find(|s| s.id == id) {
+                    session.send_next_chunk();
+                }
+            }
+
+            Message::TransferFilesLoaded(id, protocol, answering, files) => {
+                if let Some(session) = self.state.sessions.iter_mut().find(|s| s.id == id) {
+                    session.start_transfer_send(protocol, answering, files);
+                }
+            }
+
+            Message::TransferTick => {
+                for session in &mut self.state.sessions {
+                    session.poll_transfer();
+                }
+            }
+
+            Message::ScriptLoaded(id, path, source) => {
+                if let Some(session) = self.state.sessions.iter_mut().find(|s| s.id == id) {
+                    session.start_script(path, source);
+                }
+            }
+
+            Message::ScriptEvent(id, event) => {
+                if let Some(session) = self.state.sessions.iter_mut().find(|s| s.id == id) {
+                    session.handle_script_event(event);
+                }
+            }
+
+            Message::FlushIdleLines => {
+                for session in &mut self.state.sessions {
+                    session.flush_idle_line();
+                }
+            }
+
+            Message::NewSession => {
+                let id = self.state.next_session_id;
+                self.state.next_session_id += 1;
+                self.state.sessions.push(session::Session::new(id));
+                self.state.active_session = self.state.sessions.len() - 1;
             }
 
-            Message::CheckForReceivedData => {
-                if let Some(controller) = &self.state.terminal_controller {
-                    // Check for multiple messages in the queue
-                    let mut received_any = false;
-                    while let Some(data) = controller.try_receive_data() {
-                        println!("UI processing received data: {}", data);
-                        self.state.terminal.add_message(&data);
-                        received_any = true;
+            Message::SessionSelected(id) => {
+                if let Some(index) = self.state.sessions.iter().position(|s| s.id == id) {
+                    self.state.active_session = index;
+                }
+            }
+
+            Message::CloseSession(id) => {
+                if let Some(index) = self.state.sessions.iter().position(|s| s.id == id) {
+                    let mut session = self.state.sessions.remove(index);
+                    session.disconnect();
+
+                    // Always keep one session around
+                    if self.state.sessions.is_empty() {
+                        let id = self.state.next_session_id;
+                        self.state.next_session_id += 1;
+                        self.state.sessions.push(session::Session::new(id));
                     }
-                    if received_any {
-                        println!("Processed received data in UI");
+                    if self.state.active_session >= index && self.state.active_session > 0 {
+                        self.state.active_session -= 1;
                     }
                 }
             }
         }
+        Task::none()
+    }
+
+    fn tab_bar(&self) -> Element<'_, Message> {
+        let mut tabs = row![].spacing(5).align_y(Alignment::Center);
+
+        for (index, session) in self.state.sessions.iter().enumerate() {
+            let style = if index == self.state.active_session {
+                button::primary
+            } else {
+                button::secondary
+            };
+            let label = if session.status.is_active() {
+                format!("* {}", session.title())
+            } else {
+                session.title()
+            };
+
+            tabs = tabs.push(
+                row![
+                    button(text(label))
+                        .style(style)
+                        .on_press(Message::SessionSelected(session.id)),
+                    button(text("x"))
+                        .style(style)
+                        .on_press(Message::CloseSession(session.id)),
+                ]
+                .spacing(1),
+            );
+        }
+
+        tabs.push(button(text("+")).on_press(Message::NewSession))
+            .into()
     }
 
     fn view(&self) -> Element<Message> {
-        let left_sidebar: Element<Message> = self.state.left_sidebar.view(
-            &self.state.available_ports,
-            &self.state.selected_port,
-            self.state.is_connected,
-        );
-        let main_content = self.state.terminal.view();
+        let session = self.state.session();
+        // Offer the free ends of virtual port pairs alongside the system ports
+        let mut ports = self.state.available_ports.clone();
+        ports.extend(self.state.sessions.iter().filter_map(|s| {
+            s.virtual_peer.as_ref().map(|path| {
+                SerialPortInfo::new(
+                    path.clone(),
+                    9600,
+                    DataBits::Eight,
+                    Parity::None,
+                    StopBits::One,
+                    FlowControl::None,
+                )
+            })
+        }));
+        let left_sidebar: Element<Message> = self.state.left_sidebar.view(ports, session);
+        let main_content = column![self.tab_bar(), session.terminal.view()].spacing(10);
 
-        let style = self.state.style.view();
+        let style = column![
+            self.state.style.view(),
+            self.state.macros.view(),
+            self.state.sequences.view(session.sequence.as_ref()),
+        ]
+        .spacing(20);
 
         let layout = row![
             container(left_sidebar)
@@ -252,15 +433,93 @@ impl App {
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        if self.state.is_connected {
-            // Check for received data every 50ms when connected
-            iced::time::every(Duration::from_millis(50)).map(|_| Message::CheckForReceivedData)
-        } else {
-            Subscription::none()
+        // Each connected session streams its events as they happen
+        let mut subscriptions: Vec<Subscription<Message>> = self
+            .state
+            .sessions
+            .iter()
+            .filter_map(|s| s.controller.as_ref())
+            .map(|c| {
+                c.subscription()
+                    .map(|(id, event)| Message::ConnectionEvent(id, event))
+            })
+            .collect();
+
+        subscriptions.extend(self.state.sessions.iter().filter_map(|s| {
+            s.script.as_ref().map(|script| {
+                script
+                    .subscription(s.id)
+                    .map(|(id, event)| Message::ScriptEvent(id, event))
+            })
+        }));
+
+        // Partial lines are shown once no more data arrives for a while
+        if self
+            .state
+            .sessions
+            .iter()
+            .filter_map(|s| s.controller.as_ref())
+            .any(|c| c.has_partial_line())
+        {
+            subscriptions
+                .push(iced::time::every(LINE_IDLE_TIMEOUT / 2).map(|_| Message::FlushIdleLines));
+        }
+
+        // Transfers retry on their own once the other side goes quiet
+        if self.state.sessions.iter().any(|s| s.transfer.is_some()) {
+            subscriptions
+                .push(iced::time::every(TRANSFER_POLL_INTERVAL).map(|_| Message::TransferTick));
         }
+
+        // Sequence steps fail once nothing matched before their timeout
+        if self.state.sessions.iter().any(|s| s.sequence.is_some()) {
+            subscriptions
+                .push(iced::time::every(SEQUENCE_POLL_INTERVAL).map(|_| Message::SequenceTick));
+        }
+
+        subscriptions.push(keyboard::on_key_press(key_pressed));
+
+        Subscription::batch(subscriptions)
     }
 }
 
+/// Application wide shortcuts
+fn key_pressed(key: Key, modifiers: Modifiers) -> Option<Message> {
+    if let Some(number) = function_key(&key) {
+        return Some(Message::FunctionKeyPressed(number));
+    }
+    let message = match key.as_ref() {
+        Key::Character("f") if modifiers.command() => terminal::TerminalPaneMessage::SearchOpened,
+        Key::Character("r") if modifiers.control() => {
+            terminal::TerminalPaneMessage::HistorySearchOpened
+        }
+        Key::Named(key::Named::ArrowUp) => terminal::TerminalPaneMessage::HistoryPrevious,
+        Key::Named(key::Named::ArrowDown) => terminal::TerminalPaneMessage::HistoryNext,
+        Key::Named(key::Named::Escape) => terminal::TerminalPaneMessage::EscapePressed,
+        _ => return None,
+    };
+    Some(Message::TerminalPaneMessage(message))
+}
+
+fn function_key(key: &Key) -> Option<u8> {
+    let number = match key {
+        Key::Named(key::Named::F1) => 1,
+        Key::Named(key::Named::F2) => 2,
+        Key::Named(key::Named::F3) => 3,
+        Key::Named(key::Named::F4) => 4,
+        Key::Named(key::Named::F5) => 5,
+        Key::Named(key::Named::F6) => 6,
+        Key::Named(key::Named::F7) => 7,
+        Key::Named(key::Named::F8) => 8,
+        Key::Named(key::Named::F9) => 9,
+        Key::Named(key::Named::F10) => 10,
+        Key::Named(key::Named::F11) => 11,
+        Key::Named(key::Named::F12) => 12,
+        _ => return None,
+    };
+    Some(number)
+}
+
 fn main() -> iced::Result {
     tracing_subscriber::fmt()
         .with_env_filter(
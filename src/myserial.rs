@@ -9,6 +9,21 @@ pub enum DataBits {
     Eight = 8,
 }
 
+impl DataBits {
+    pub const ALL: [DataBits; 4] = [
+        DataBits::Five,
+        DataBits::Six,
+        DataBits::Seven,
+        DataBits::Eight,
+    ];
+}
+
+impl std::fmt::Display for DataBits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bits", *self as u8)
+    }
+}
+
 impl From<DataBits> for serialport::DataBits {
     fn from(data_bits: DataBits) -> Self {
         match data_bits {
@@ -27,6 +42,20 @@ pub enum Parity {
     Even,
 }
 
+impl Parity {
+    pub const ALL: [Parity; 3] = [Parity::None, Parity::Odd, Parity::Even];
+}
+
+impl std::fmt::Display for Parity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Parity::None => write!(f, "No parity"),
+            Parity::Odd => write!(f, "Odd parity"),
+            Parity::Even => write!(f, "Even parity"),
+        }
+    }
+}
+
 impl From<Parity> for serialport::Parity {
     fn from(parity: Parity) -> Self {
         match parity {
@@ -43,6 +72,19 @@ pub enum StopBits {
     Two,
 }
 
+impl StopBits {
+    pub const ALL: [StopBits; 2] = [StopBits::One, StopBits::Two];
+}
+
+impl std::fmt::Display for StopBits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopBits::One => write!(f, "1 stop bit"),
+            StopBits::Two => write!(f, "2 stop bits"),
+        }
+    }
+}
+
 impl From<StopBits> for serialport::StopBits {
     fn from(stop_bits: StopBits) -> Self {
         match stop_bits {
@@ -58,6 +100,24 @@ pub enum FlowControl {
     Hardware,
 }
 
+impl FlowControl {
+    pub const ALL: [FlowControl; 3] = [
+        FlowControl::None,
+        FlowControl::Software,
+        FlowControl::Hardware,
+    ];
+}
+
+impl std::fmt::Display for FlowControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlowControl::None => write!(f, "No flow control"),
+            FlowControl::Software => write!(f, "XON/XOFF"),
+            FlowControl::Hardware => write!(f, "RTS/CTS"),
+        }
+    }
+}
+
 impl From<FlowControl> for serialport::FlowControl {
     fn from(flow_control: FlowControl) -> Self {
         match flow_control {
@@ -68,6 +128,28 @@ impl From<FlowControl> for serialport::FlowControl {
     }
 }
 
+/// Line parameters chosen in the sidebar, applied to whichever port a session opens
+#[derive(Clone, Serialize, Deserialize, Debug, Copy, PartialEq)]
+pub struct LineSettings {
+    pub speed: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        Self {
+            speed: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SerialPortInfo {
     pub name: String,
@@ -76,6 +158,9 @@ pub struct SerialPortInfo {
     pub parity: Parity,
     pub stop_bits: StopBits,
     pub flow_control: FlowControl,
+    /// USB serial number, used to find the device again after it re-enumerates
+    #[serde(default)]
+    pub serial_number: Option<String>,
 }
 
 impl SerialPortInfo {
@@ -94,9 +179,34 @@ impl SerialPortInfo {
             parity,
             stop_bits,
             flow_control,
+            serial_number: None,
         }
     }
 
+    pub fn with_settings(mut self, settings: &LineSettings) -> Self {
+        self.speed = settings.speed;
+        self.data_bits = settings.data_bits;
+        self.parity = settings.parity;
+        self.stop_bits = settings.stop_bits;
+        self.flow_control = settings.flow_control;
+        self
+    }
+
+    pub fn line_settings(&self) -> LineSettings {
+        LineSettings {
+            speed: self.speed,
+            data_bits: self.data_bits,
+            parity: self.parity,
+            stop_bits: self.stop_bits,
+            flow_control: self.flow_control,
+        }
+    }
+
+    pub fn with_serial_number(mut self, serial_number: Option<String>) -> Self {
+        self.serial_number = serial_number;
+        self
+    }
+
     pub fn from_json(json: String) -> Option<Self> {
         let serial_port_info = serde_json::from_str(&json);
         return match serial_port_info {
@@ -0,0 +1,569 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use iced::futures::{SinkExt, Stream};
+use iced::Subscription;
+use regex::Regex;
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Map, INT};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::config::{SCRIPT_BUFFER_LIMIT, SCRIPT_DEFAULT_TIMEOUT, SCRIPT_POLL_INTERVAL};
+use crate::hex;
+use crate::myserial::{DataBits, FlowControl, LineSettings, Parity, StopBits};
+use crate::util::keep_newest;
+
+/// Identifies each run so starting a script again starts a fresh subscription
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(1);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Port settings changed by `set_baud` or `set_port`, the rest stay as they are
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PortChange {
+    pub speed: Option<u32>,
+    pub data_bits: Option<DataBits>,
+    pub parity: Option<Parity>,
+    pub stop_bits: Option<StopBits>,
+    pub flow_control: Option<FlowControl>,
+}
+
+impl PortChange {
+    pub fn apply(&self, settings: LineSettings) -> LineSettings {
+        LineSettings {
+            speed: self.speed.unwrap_or(settings.speed),
+            data_bits: self.data_bits.unwrap_or(settings.data_bits),
+            parity: self.parity.unwrap_or(settings.parity),
+            stop_bits: self.stop_bits.unwrap_or(settings.stop_bits),
+            flow_control: self.flow_control.unwrap_or(settings.flow_control),
+        }
+    }
+
+    /// Reads a map such as `#{ baud: 115200, parity: "even" }`
+    fn from_map(map: &Map) -> ScriptResult<Self> {
+        let mut change = PortChange::default();
+        for (key, value) in map {
+            match key.as_str() {
+                "baud" | "speed" => change.speed = Some(baud(value.as_int()?)?),
+                "data_bits" => {
+                    change.data_bits = Some(match value.as_int()? {
+                        5 => DataBits::Five,
+                        6 => DataBits::Six,
+                        7 => DataBits::Seven,
+                        8 => DataBits::Eight,
+                        bits => return Err(format!("Invalid data bits {}", bits).into()),
+                    })
+                }
+                "parity" => {
+                    change.parity = Some(match value.clone().into_string()?.as_str() {
+                        "none" => Parity::None,
+                        "odd" => Parity::Odd,
+                        "even" => Parity::Even,
+                        parity => return Err(format!("Invalid parity '{}'", parity).into()),
+                    })
+                }
+                "stop_bits" => {
+                    change.stop_bits = Some(match value.as_int()? {
+                        1 => StopBits::One,
+                        2 => StopBits::Two,
+                        bits => return Err(format!("Invalid stop bits {}", bits).into()),
+                    })
+                }
+                "flow_control" => {
+                    change.flow_control = Some(match value.clone().into_string()?.as_str() {
+                        "none" => FlowControl::None,
+                        "software" => FlowControl::Software,
+                        "hardware" => FlowControl::Hardware,
+                        flow => return Err(format!("Invalid flow control '{}'", flow).into()),
+                    })
+                }
+                key => return Err(format!("Unknown port setting '{}'", key).into()),
+            }
+        }
+        Ok(change)
+    }
+}
+
+fn baud(rate: INT) -> ScriptResult<u32> {
+    u32::try_from(rate)
+        .ok()
+        .filter(|rate| *rate > 0)
+        .ok_or_else(|| format!("Invalid baud rate {}", rate).into())
+}
+
+fn millis(ms: INT) -> ScriptResult<Duration> {
+    u64::try_from(ms)
+        .map(Duration::from_millis)
+        .map_err(|_| format!("Invalid timeout {} ms", ms).into())
+}
+
+/// Events reported by a running script back to its session
+#[derive(Debug, Clone)]
+pub enum ScriptEvent {
+    /// The script thread started, received data reaches it through the handle
+    Started(ScriptHandle),
+    /// Bytes to send as they are
+    Send(Vec<u8>),
+    /// Text to send followed by the session's line ending
+    SendLine(Vec<u8>),
+    /// Output of `print`, `debug` and `log`
+    Log(String),
+    SetPort(PortChange),
+    Finished(Result<(), String>),
+}
+
+/// Feeds received data to a running script and stops it
+#[derive(Debug, Clone)]
+pub struct ScriptHandle {
+    data: mpsc::Sender<Vec<u8>>,
+    stop: Arc<AtomicBool>,
+}
+
+/// A script loaded from a file, running while its subscription is alive and
+/// stopped when dropped
+#[derive(Debug)]
+pub struct Script {
+    pub name: String,
+    source: String,
+    run_id: u64,
+    handle: Option<ScriptHandle>,
+}
+
+impl Script {
+    pub fn new(name: String, source: String) -> Self {
+        Self {
+            name,
+            source,
+            run_id: NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed),
+            handle: None,
+        }
+    }
+
+    /// Stores the handle announced by the script thread
+    pub fn attach(&mut self, handle: ScriptHandle) {
+        self.handle = Some(handle);
+    }
+
+    /// Passes data read from the connection on to `wait_for` and `read_line`
+    pub fn received(&self, data: &[u8]) {
+        if let Some(handle) = &self.handle {
+            let _ = handle.data.send(data.to_vec());
+        }
+    }
+
+    /// Events of the running script, tagged with the session id
+    pub fn subscription(&self, id: u32) -> Subscription<(u32, ScriptEvent)> {
+        Subscription::run_with_id(self.run_id, run_script(id, self.source.clone()))
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.handle {
+            handle.stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Runs the script on its own thread, as waiting for data blocks, and
+/// forwards what it asks for to the UI
+fn run_script(id: u32, source: String) -> impl Stream<Item = (u32, ScriptEvent)> {
+    iced::stream::channel(100, move |mut output| async move {
+        let (events, mut receiver) = unbounded_channel();
+        let (data, data_receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = ScriptHandle {
+            data,
+            stop: stop.clone(),
+        };
+        if output
+            .send((id, ScriptEvent::Started(handle)))
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        thread::spawn(move || {
+            let context = Context {
+                events: events.clone(),
+                data: data_receiver,
+                stop,
+                buffer: String::new(),
+            };
+            let result = run(&source, context);
+            let _ = events.send(ScriptEvent::Finished(result));
+        });
+
+        while let Some(event) = receiver.recv().await {
+            if output.send((id, event)).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// What the script's functions share
+struct Context {
+    events: UnboundedSender<ScriptEvent>,
+    data: mpsc::Receiver<Vec<u8>>,
+    stop: Arc<AtomicBool>,
+    /// Received text not yet consumed by `wait_for` or `read_line`
+    buffer: String,
+}
+
+impl Context {
+    /// Set by the stop button, or once the session stopped listening
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed) || self.events.is_closed()
+    }
+
+    fn emit(&self, event: ScriptEvent) -> ScriptResult<()> {
+        self.events.send(event).map_err(|_| "Session closed".into())
+    }
+
+    /// Waits a little for data, returning whether `deadline` is still ahead
+    fn receive(&mut self, deadline: Instant) -> ScriptResult<bool> {
+        if self.stopped() {
+            return Err("Stopped".into());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+
+        match self
+            .data
+            .recv_timeout((deadline - now).min(SCRIPT_POLL_INTERVAL))
+        {
+            Ok(data) => {
+                self.buffer.push_str(&String::from_utf8_lossy(&data));
+                keep_newest(&mut self.buffer, SCRIPT_BUFFER_LIMIT);
+                Ok(true)
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(Instant::now() < deadline),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err("Session closed".into()),
+        }
+    }
+
+    /// Consumes received text up to the end of the first match of `pattern`
+    fn wait_for(&mut self, pattern: &str, timeout: Duration) -> ScriptResult<bool> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(found) = regex.find(&self.buffer) {
+                let end = found.end();
+                self.buffer.drain(..end);
+                return Ok(true);
+            }
+            if !self.receive(deadline)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Next received line without its line ending, `()` on timeout
+    fn read_line(&mut self, timeout: Duration) -> ScriptResult<Dynamic> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(end) = self.buffer.find('\n') {
+                let line: String = self.buffer.drain(..=end).collect();
+                return Ok(line.trim_end_matches(['\r', '\n']).to_string().into());
+            }
+            if !self.receive(deadline)? {
+                return Ok(Dynamic::UNIT);
+            }
+        }
+    }
+
+    /// Sleeps without missing data received meanwhile
+    fn sleep(&mut self, duration: Duration) -> ScriptResult<()> {
+        let deadline = Instant::now() + duration;
+        while self.receive(deadline)? {}
+        Ok(())
+    }
+}
+
+fn run(source: &str, context: Context) -> Result<(), String> {
+    let events = context.events.clone();
+    let stop = context.stop.clone();
+    let context = Rc::new(RefCell::new(context));
+    let mut engine = Engine::new();
+
+    let log = events.clone();
+    engine.on_print(move |text| {
+        let _ = log.send(ScriptEvent::Log(text.to_string()));
+    });
+    let log = events.clone();
+    engine.on_debug(move |text, _, position| {
+        let _ = log.send(ScriptEvent::Log(format!("{} {}", position, text)));
+    });
+    engine.on_progress(move |_| {
+        (stop.load(Ordering::Relaxed) || events.is_closed()).then(|| Dynamic::from("Stopped"))
+    });
+
+    let c = context.clone();
+    engine.register_fn("send", move |text: &str| {
+        c.borrow().emit(ScriptEvent::Send(text.as_bytes().to_vec()))
+    });
+    let c = context.clone();
+    engine.register_fn("send_line", move |text: &str| {
+        c.borrow()
+            .emit(ScriptEvent::SendLine(text.as_bytes().to_vec()))
+    });
+    let c = context.clone();
+    engine.register_fn("send_bytes", move |bytes: Blob| {
+        c.borrow().emit(ScriptEvent::Send(bytes))
+    });
+    let c = context.clone();
+    engine.register_fn("send_bytes", move |bytes: Array| -> ScriptResult<()> {
+        let bytes = bytes
+            .into_iter()
+            .map(|byte| {
+                let byte = byte.as_int()?;
+                u8::try_from(byte).map_err(|_| format!("{} is not a byte", byte))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        c.borrow().emit(ScriptEvent::Send(bytes))
+    });
+    let c = context.clone();
+    engine.register_fn("send_hex", move |text: &str| -> ScriptResult<()> {
+        let bytes = hex::parse(text).map_err(|e| e.to_string())?;
+        c.borrow().emit(ScriptEvent::Send(bytes))
+    });
+
+    let c = context.clone();
+    engine.register_fn("wait_for", move |pattern: &str| {
+        c.borrow_mut().wait_for(pattern, SCRIPT_DEFAULT_TIMEOUT)
+    });
+    let c = context.clone();
+    engine.register_fn("wait_for", move |pattern: &str, timeout_ms: INT| {
+        c.borrow_mut().wait_for(pattern, millis(timeout_ms)?)
+    });
+    let c = context.clone();
+    engine.register_fn("read_line", move || {
+        c.borrow_mut().read_line(SCRIPT_DEFAULT_TIMEOUT)
+    });
+    let c = context.clone();
+    engine.register_fn("read_line", move |timeout_ms: INT| {
+        c.borrow_mut().read_line(millis(timeout_ms)?)
+    });
+    let c = context.clone();
+    engine.register_fn("sleep", move |ms: INT| c.borrow_mut().sleep(millis(ms)?));
+
+    let c = context.clone();
+    engine.register_fn("log", move |text: &str| {
+        c.borrow().emit(ScriptEvent::Log(text.to_string()))
+    });
+    let c = context.clone();
+    engine.register_fn("set_baud", move |rate: INT| {
+        let change = PortChange {
+            speed: Some(baud(rate)?),
+            ..PortChange::default()
+        };
+        c.borrow().emit(ScriptEvent::SetPort(change))
+    });
+    let c = context;
+    engine.register_fn("set_port", move |settings: Map| {
+        let change = PortChange::from_map(&settings)?;
+        c.borrow().emit(ScriptEvent::SetPort(change))
+    });
+
+    engine.run(source).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+
+    struct Harness {
+        context: Context,
+        data: mpsc::Sender<Vec<u8>>,
+        events: UnboundedReceiver<ScriptEvent>,
+        stop: Arc<AtomicBool>,
+    }
+
+    fn harness() -> Harness {
+        let (events, events_receiver) = unbounded_channel();
+        let (data, data_receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        Harness {
+            context: Context {
+                events,
+                data: data_receiver,
+                stop: stop.clone(),
+                buffer: String::new(),
+            },
+            data,
+            events: events_receiver,
+            stop,
+        }
+    }
+
+    fn map(entries: &[(&str, Dynamic)]) -> Map {
+        entries
+            .iter()
+            .map(|(key, value)| ((*key).into(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn port_change_from_map() {
+        let change = PortChange::from_map(&map(&[
+            ("baud", Dynamic::from(115200 as INT)),
+            ("data_bits", Dynamic::from(7 as INT)),
+            ("parity", Dynamic::from("even")),
+            ("flow_control", Dynamic::from("hardware")),
+        ]))
+        .unwrap();
+        assert_eq!(
+            change.apply(LineSettings::default()),
+            LineSettings {
+                speed: 115200,
+                data_bits: DataBits::Seven,
+                parity: Parity::Even,
+                flow_control: FlowControl::Hardware,
+                ..LineSettings::default()
+            }
+        );
+
+        let rejected = [
+            ("parity", Dynamic::from("mark"), "Invalid parity 'mark'"),
+            ("data_bits", Dynamic::from(9 as INT), "Invalid data bits 9"),
+            ("stop_bits", Dynamic::from(3 as INT), "Invalid stop bits 3"),
+            ("baud", Dynamic::from(0 as INT), "Invalid baud rate 0"),
+            (
+                "speeed",
+                Dynamic::from(9600 as INT),
+                "Unknown port setting 'speeed'",
+            ),
+        ];
+        for (key, value, message) in rejected {
+            let error = PortChange::from_map(&map(&[(key, value)])).unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn baud_and_millis_validation() {
+        assert_eq!(baud(115200).unwrap(), 115200);
+        assert!(baud(0).is_err());
+        assert!(baud(-9600).is_err());
+        assert!(baud(INT::from(u32::MAX) + 1).is_err());
+
+        assert_eq!(millis(250).unwrap(), Duration::from_millis(250));
+        assert_eq!(millis(0).unwrap(), Duration::ZERO);
+        assert!(millis(-1).is_err());
+    }
+
+    #[test]
+    fn wait_for_matches_across_chunks_and_drains_the_match() {
+        let mut harness = harness();
+        harness.data.send(b"U-Boot 2024\r\nlog".to_vec()).unwrap();
+        harness.data.send(b"in: rest".to_vec()).unwrap();
+
+        let found = harness
+            .context
+            .wait_for("log+in:", Duration::from_secs(1))
+            .unwrap();
+        assert!(found);
+        assert_eq!(harness.context.buffer, " rest");
+    }
+
+    #[test]
+    fn wait_for_times_out() {
+        let mut harness = harness();
+        harness.data.send(b"nothing here".to_vec()).unwrap();
+
+        let start = Instant::now();
+        let found = harness
+            .context
+            .wait_for("login", Duration::from_millis(60))
+            .unwrap();
+        assert!(!found);
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        // Unmatched text is kept for the next call
+        assert_eq!(harness.context.buffer, "nothing here");
+        assert!(harness.context.wait_for("(", Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn read_line_strips_the_line_ending() {
+        let mut harness = harness();
+        harness.data.send(b"first\r\nsec".to_vec()).unwrap();
+        harness.data.send(b"ond\nthird".to_vec()).unwrap();
+
+        let timeout = Duration::from_millis(60);
+        let first = harness.context.read_line(timeout).unwrap();
+        assert_eq!(first.into_string().unwrap(), "first");
+        let second = harness.context.read_line(timeout).unwrap();
+        assert_eq!(second.into_string().unwrap(), "second");
+        assert!(harness.context.read_line(timeout).unwrap().is_unit());
+        assert_eq!(harness.context.buffer, "third");
+    }
+
+    #[test]
+    fn stop_and_closed_session_end_waiting() {
+        let mut harness = harness();
+        harness.stop.store(true, Ordering::Relaxed);
+        let error = harness
+            .context
+            .wait_for("x", Duration::from_secs(10))
+            .unwrap_err();
+        assert!(error.to_string().contains("Stopped"));
+
+        let mut harness = self::harness();
+        drop(harness.data);
+        let error = harness
+            .context
+            .read_line(Duration::from_secs(10))
+            .unwrap_err();
+        assert!(error.to_string().contains("Session closed"));
+
+        let mut harness = self::harness();
+        drop(harness.events);
+        assert!(harness.context.stopped());
+        assert!(harness.context.sleep(Duration::from_secs(10)).is_err());
+        assert!(harness
+            .context
+            .emit(ScriptEvent::Log(String::new()))
+            .is_err());
+    }
+
+    #[test]
+    fn script_functions_emit_events() {
+        let mut harness = harness();
+        harness.data.send(b"ready\n".to_vec()).unwrap();
+
+        let source = r#"
+            set_port(#{ baud: 19200, stop_bits: 2 });
+            send_line("hi");
+            send_hex("AA 55");
+            log(read_line(1000));
+        "#;
+        run(source, harness.context).unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = harness.events.try_recv() {
+            events.push(event);
+        }
+        match events.as_slice() {
+            [ScriptEvent::SetPort(change), ScriptEvent::SendLine(line), ScriptEvent::Send(bytes), ScriptEvent::Log(text)] =>
+            {
+                assert_eq!(change.speed, Some(19200));
+                assert_eq!(change.stop_bits, Some(StopBits::Two));
+                assert_eq!(change.parity, None);
+                assert_eq!(line, b"hi");
+                assert_eq!(bytes, &[0xAA, 0x55]);
+                assert_eq!(text, "ready");
+            }
+            other => panic!("unexpected events {:?}", other),
+        }
+    }
+}
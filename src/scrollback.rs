@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// Which way the content of a line travelled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Received,
+    Sent,
+    /// Connection status and errors from the application itself
+    Status,
+}
+
+/// What produced a line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Device,
+    /// Typed into the terminal input
+    Input,
+    /// Sent with a macro button or its function key
+    Macro,
+    Script,
+    /// Sent by a wait-and-respond sequence step
+    Sequence,
+    Application,
+}
+
+#[derive(Debug)]
+pub enum Content {
+    Text(String),
+    Data {
+        /// Position of the first byte within everything sent or received
+        offset: usize,
+        /// Bytes as they went over the wire, shown by the hex dump
+        raw: Vec<u8>,
+        /// Bytes shown in text mode, with newlines normalized
+        text: Vec<u8>,
+    },
+}
+
+/// One entry of the scrollback, rendered to one or more display rows
+#[derive(Debug)]
+pub struct Line {
+    pub direction: Direction,
+    pub source: Source,
+    pub time: SystemTime,
+    pub connected_at: Option<SystemTime>,
+    pub content: Content,
+}
+
+/// Ring buffer of lines, the oldest line is dropped once the limit is reached
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<Line>,
+    limit: usize,
+}
+
+impl Scrollback {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(limit.min(1024)),
+            limit,
+        }
+    }
+
+    /// Appends a line, returning the line it pushed out if the buffer was full
+    pub fn push(&mut self, line: Line) -> Option<Line> {
+        let evicted = if self.lines.len() >= self.limit {
+            self.lines.pop_front()
+        } else {
+            None
+        };
+        self.lines.push_back(line);
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Line> {
+        self.lines.get(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Line {
+        Line {
+            direction: Direction::Received,
+            source: Source::Device,
+            time: SystemTime::UNIX_EPOCH,
+            connected_at: None,
+            content: Content::Text(String::from(text)),
+        }
+    }
+
+    fn text(line: Option<&Line>) -> &str {
+        match line.map(|line| &line.content) {
+            Some(Content::Text(text)) => text,
+            other => panic!("unexpected line {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oldest_line_is_evicted_at_the_limit() {
+        let mut scrollback = Scrollback::new(3);
+        for name in ["a", "b", "c"] {
+            assert!(scrollback.push(line(name)).is_none());
+        }
+        assert_eq!(scrollback.len(), 3);
+
+        let evicted = scrollback.push(line("d"));
+        assert_eq!(text(evicted.as_ref()), "a");
+        let evicted = scrollback.push(line("e"));
+        assert_eq!(text(evicted.as_ref()), "b");
+
+        assert_eq!(scrollback.len(), 3);
+        assert_eq!(text(scrollback.get(0)), "c");
+        assert_eq!(text(scrollback.get(2)), "e");
+        assert!(scrollback.get(3).is_none());
+    }
+}
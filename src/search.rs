@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+/// A match within a display row
+#[derive(Debug, Clone)]
+pub struct Hit {
+    /// Row counted from the first row ever displayed, so evicting rows does
+    /// not renumber the remaining hits
+    row: usize,
+    pub range: Range<usize>,
+}
+
+/// Plain text or regex search over the rows of the terminal display
+#[derive(Debug, Default)]
+pub struct Search {
+    pub query: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// Why the query is not a valid regex
+    pub error: Option<String>,
+    matcher: Option<Regex>,
+    hits: VecDeque<Hit>,
+    current: Option<usize>,
+    /// Display rows dropped from the front since the last rescan
+    evicted_rows: usize,
+}
+
+impl Search {
+    /// Rebuilds the matcher after the query or one of the options changed
+    pub fn compile(&mut self) {
+        self.matcher = None;
+        self.error = None;
+        if self.query.is_empty() {
+            return;
+        }
+
+        let pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        match RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+        {
+            Ok(matcher) => self.matcher = Some(matcher),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Replaces all hits by matching every display row, the newest hit becomes current
+    pub fn rescan<'a>(&mut self, rows: impl Iterator<Item = &'a str>) {
+        self.hits.clear();
+        self.evicted_rows = 0;
+        self.scan(0, rows);
+        self.current = self.hits.len().checked_sub(1);
+    }
+
+    /// Adds the hits of rows appended to the display starting at row `first`
+    pub fn scan<'a>(&mut self, first: usize, rows: impl Iterator<Item = &'a str>) {
+        let Some(matcher) = &self.matcher else {
+            return;
+        };
+        for (index, row) in rows.enumerate() {
+            for found in matcher.find_iter(row).filter(|found| !found.is_empty()) {
+                self.hits.push_back(Hit {
+                    row: self.evicted_rows + first + index,
+                    range: found.range(),
+                });
+            }
+        }
+    }
+
+    /// Drops the hits of the first `count` display rows after they were evicted
+    pub fn remove_rows(&mut self, count: usize) {
+        self.evicted_rows += count;
+        let removed = self.hits.partition_point(|hit| hit.row < self.evicted_rows);
+        self.hits.drain(..removed);
+        self.current = match self.current {
+            Some(current) if current >= removed => Some(current - removed),
+            Some(_) if !self.hits.is_empty() => Some(0),
+            _ => None,
+        };
+    }
+
+    pub fn hit_count(&self) -> usize {
+        self.hits.len()
+    }
+
+    /// Zero based index of the current hit
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Display row of the current hit
+    pub fn current_row(&self) -> Option<usize> {
+        let hit = self.hits.get(self.current?)?;
+        Some(hit.row - self.evicted_rows)
+    }
+
+    pub fn next(&mut self) {
+        if !self.hits.is_empty() {
+            self.current = Some(
+                self.current
+                    .map_or(0, |current| (current + 1) % self.hits.len()),
+            );
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if !self.hits.is_empty() {
+            let last = self.hits.len() - 1;
+            self.current = Some(match self.current {
+                Some(0) | None => last,
+                Some(current) => current - 1,
+            });
+        }
+    }
+
+    /// Hits within display row `row` with whether each is the current one
+    pub fn hits_in_row(&self, row: usize) -> impl Iterator<Item = (&Hit, bool)> {
+        let row = row + self.evicted_rows;
+        let first = self.hits.partition_point(|hit| hit.row < row);
+        self.hits
+            .range(first..)
+            .take_while(move |hit| hit.row == row)
+            .enumerate()
+            .map(move |(index, hit)| (hit, self.current == Some(first + index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROWS: [&str; 4] = ["ERROR boot", "ok", "error: disk 42", "Error 7 and error 8"];
+
+    fn search(query: &str, regex: bool, case_sensitive: bool) -> Search {
+        let mut search = Search {
+            query: String::from(query),
+            regex,
+            case_sensitive,
+            ..Search::default()
+        };
+        search.compile();
+        search.rescan(ROWS.iter().copied());
+        search
+    }
+
+    /// Start and end of the hits in each row
+    fn rows_with_hits(search: &Search) -> Vec<Vec<(usize, usize)>> {
+        (0..ROWS.len())
+            .map(|row| {
+                search
+                    .hits_in_row(row)
+                    .map(|(hit, _)| (hit.range.start, hit.range.end))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plain_text_case_sensitivity() {
+        let search = search("error", false, false);
+        assert_eq!(search.hit_count(), 4);
+        assert_eq!(
+            rows_with_hits(&search),
+            [vec![(0, 5)], vec![], vec![(0, 5)], vec![(0, 5), (12, 17)]]
+        );
+
+        let search = self::search("error", false, true);
+        assert_eq!(search.hit_count(), 2);
+        assert_eq!(
+            rows_with_hits(&search),
+            [vec![], vec![], vec![(0, 5)], vec![(12, 17)]]
+        );
+    }
+
+    #[test]
+    fn plain_text_is_not_a_pattern() {
+        assert_eq!(search("r.", false, false).hit_count(), 0);
+        assert!(search("(", false, false).error.is_none());
+    }
+
+    #[test]
+    fn regex_matches_across_rows() {
+        let search = search(r"\d+", true, true);
+        assert_eq!(
+            rows_with_hits(&search),
+            [vec![], vec![], vec![(12, 14)], vec![(6, 7), (18, 19)]]
+        );
+        // The newest hit is current, next wraps around to the first
+        assert_eq!(search.current_row(), Some(3));
+
+        let mut search = search;
+        search.next();
+        assert_eq!(search.current(), Some(0));
+        assert_eq!(search.current_row(), Some(2));
+        search.previous();
+        assert_eq!(search.current(), Some(2));
+    }
+
+    #[test]
+    fn invalid_regex_reports_an_error() {
+        let search = search("(", true, false);
+        assert!(search.error.is_some());
+        assert_eq!(search.hit_count(), 0);
+    }
+
+    #[test]
+    fn evicted_rows_keep_later_hits_in_place() {
+        let mut search = search("error", false, false);
+        search.remove_rows(1);
+        assert_eq!(search.hit_count(), 3);
+        assert_eq!(search.current_row(), Some(2));
+
+        // Rows appended after the eviction are numbered from the new front
+        search.scan(3, ["no ERROR here"].into_iter());
+        assert_eq!(search.hit_count(), 4);
+        assert_eq!(search.hits_in_row(3).count(), 1);
+    }
+}
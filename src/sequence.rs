@@ -0,0 +1,590 @@
+use std::path::Path;
+use std::time::Instant;
+
+use iced::widget::{button, column, container, pick_list, row, text, text_input};
+use iced::{Alignment, Element};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    SEQUENCES_FILE, SEQUENCE_BUFFER_LIMIT, SEQUENCE_STEP_TIMEOUT_MS, SEQUENCE_TIMEOUTS_MS,
+};
+use crate::encoding::Encoding;
+use crate::util::{keep_newest, load_json, save_json};
+use crate::Message;
+
+/// Waits for `expect` in the received data, then sends `send`
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Step {
+    /// Text to wait for, the step sends straight away when empty
+    pub expect: String,
+    /// Sent as written, with escapes such as `\r` or `\x03`
+    pub send: String,
+    pub timeout_ms: u64,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            expect: String::new(),
+            send: String::new(),
+            timeout_ms: SEQUENCE_STEP_TIMEOUT_MS,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Sequence {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Self {
+            name: String::from("New sequence"),
+            steps: vec![Step::default()],
+        }
+    }
+}
+
+/// Sequences for one kind of device, such as a board and its bootloader
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub sequences: Vec<Sequence>,
+}
+
+impl Profile {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            sequences: Vec::new(),
+        }
+    }
+}
+
+/// What a running sequence asks of its session after being fed
+#[derive(Debug, Default)]
+pub struct Output {
+    /// Bytes to write to the connection, in order
+    pub send: Vec<Vec<u8>>,
+    pub log: Vec<String>,
+    /// Set once the sequence is over, with why it failed if it did
+    pub finished: Option<Result<(), String>>,
+}
+
+/// A sequence being run against a session's received data
+#[derive(Debug)]
+pub struct SequenceRun {
+    pub sequence: Sequence,
+    step: usize,
+    /// Received text not yet matched by a step
+    buffer: String,
+    /// When the current step times out
+    deadline: Instant,
+}
+
+impl SequenceRun {
+    pub fn new(sequence: Sequence, now: Instant) -> Self {
+        let deadline = now + step_timeout(sequence.steps.first());
+        Self {
+            sequence,
+            step: 0,
+            buffer: String::new(),
+            deadline,
+        }
+    }
+
+    /// Runs the steps that do not wait for anything
+    pub fn start(&mut self, now: Instant, out: &mut Output) {
+        self.advance(now, out);
+    }
+
+    pub fn received(&mut self, data: &[u8], now: Instant, out: &mut Output) {
+        self.buffer.push_str(&String::from_utf8_lossy(data));
+        keep_newest(&mut self.buffer, SEQUENCE_BUFFER_LIMIT);
+        self.advance(now, out);
+    }
+
+    /// Fails the sequence once the current step waited too long
+    pub fn poll(&mut self, now: Instant, out: &mut Output) {
+        if let Some(step) = self.sequence.steps.get(self.step) {
+            if now >= self.deadline {
+                out.finished = Some(Err(format!(
+                    "step {} timed out after {} ms waiting for '{}'",
+                    self.step + 1,
+                    step.timeout_ms,
+                    step.expect
+                )));
+            }
+        }
+    }
+
+    /// Steps done and the number of steps
+    pub fn progress(&self) -> (usize, usize) {
+        (self.step, self.sequence.steps.len())
+    }
+
+    fn advance(&mut self, now: Instant, out: &mut Output) {
+        while let Some(step) = self.sequence.steps.get(self.step) {
+            if !step.expect.is_empty() {
+                match self.buffer.find(&step.expect) {
+                    Some(start) => {
+                        self.buffer.drain(..start + step.expect.len());
+                    }
+                    None => return,
+                }
+            }
+
+            if !step.send.is_empty() {
+                match Encoding::AsciiEscaped.encode(&step.send) {
+                    Ok(bytes) => out.send.push(bytes),
+                    Err(e) => {
+                        out.finished = Some(Err(format!("step {}: {}", self.step + 1, e)));
+                        return;
+                    }
+                }
+            }
+            out.log.push(match step.expect.is_empty() {
+                true => format!("Step {}: sent '{}'", self.step + 1, step.send),
+                false => format!(
+                    "Step {}: got '{}', sent '{}'",
+                    self.step + 1,
+                    step.expect,
+                    step.send
+                ),
+            });
+
+            self.step += 1;
+            self.deadline = now + step_timeout(self.sequence.steps.get(self.step));
+        }
+        out.finished = Some(Ok(()));
+    }
+}
+
+fn step_timeout(step: Option<&Step>) -> std::time::Duration {
+    std::time::Duration::from_millis(step.map_or(0, |step| step.timeout_ms))
+}
+
+/// Profiles of wait-and-respond sequences, shown below the macros
+pub struct SequencePanel {
+    profiles: Vec<Profile>,
+    /// Index of the profile whose sequences are shown
+    profile: usize,
+    /// Sequence whose steps are shown for editing
+    editing: Option<usize>,
+    error: Option<String>,
+    /// The saved file could not be read, saving would overwrite it
+    load_failed: bool,
+    /// Edits typed since the last save
+    unsaved: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum SequenceMessage {
+    ProfileSelected(String),
+    ProfileAdded,
+    ProfileRenamed(String),
+    /// Enter pressed in the profile name
+    ProfileRenameDone,
+    ProfileRemoved,
+    /// Runs the sequence with this index on the active session
+    RunPressed(usize),
+    StopPressed,
+    AddPressed,
+    EditPressed(usize),
+    EditDone,
+    RemovePressed(usize),
+    Changed(usize, Sequence),
+}
+
+impl Default for SequencePanel {
+    fn default() -> Self {
+        let (mut profiles, error) = match load_json(Path::new(SEQUENCES_FILE)) {
+            Ok(profiles) => (profiles, None),
+            Err(e) => {
+                println!("Cannot load sequences: {:?}", e);
+                (Vec::new(), Some(format!("{:#}, changes are not saved", e)))
+            }
+        };
+        let load_failed = error.is_some();
+        if profiles.is_empty() {
+            profiles.push(Profile::new(String::from("Default")));
+        }
+        Self {
+            profiles,
+            profile: 0,
+            editing: None,
+            error,
+            load_failed,
+            unsaved: false,
+        }
+    }
+}
+
+impl SequencePanel {
+    pub fn update(&mut self, message: SequenceMessage) {
+        match message {
+            SequenceMessage::RunPressed(index) => {
+                println!("Sequence {} run pressed", index);
+                return;
+            }
+            SequenceMessage::StopPressed => {
+                println!("Sequence stop pressed");
+                return;
+            }
+            SequenceMessage::ProfileSelected(name) => {
+                if let Some(index) = self.profiles.iter().position(|p| p.name == name) {
+                    self.profile = index;
+                    self.editing = None;
+                }
+                if !self.unsaved {
+                    return;
+                }
+            }
+            SequenceMessage::ProfileAdded => {
+                // Names have to be unique to be told apart in the picker
+                let name = (self.profiles.len() + 1..)
+                    .map(|number| format!("Profile {}", number))
+                    .find(|name| self.profiles.iter().all(|p| &p.name != name))
+                    .unwrap_or_default();
+                self.profiles.push(Profile::new(name));
+                self.profile = self.profiles.len() - 1;
+                self.editing = None;
+            }
+            SequenceMessage::ProfileRenamed(name) => {
+                if !self.profiles.iter().any(|p| p.name == name) {
+                    self.profiles[self.profile].name = name;
+                    self.unsaved = true;
+                }
+                return;
+            }
+            SequenceMessage::ProfileRenameDone => {}
+            SequenceMessage::ProfileRemoved => {
+                self.profiles.remove(self.profile);
+                if self.profiles.is_empty() {
+                    self.profiles.push(Profile::new(String::from("Default")));
+                }
+                self.profile = self.profile.min(self.profiles.len() - 1);
+                self.editing = None;
+            }
+            SequenceMessage::AddPressed => {
+                let sequences = &mut self.profiles[self.profile].sequences;
+                sequences.push(Sequence::default());
+                self.editing = Some(sequences.len() - 1);
+            }
+            SequenceMessage::EditPressed(index) => {
+                self.editing = Some(index);
+                return;
+            }
+            SequenceMessage::EditDone => {
+                self.editing = None;
+            }
+            SequenceMessage::RemovePressed(index) => {
+                let sequences = &mut self.profiles[self.profile].sequences;
+                if index < sequences.len() {
+                    sequences.remove(index);
+                }
+                self.editing = None;
+            }
+            SequenceMessage::Changed(index, changed) => {
+                if let Some(existing) = self.profiles[self.profile].sequences.get_mut(index) {
+                    *existing = changed;
+                    self.unsaved = true;
+                }
+                return;
+            }
+        }
+
+        self.save();
+    }
+
+    /// Writes the profiles unless the file could not be read, keeping it for
+    /// the user to repair
+    fn save(&mut self) {
+        if self.load_failed {
+            return;
+        }
+        self.error = match save_json(Path::new(SEQUENCES_FILE), &self.profiles) {
+            Ok(()) => {
+                self.unsaved = false;
+                None
+            }
+            Err(e) => {
+                println!("Cannot save sequences: {:?}", e);
+                Some(format!("{:#}", e))
+            }
+        };
+    }
+
+    /// Sequence with this index in the selected profile
+    pub fn get(&self, index: usize) -> Option<&Sequence> {
+        self.profiles[self.profile].sequences.get(index)
+    }
+
+    /// `running` is the sequence the active session runs, if any
+    pub fn view(&self, running: Option<&SequenceRun>) -> Element<'_, Message> {
+        let profile = &self.profiles[self.profile];
+        let names: Vec<String> = self.profiles.iter().map(|p| p.name.clone()).collect();
+
+        let mut sequences = column![
+            text("sequences:"),
+            row![
+                pick_list(names, Some(profile.name.clone()), |name| {
+                    Message::SequenceMessage(SequenceMessage::ProfileSelected(name))
+                }),
+                button("+").on_press(Message::SequenceMessage(SequenceMessage::ProfileAdded)),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+        ]
+        .spacing(5);
+
+        if let Some(run) = running {
+            let (done, total) = run.progress();
+            sequences = sequences.push(
+                row![
+                    text(format!(
+                        "● {} step {}/{}",
+                        run.sequence.name,
+                        (done + 1).min(total),
+                        total
+                    ))
+                    .style(text::success),
+                    button("Stop").on_press(Message::SequenceMessage(SequenceMessage::StopPressed)),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center),
+            );
+        }
+
+        for (index, sequence) in profile.sequences.iter().enumerate() {
+            let mut run_button = button(text(sequence.name.clone()));
+            if running.is_none() {
+                run_button = run_button
+                    .on_press(Message::SequenceMessage(SequenceMessage::RunPressed(index)));
+            }
+            sequences = sequences.push(
+                row![
+                    run_button,
+                    button("Edit")
+                        .style(button::secondary)
+                        .on_press(Message::SequenceMessage(SequenceMessage::EditPressed(
+                            index
+                        ))),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center),
+            );
+
+            if self.editing == Some(index) {
+                sequences = sequences.push(self.editor(index, sequence));
+            }
+        }
+
+        sequences = sequences.push(
+            row![button("Add sequence")
+                .on_press(Message::SequenceMessage(SequenceMessage::AddPressed)),]
+            .spacing(5),
+        );
+        sequences = sequences.push(
+            row![
+                text_input("Profile name", &profile.name)
+                    .on_input(|name| {
+                        Message::SequenceMessage(SequenceMessage::ProfileRenamed(name))
+                    })
+                    .on_submit(Message::SequenceMessage(SequenceMessage::ProfileRenameDone)),
+                button("Remove profile")
+                    .style(button::danger)
+                    .on_press(Message::SequenceMessage(SequenceMessage::ProfileRemoved)),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+        );
+        if let Some(error) = &self.error {
+            sequences = sequences.push(text(error.clone()).style(text::danger));
+        }
+
+        container(sequences).into()
+    }
+
+    fn editor<'a>(&self, index: usize, sequence: &'a Sequence) -> Element<'a, Message> {
+        let changed =
+            move |sequence| Message::SequenceMessage(SequenceMessage::Changed(index, sequence));
+
+        let mut editor = column![text_input("Name", &sequence.name).on_input(move |name| {
+            changed(Sequence {
+                name,
+                ..sequence.clone()
+            })
+        })]
+        .spacing(5);
+
+        for (number, step) in sequence.steps.iter().enumerate() {
+            let step_changed = move |step: Step| {
+                let mut sequence = sequence.clone();
+                sequence.steps[number] = step;
+                changed(sequence)
+            };
+            editor = editor.push(
+                row![
+                    text(format!("{}.", number + 1)),
+                    text_input("Wait for", &step.expect).on_input(move |expect| {
+                        step_changed(Step {
+                            expect,
+                            ..step.clone()
+                        })
+                    }),
+                    text_input("Send, e.g. root\\r", &step.send).on_input(move |send| {
+                        step_changed(Step {
+                            send,
+                            ..step.clone()
+                        })
+                    }),
+                    pick_list(
+                        SEQUENCE_TIMEOUTS_MS,
+                        Some(step.timeout_ms),
+                        move |timeout_ms| {
+                            step_changed(Step {
+                                timeout_ms,
+                                ..step.clone()
+                            })
+                        }
+                    ),
+                    text("ms"),
+                    button("x").style(button::danger).on_press({
+                        let mut sequence = sequence.clone();
+                        sequence.steps.remove(number);
+                        changed(sequence)
+                    }),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center),
+            );
+        }
+
+        let mut with_step = sequence.clone();
+        with_step.steps.push(Step::default());
+        editor
+            .push(
+                row![
+                    button("Add step").on_press(changed(with_step)),
+                    button("Done").on_press(Message::SequenceMessage(SequenceMessage::EditDone)),
+                    button("Remove")
+                        .style(button::danger)
+                        .on_press(Message::SequenceMessage(SequenceMessage::RemovePressed(
+                            index
+                        ))),
+                ]
+                .spacing(5),
+            )
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn step(expect: &str, send: &str) -> Step {
+        Step {
+            expect: String::from(expect),
+            send: String::from(send),
+            timeout_ms: 1000,
+        }
+    }
+
+    fn started(steps: Vec<Step>, now: Instant) -> (SequenceRun, Output) {
+        let sequence = Sequence {
+            name: String::from("test"),
+            steps,
+        };
+        let mut run = SequenceRun::new(sequence, now);
+        let mut out = Output::default();
+        run.start(now, &mut out);
+        (run, out)
+    }
+
+    #[test]
+    fn expect_matches_across_split_chunks() {
+        let now = Instant::now();
+        let (mut run, out) = started(vec![step("login:", "root\\r")], now);
+        assert!(out.send.is_empty());
+        assert!(out.finished.is_none());
+
+        let mut out = Output::default();
+        run.received(b"board log", now, &mut out);
+        assert!(out.send.is_empty());
+        run.received(b"in: ", now, &mut out);
+        assert_eq!(out.send, [b"root\r".to_vec()]);
+        assert_eq!(out.log, ["Step 1: got 'login:', sent 'root\\r'"]);
+        assert_eq!(out.finished, Some(Ok(())));
+    }
+
+    #[test]
+    fn matched_text_is_drained_from_the_buffer() {
+        let now = Instant::now();
+        let (mut run, _) = started(vec![step("OK", "a"), step("OK", "b")], now);
+
+        let mut out = Output::default();
+        run.received(b"OK", now, &mut out);
+        // The same OK cannot satisfy the second step
+        assert_eq!(out.send, [b"a".to_vec()]);
+        assert!(out.finished.is_none());
+        assert_eq!(run.progress(), (1, 2));
+
+        run.received(b" and OK", now, &mut out);
+        assert_eq!(out.send, [b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(out.finished, Some(Ok(())));
+    }
+
+    #[test]
+    fn empty_expect_sends_straight_away() {
+        let now = Instant::now();
+        let (run, out) = started(vec![step("", "\\x03"), step("", "reset\\r\\n")], now);
+        assert_eq!(out.send, [vec![0x03], b"reset\r\n".to_vec()]);
+        assert_eq!(
+            out.log,
+            ["Step 1: sent '\\x03'", "Step 2: sent 'reset\\r\\n'"]
+        );
+        assert_eq!(out.finished, Some(Ok(())));
+        assert_eq!(run.progress(), (2, 2));
+    }
+
+    #[test]
+    fn invalid_escape_fails_the_step() {
+        let now = Instant::now();
+        let (_, out) = started(vec![step("", "ok"), step("", "bad\\q")], now);
+        assert_eq!(out.send, [b"ok".to_vec()]);
+        assert_eq!(
+            out.finished,
+            Some(Err(String::from("step 2: Unknown escape '\\q'")))
+        );
+    }
+
+    #[test]
+    fn each_step_has_its_own_deadline() {
+        let start = Instant::now();
+        let (mut run, _) = started(vec![step("a", ""), step("b", "")], start);
+
+        let mut out = Output::default();
+        run.poll(start + Duration::from_millis(999), &mut out);
+        assert!(out.finished.is_none());
+
+        // Matching the first step restarts the clock for the second
+        let matched = start + Duration::from_millis(900);
+        run.received(b"a", matched, &mut out);
+        run.poll(start + Duration::from_millis(1500), &mut out);
+        assert!(out.finished.is_none());
+
+        run.poll(matched + Duration::from_millis(1000), &mut out);
+        assert_eq!(
+            out.finished,
+            Some(Err(String::from(
+                "step 2 timed out after 1000 ms waiting for 'b'"
+            )))
+        );
+    }
+}
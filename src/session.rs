@@ -0,0 +1,1120 @@
+use std::collections::HashSet;
+use std::time::{Instant, SystemTime};
+
+use anyhow::{anyhow, Result};
+use iced::Task;
+
+use crate::controller::{ConnectionEvent, ConnectionStatus, ReconnectPolicy, TerminalController};
+use crate::encoding::Encoding;
+use crate::file_sender::{FileSender, SendFileSettings};
+use crate::file_transfer::zmodem::{AutoStart, AutoStartDetector, ZmodemSender};
+use crate::file_transfer::{self, Output, Protocol, Transfer, TransferFile, TransferSettings};
+use crate::hex;
+use crate::line_assembler::{ReceiveMode, ReceivedData};
+use crate::line_ending::LineEndings;
+use crate::macros::Macro;
+use crate::myserial::{LineSettings, SerialPortInfo};
+use crate::script::{Script, ScriptEvent};
+use crate::scrollback::Source;
+use crate::sequence::{self, Sequence, SequenceRun};
+use crate::session_log::{LogSettings, SessionLog};
+use crate::terminal::{TerminalPane, TerminalPaneMessage};
+use crate::transport::{Endpoint, ModemState, TcpTarget, TransportKind};
+use crate::Message;
+
+/// One connection shown as a tab, with its own terminal, port and status
+pub struct Session {
+    pub id: u32,
+    pub terminal: TerminalPane,
+    pub transport_kind: TransportKind,
+    pub selected_port: Option<SerialPortInfo>,
+    pub tcp_address: String,
+    pub line_settings: LineSettings,
+    pub line_endings: LineEndings,
+    pub receive_mode: ReceiveMode,
+    pub encoding: Encoding,
+    pub controller: Option<TerminalController>,
+    pub status: ConnectionStatus,
+    pub modem_state: Option<ModemState>,
+    pub virtual_peer: Option<String>,
+    pub reconnect_policy: ReconnectPolicy,
+    pub log_settings: LogSettings,
+    /// Open while logging to file is enabled
+    pub log: Option<SessionLog>,
+    pub send_file_settings: SendFileSettings,
+    /// Present while a file is being sent
+    pub file_sender: Option<FileSender>,
+    pub transfer_settings: TransferSettings,
+    /// Present while an XMODEM, YMODEM or ZMODEM transfer runs, received
+    /// data then goes to it instead of the terminal
+    pub transfer: Option<Box<dyn Transfer>>,
+    /// Ids of the transfer's writes not confirmed yet
+    transfer_writes: HashSet<u64>,
+    auto_start: AutoStartDetector,
+    pub script_path: String,
+    /// Present while a script runs
+    pub script: Option<Script>,
+    /// Present while a wait-and-respond sequence runs
+    pub sequence: Option<SequenceRun>,
+}
+
+impl Session {
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            terminal: TerminalPane::default(),
+            transport_kind: TransportKind::Serial,
+            selected_port: None,
+            tcp_address: String::new(),
+            line_settings: LineSettings::default(),
+            line_endings: LineEndings::default(),
+            receive_mode: ReceiveMode::default(),
+            encoding: Encoding::default(),
+            controller: None,
+            status: ConnectionStatus::Disconnected,
+            modem_state: None,
+            virtual_peer: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            log_settings: LogSettings::default(),
+            log: None,
+            send_file_settings: SendFileSettings::default(),
+            file_sender: None,
+            transfer_settings: TransferSettings::default(),
+            transfer: None,
+            transfer_writes: HashSet::new(),
+            auto_start: AutoStartDetector::default(),
+            script_path: String::new(),
+            script: None,
+            sequence: None,
+        }
+    }
+
+    /// Label shown on the session's tab
+    pub fn title(&self) -> String {
+        match &self.status {
+            ConnectionStatus::Connecting(name)
+            | ConnectionStatus::Connected(name)
+            | ConnectionStatus::WaitingForDevice(name) => name.clone(),
+            _ => match self.endpoint() {
+                Ok(endpoint) => endpoint.name(),
+                Err(_) => format!("Session {}", self.id),
+            },
+        }
+    }
+
+    /// Endpoint described by the session's sidebar settings
+    pub fn endpoint(&self) -> Result<Endpoint> {
+        match self.transport_kind {
+            TransportKind::Serial => match &self.selected_port {
+                Some(port) => Ok(Endpoint::Serial(
+                    port.clone().with_settings(&self.line_settings),
+                )),
+                None => Err(anyhow!("No port selected")),
+            },
+            TransportKind::Tcp => Ok(Endpoint::Tcp(self.tcp_address.parse()?)),
+            TransportKind::Virtual => Ok(Endpoint::Virtual),
+            TransportKind::Rfc2217 => {
+                let target: TcpTarget = self.tcp_address.parse()?;
+                let sinfo = SerialPortInfo::new(
+                    target.to_string(),
+                    self.line_settings.speed,
+                    self.line_settings.data_bits,
+                    self.line_settings.parity,
+                    self.line_settings.stop_bits,
+                    self.line_settings.flow_control,
+                );
+                Ok(Endpoint::Rfc2217(target, sinfo))
+            }
+        }
+    }
+
+    pub fn update(&mut self, msg: TerminalPaneMessage) -> Task<Message> {
+        match &msg {
+            TerminalPaneMessage::InputSubmit => {
+                // Send data to connected port if available
+                if let Some(controller) = &self.controller {
+                    let data_to_send = self.terminal.input_value.clone();
+                    println!(
+                        "Session {}: Attempting to send data: '{}'",
+                        self.id, data_to_send
+                    );
+
+                    if data_to_send.trim().is_empty() {
+                        println!("Session {}: Not sending empty data", self.id);
+                    } else if self.terminal.hex_mode {
+                        // Hex input goes out as raw bytes, without a line ending
+                        match hex::parse(&data_to_send) {
+                            Ok(bytes) => match controller.push(bytes.clone()) {
+                                Ok(_) => {
+                                    let text = hex::format(&bytes).into_bytes();
+                                    self.record_sent(&bytes, text, Source::Input);
+                                }
+                                Err(e) => {
+                                    println!("Session {}: Failed to queue data: {:?}", self.id, e);
+                                    self.terminal
+                                        .add_message(&format!("Error sending data: {}", e));
+                                }
+                            },
+                            Err(e) => {
+                                println!("Session {}: Invalid hex input: {}", self.id, e);
+                            }
+                        }
+                    } else {
+                        let encoded = self.encoding.encode(&data_to_send);
+                        let pushed = encoded
+                            .and_then(|bytes| Ok((controller.push_line(bytes.clone())?, bytes)));
+                        match pushed {
+                            Ok((raw, text)) => {
+                                println!(
+                                    "Session {}: Successfully queued data for transmission",
+                                    self.id
+                                );
+                                self.record_sent(&raw, text, Source::Input);
+                            }
+                            Err(e) => {
+                                println!("Session {}: Failed to queue data: {:?}", self.id, e);
+                                self.terminal
+                                    .add_message(&format!("Error sending data: {}", e));
+                            }
+                        }
+                    }
+                } else {
+                    println!(
+                        "Session {}: No controller available for sending data",
+                        self.id
+                    );
+                    self.terminal.add_message("Not connected to any port");
+                }
+            }
+            TerminalPaneMessage::InputChanged(_)
+            | TerminalPaneMessage::HexModeToggled(_)
+            | TerminalPaneMessage::DisplayModeSelected(_)
+            | TerminalPaneMessage::TimestampsChanged(_)
+            | TerminalPaneMessage::Scrolled(_)
+            | TerminalPaneMessage::SearchOpened
+            | TerminalPaneMessage::SearchClosed
+            | TerminalPaneMessage::SearchChanged(_)
+            | TerminalPaneMessage::SearchRegexToggled(_)
+            | TerminalPaneMessage::SearchCaseToggled(_)
+            | TerminalPaneMessage::SearchNext
+            | TerminalPaneMessage::SearchPrevious
+            | TerminalPaneMessage::HistoryPrevious
+            | TerminalPaneMessage::HistoryNext
+            | TerminalPaneMessage::HistorySearchOpened
+            | TerminalPaneMessage::HistorySearchChanged(_)
+            | TerminalPaneMessage::HistorySearchAccepted
+            | TerminalPaneMessage::EscapePressed => {}
+        }
+        self.terminal.update(msg)
+    }
+
+    pub fn connect(&mut self) {
+        match self.endpoint() {
+            Ok(endpoint) => {
+                if !self.status.is_active() {
+                    println!("Session {}: Attempting to connect to {}", self.id, endpoint);
+                    let mut controller = TerminalController::new(self.id);
+                    controller.set_reconnect_policy(self.reconnect_policy);
+                    controller.set_line_endings(self.line_endings);
+                    controller.set_receive_mode(self.receive_mode);
+
+                    // The endpoint is opened by the runner task, the outcome
+                    // arrives as a Connected or ConnectFailed event
+                    controller.create_stream(&endpoint);
+                    self.controller = Some(controller);
+                    self.status = ConnectionStatus::Connecting(endpoint.name());
+                    self.terminal
+                        .add_message(&format!("Connecting to {}...", endpoint));
+                } else {
+                    println!("Session {}: Already connected", self.id);
+                }
+            }
+            Err(e) => {
+                self.terminal.add_message(&e.to_string());
+                println!("Session {}: Cannot connect: {}", self.id, e);
+            }
+        }
+    }
+
+    /// Changes how a lost device is waited for, taking effect immediately on a
+    /// running connection
+    pub fn set_reconnect_policy(&mut self, mut policy: ReconnectPolicy) {
+        // The longest wait can not be shorter than the first
+        policy.max_backoff = policy.max_backoff.max(policy.initial_backoff);
+        self.reconnect_policy = policy;
+        if let Some(controller) = &mut self.controller {
+            controller.set_reconnect_policy(policy);
+        }
+    }
+
+    /// Changes the line endings, taking effect immediately on a running connection
+    pub fn set_line_endings(&mut self, line_endings: LineEndings) {
+        self.line_endings = line_endings;
+        if let Some(controller) = &mut self.controller {
+            controller.set_line_endings(line_endings);
+        }
+    }
+
+    /// Applies line settings to the open port too, sends and transfers carry on
+    pub fn set_line_settings(&mut self, line_settings: LineSettings) {
+        self.line_settings = line_settings;
+        if let Some(controller) = &mut self.controller {
+            controller.set_line_settings(line_settings);
+        }
+    }
+
+    /// Changes the encoding for both received data and typed input
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+        self.terminal.set_encoding(encoding);
+    }
+
+    /// Changes how received data is grouped, showing any partial line first
+    pub fn set_receive_mode(&mut self, receive_mode: ReceiveMode) {
+        self.receive_mode = receive_mode;
+        if let Some(controller) = &mut self.controller {
+            if let Some(received) = controller.set_receive_mode(receive_mode) {
+                self.show_received(received);
+            }
+        }
+    }
+
+    /// Shows a partial received line once it has been idle long enough
+    pub fn flush_idle_line(&mut self) {
+        if let Some(controller) = &mut self.controller {
+            if let Some(received) = controller.flush_idle_line() {
+                self.show_received(received);
+            }
+        }
+    }
+
+    fn flush_line(&mut self) {
+        if let Some(controller) = &mut self.controller {
+            if let Some(received) = controller.flush_line() {
+                self.show_received(received);
+            }
+        }
+    }
+
+    fn show_received(&mut self, received: ReceivedData) {
+        if let Some(log) = &mut self.log {
+            let text = self.encoding.decode(&received.text);
+            let result = log.received_line(&text, received.time);
+            self.check_log(result);
+        }
+        self.terminal
+            .add_received(&received.raw, received.text, received.time);
+
+        // Answer status queries from device shells, such as cursor position reports
+        let responses = self.terminal.take_responses();
+        if !responses.is_empty() {
+            if let Some(controller) = &self.controller {
+                if let Err(e) = controller.push(responses) {
+                    println!(
+                        "Session {}: Failed to answer terminal query: {:?}",
+                        self.id, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Shows sent bytes in the terminal and writes them to the log
+    fn record_sent(&mut self, raw: &[u8], text: Vec<u8>, source: Source) {
+        self.log_sent(raw, &text);
+        self.terminal.add_sent(raw, text, source);
+    }
+
+    fn log_sent(&mut self, raw: &[u8], text: &[u8]) {
+        if let Some(log) = &mut self.log {
+            let result = log.sent(raw, &self.encoding.decode(text), SystemTime::now());
+            self.check_log(result);
+        }
+    }
+
+    /// Reads the file chosen in the sidebar without blocking the UI, sending
+    /// starts once it arrives as a `Message::FileLoaded`
+    pub fn send_file(&mut self) -> Task<Message> {
+        if !matches!(self.status, ConnectionStatus::Connected(_)) {
+            self.terminal.add_message("Not connected to any port");
+            return Task::none();
+        }
+        if self.file_sender.is_some() {
+            self.terminal.add_message("A file is already being sent");
+            return Task::none();
+        }
+        let path = self.send_file_settings.path.trim().to_string();
+        if path.is_empty() {
+            self.terminal.add_message("No file selected");
+            return Task::none();
+        }
+
+        println!("Session {}: Reading {} to send", self.id, path);
+        let id = self.id;
+        Task::perform(tokio::fs::read(path.clone()), move |result| {
+            Message::FileLoaded(id, path.clone(), result.map_err(|e| e.to_string()))
+        })
+    }
+
+    pub fn start_file_send(&mut self, path: String, data: Result<Vec<u8>, String>) {
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Session {}: Cannot read {}: {}", self.id, path, e);
+                self.terminal
+                    .add_message(&format!("Cannot read {}: {}", path, e));
+                return;
+            }
+        };
+        if self.file_sender.is_some() {
+            return;
+        }
+
+        println!(
+            "Session {}: Sending {} ({} bytes)",
+            self.id,
+            path,
+            data.len()
+        );
+        self.terminal
+            .add_message(&format!("Sending {} ({} bytes)...", path, data.len()));
+        self.file_sender = Some(FileSender::new(path, data, &self.send_file_settings));
+        self.send_next_chunk();
+    }
+
+    /// Queues the next chunk of the file being sent, or reports it as done
+    pub fn send_next_chunk(&mut self) {
+        let Some(sender) = &mut self.file_sender else {
+            return;
+        };
+        if sender.is_done() {
+            let (written, _) = sender.progress();
+            println!("Session {}: Sent {}", self.id, sender.path);
+            self.terminal
+                .add_message(&format!("Sent {} ({} bytes)", sender.path, written));
+            self.file_sender = None;
+            return;
+        }
+        let Some(chunk) = sender.next_chunk() else {
+            return;
+        };
+
+        let pushed = match &self.controller {
+            Some(controller) => controller.push(chunk.clone()),
+            None => Err(anyhow!("Not connected")),
+        };
+        match pushed {
+            Ok(write_id) => {
+                sender.queued(write_id, chunk.len());
+                self.log_sent(&chunk, &chunk);
+            }
+            Err(e) => self.stop_file_send(&e.to_string()),
+        }
+    }
+
+    /// Stops sending the current file, a chunk already queued still goes out
+    pub fn stop_file_send(&mut self, reason: &str) {
+        if let Some(sender) = self.file_sender.take() {
+            let (written, total) = sender.progress();
+            println!(
+                "Session {}: Stopped sending {}: {}",
+                self.id, sender.path, reason
+            );
+            self.terminal.add_message(&format!(
+                "Stopped sending {} after {} of {} bytes: {}",
+                sender.path, written, total, reason
+            ));
+        }
+    }
+
+    /// Whether the connection is free for a protocol transfer
+    fn can_start_transfer(&mut self) -> bool {
+        if !matches!(self.status, ConnectionStatus::Connected(_)) {
+            self.terminal.add_message("Not connected to any port");
+            false
+        } else if self.transfer.is_some() {
+            self.terminal.add_message("A transfer is already running");
+            false
+        } else if self.file_sender.is_some() {
+            self.terminal.add_message("A file is already being sent");
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Reads the files chosen in the sidebar without blocking the UI, the
+    /// transfer starts once they arrive as a `Message::TransferFilesLoaded`.
+    /// `answering` is set when a remote `rz` already waits for them
+    pub fn send_transfer_files(&mut self, answering: bool) -> Task<Message> {
+        if !self.can_start_transfer() {
+            return Task::none();
+        }
+        let paths = self.transfer_settings.paths();
+        if paths.is_empty() {
+            self.terminal.add_message("No files selected to send");
+            return Task::none();
+        }
+        let protocol = if answering {
+            Protocol::Zmodem
+        } else {
+            self.transfer_settings.protocol
+        };
+        if paths.len() > 1 && !protocol.is_batch() {
+            self.terminal
+                .add_message(&format!("{} sends a single file", protocol));
+            return Task::none();
+        }
+
+        println!(
+            "Session {}: Reading {} file(s) to send with {}",
+            self.id,
+            paths.len(),
+            protocol
+        );
+        let id = self.id;
+        Task::perform(file_transfer::read_files(paths), move |files| {
+            Message::TransferFilesLoaded(id, protocol, answering, files)
+        })
+    }
+
+    pub fn start_transfer_send(
+        &mut self,
+        protocol: Protocol,
+        answering: bool,
+        files: Result<Vec<TransferFile>, String>,
+    ) {
+        let files = match files {
+            Ok(files) => files,
+            Err(e) => {
+                println!("Session {}: {}", self.id, e);
+                self.terminal.add_message(&e);
+                return;
+            }
+        };
+        if self.transfer.is_some() {
+            return;
+        }
+        let transfer: Box<dyn Transfer> = if answering {
+            Box::new(ZmodemSender::new(files, false))
+        } else {
+            file_transfer::sender(protocol, files)
+        };
+        self.start_transfer(transfer);
+    }
+
+    /// Waits for the other side to send with the protocol chosen in the sidebar
+    pub fn receive_transfer(&mut self) {
+        if self.can_start_transfer() {
+            self.start_transfer(file_transfer::receiver(self.transfer_settings.protocol));
+        }
+    }
+
+    fn start_transfer(&mut self, mut transfer: Box<dyn Transfer>) {
+        // Whatever arrived before belongs to the terminal
+        self.flush_line();
+        println!("Session {}: Starting {}", self.id, transfer.name());
+        self.terminal
+            .add_message(&format!("--- {} started ---", transfer.name()));
+
+        let mut out = Output::default();
+        transfer.start(Instant::now(), &mut out);
+        self.transfer = Some(transfer);
+        self.transfer_writes.clear();
+        self.apply_transfer_output(out);
+    }
+
+    fn transfer_received(&mut self, data: &[u8]) {
+        if let Some(transfer) = &mut self.transfer {
+            let mut out = Output::default();
+            transfer.receive(data, Instant::now(), &mut out);
+            self.apply_transfer_output(out);
+        }
+    }
+
+    /// Repeats packets or gives up once the other side has gone quiet
+    pub fn poll_transfer(&mut self) {
+        if let Some(transfer) = &mut self.transfer {
+            let mut out = Output::default();
+            transfer.poll(Instant::now(), &mut out);
+            self.apply_transfer_output(out);
+        }
+    }
+
+    /// Tells the other side to stop and ends the transfer
+    pub fn cancel_transfer(&mut self) {
+        if let Some(transfer) = &mut self.transfer {
+            let mut out = Output::default();
+            transfer.cancel(&mut out);
+            self.apply_transfer_output(out);
+        }
+    }
+
+    /// Ends the transfer without a word to the other side, which is gone
+    fn stop_transfer(&mut self, reason: &str) {
+        if let Some(transfer) = self.transfer.take() {
+            println!(
+                "Session {}: Stopped {}: {}",
+                self.id,
+                transfer.name(),
+                reason
+            );
+            self.terminal
+                .add_message(&format!("--- {} stopped: {} ---", transfer.name(), reason));
+        }
+    }
+
+    /// Sends what the transfer asked for, logs its progress to the terminal
+    /// and saves the files it received
+    fn apply_transfer_output(&mut self, out: Output) {
+        let Some(transfer) = &self.transfer else {
+            return;
+        };
+        let name = transfer.name();
+
+        if !out.send.is_empty() {
+            let pushed = match &self.controller {
+                Some(controller) => controller.push(out.send),
+                None => Err(anyhow!("Not connected")),
+            };
+            match pushed {
+                Ok(write_id) => {
+                    self.transfer_writes.insert(write_id);
+                }
+                Err(e) => {
+                    self.stop_transfer(&e.to_string());
+                    return;
+                }
+            }
+        }
+        for line in out.log {
+            self.terminal.add_message(&format!("{}: {}", name, line));
+        }
+        for file in out.received {
+            match file_transfer::save_file(&file) {
+                Ok(path) => {
+                    println!("Session {}: Saved {}", self.id, path.display());
+                    self.terminal
+                        .add_message(&format!("{}: Saved {}", name, path.display()));
+                }
+                Err(e) => {
+                    println!("Session {}: Cannot save {}: {:?}", self.id, file.name, e);
+                    self.terminal.add_message(&format!("{}: {:#}", name, e));
+                }
+            }
+        }
+
+        if let Some(result) = out.finished {
+            self.transfer = None;
+            match result {
+                Ok(()) => {
+                    println!("Session {}: {} finished", self.id, name);
+                    self.terminal
+                        .add_message(&format!("--- {} finished ---", name));
+                }
+                Err(reason) => {
+                    println!("Session {}: {} failed: {}", self.id, name, reason);
+                    self.terminal
+                        .add_message(&format!("--- {} failed: {} ---", name, reason));
+                }
+            }
+        }
+    }
+
+    /// Reads the script chosen in the sidebar, it starts once it arrives as
+    /// a `Message::ScriptLoaded`
+    pub fn run_script(&mut self) -> Task<Message> {
+        if self.script.is_some() {
+            self.terminal.add_message("A script is already running");
+            return Task::none();
+        }
+        let path = self.script_path.trim().to_string();
+        if path.is_empty() {
+            self.terminal.add_message("No script selected");
+            return Task::none();
+        }
+
+        println!("Session {}: Reading script {}", self.id, path);
+        let id = self.id;
+        Task::perform(tokio::fs::read_to_string(path.clone()), move |result| {
+            Message::ScriptLoaded(id, path.clone(), result.map_err(|e| e.to_string()))
+        })
+    }
+
+    pub fn start_script(&mut self, path: String, source: Result<String, String>) {
+        let source = match source {
+            Ok(source) => source,
+            Err(e) => {
+                println!("Session {}: Cannot read {}: {}", self.id, path, e);
+                self.terminal
+                    .add_message(&format!("Cannot read {}: {}", path, e));
+                return;
+            }
+        };
+        if self.script.is_some() {
+            return;
+        }
+
+        let name = std::path::Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(path);
+        println!("Session {}: Running script {}", self.id, name);
+        self.terminal
+            .add_message(&format!("--- Script {} started ---", name));
+        self.script = Some(Script::new(name, source));
+    }
+
+    pub fn stop_script(&mut self) {
+        if let Some(script) = self.script.take() {
+            println!("Session {}: Stopped script {}", self.id, script.name);
+            self.terminal
+                .add_message(&format!("--- Script {} stopped ---", script.name));
+        }
+    }
+
+    pub fn handle_script_event(&mut self, event: ScriptEvent) {
+        let Some(script) = &mut self.script else {
+            return;
+        };
+        let name = script.name.clone();
+
+        match event {
+            ScriptEvent::Started(handle) => script.attach(handle),
+            ScriptEvent::Send(data) => {
+                let pushed = match &self.controller {
+                    Some(controller) => controller.push(data.clone()),
+                    None => Err(anyhow!("Not connected")),
+                };
+                match pushed {
+                    Ok(_) => self.record_sent(&data, data.clone(), Source::Script),
+                    Err(e) => self.script_failed(&e.to_string()),
+                }
+            }
+            ScriptEvent::SendLine(text) => {
+                let pushed = match &self.controller {
+                    Some(controller) => controller.push_line(text.clone()),
+                    None => Err(anyhow!("Not connected")),
+                };
+                match pushed {
+                    Ok(raw) => self.record_sent(&raw, text, Source::Script),
+                    Err(e) => self.script_failed(&e.to_string()),
+                }
+            }
+            ScriptEvent::Log(text) => {
+                self.terminal.add_message(&format!("[{}] {}", name, text));
+            }
+            ScriptEvent::SetPort(change) => {
+                let line_settings = change.apply(self.line_settings);
+                println!(
+                    "Session {}: Script changed line settings to {:?}",
+                    self.id, line_settings
+                );
+                self.set_line_settings(line_settings);
+            }
+            ScriptEvent::Finished(result) => {
+                self.script = None;
+                match result {
+                    Ok(()) => {
+                        println!("Session {}: Script {} finished", self.id, name);
+                        self.terminal
+                            .add_message(&format!("--- Script {} finished ---", name));
+                    }
+                    Err(e) => self.script_failed(&e),
+                }
+            }
+        }
+    }
+
+    fn script_failed(&mut self, reason: &str) {
+        let name = match self.script.take() {
+            Some(script) => script.name.clone(),
+            None => String::from("script"),
+        };
+        println!("Session {}: Script {} failed: {}", self.id, name, reason);
+        self.terminal
+            .add_message(&format!("--- Script {} failed: {} ---", name, reason));
+    }
+
+    /// Starts a wait-and-respond sequence on the open connection
+    pub fn run_sequence(&mut self, sequence: Sequence) {
+        if self.controller.is_none() {
+            self.terminal.add_message("Not connected to any port");
+            return;
+        }
+        if self.sequence.is_some() {
+            self.terminal.add_message("A sequence is already running");
+            return;
+        }
+        println!("Session {}: Running sequence {}", self.id, sequence.name);
+        self.terminal
+            .add_message(&format!("--- Sequence {} started ---", sequence.name));
+        let now = Instant::now();
+        let mut run = SequenceRun::new(sequence, now);
+        let mut out = sequence::Output::default();
+        run.start(now, &mut out);
+        self.sequence = Some(run);
+        self.apply_sequence_output(out);
+    }
+
+    /// Fails the running sequence once its current step timed out
+    pub fn poll_sequence(&mut self) {
+        if let Some(run) = &mut self.sequence {
+            let mut out = sequence::Output::default();
+            run.poll(Instant::now(), &mut out);
+            self.apply_sequence_output(out);
+        }
+    }
+
+    pub fn stop_sequence(&mut self, reason: &str) {
+        if let Some(run) = self.sequence.take() {
+            println!(
+                "Session {}: Sequence {} stopped: {}",
+                self.id, run.sequence.name, reason
+            );
+            self.terminal.add_message(&format!(
+                "--- Sequence {} stopped: {} ---",
+                run.sequence.name, reason
+            ));
+        }
+    }
+
+    fn apply_sequence_output(&mut self, out: sequence::Output) {
+        let Some(run) = &self.sequence else {
+            return;
+        };
+        let name = run.sequence.name.clone();
+
+        for data in out.send {
+            let pushed = match &self.controller {
+                Some(controller) => controller.push(data.clone()),
+                None => Err(anyhow!("Not connected")),
+            };
+            if let Err(e) = pushed {
+                self.sequence = None;
+                println!("Session {}: Sequence {} failed: {}", self.id, name, e);
+                self.terminal
+                    .add_message(&format!("--- Sequence {} failed: {} ---", name, e));
+                return;
+            }
+            self.record_sent(&data, data.clone(), Source::Sequence);
+        }
+        for line in out.log {
+            println!("Session {}: Sequence {}: {}", self.id, name, line);
+        }
+
+        match out.finished {
+            Some(Ok(())) => {
+                self.sequence = None;
+                println!("Session {}: Sequence {} finished", self.id, name);
+                self.terminal
+                    .add_message(&format!("--- Sequence {} finished ---", name));
+            }
+            Some(Err(e)) => {
+                self.sequence = None;
+                println!("Session {}: Sequence {} failed: {}", self.id, name, e);
+                self.terminal
+                    .add_message(&format!("--- Sequence {} failed: {} ---", name, e));
+            }
+            None => {}
+        }
+    }
+
+    /// Starts or stops writing traffic to a log file named after the endpoint
+    pub fn set_logging(&mut self, enabled: bool) {
+        if !enabled {
+            if let Some(log) = self.log.take() {
+                println!(
+                    "Session {}: Stopped logging to {}",
+                    self.id,
+                    log.path().display()
+                );
+                self.terminal
+                    .add_message(&format!("Stopped logging to {}", log.path().display()));
+            }
+            return;
+        }
+
+        if self.log.is_none() {
+            match SessionLog::open(&self.log_settings, &self.title()) {
+                Ok(log) => {
+                    println!("Session {}: Logging to {}", self.id, log.path().display());
+                    self.terminal
+                        .add_message(&format!("Logging to {}", log.path().display()));
+                    self.log = Some(log);
+                }
+                Err(e) => {
+                    println!("Session {}: Cannot start logging: {:?}", self.id, e);
+                    self.terminal
+                        .add_message(&format!("Cannot start logging: {:#}", e));
+                }
+            }
+        }
+    }
+
+    /// Changes the log settings, the format and TX option apply to a running log
+    pub fn set_log_settings(&mut self, settings: LogSettings) {
+        if let Some(log) = &mut self.log {
+            log.format = settings.format;
+            log.include_tx = settings.include_tx;
+        }
+        self.log_settings = settings;
+    }
+
+    /// Stops logging after a failed write so the error is only reported once
+    fn check_log(&mut self, result: Result<()>) {
+        if let Err(e) = result {
+            println!("Session {}: Log write failed: {:?}", self.id, e);
+            self.log = None;
+            self.terminal
+                .add_message(&format!("Logging stopped: {}", e));
+        }
+    }
+
+    pub fn disconnect(&mut self) {
+        if self.status.is_active() {
+            self.flush_line();
+            self.stop_file_send("disconnected");
+            self.stop_transfer("disconnected");
+            self.stop_sequence("disconnected");
+            self.stop_script();
+            if let Some(mut controller) = self.controller.take() {
+                controller.end_stream();
+            }
+            self.status = ConnectionStatus::Disconnected;
+            self.modem_state = None;
+            self.virtual_peer = None;
+            self.terminal.add_message("Disconnected");
+        }
+    }
+
+    pub fn send(&mut self, data: Vec<u8>) {
+        if let Some(controller) = &self.controller {
+            if let Err(e) = controller.push(data.clone()) {
+                self.terminal
+                    .add_message(&format!("Error sending data: {}", e));
+            } else {
+                self.record_sent(&data, data.clone(), Source::Application);
+            }
+        }
+    }
+
+    /// Sends a macro's payload with its own line ending rather than the session's
+    pub fn send_macro(&mut self, m: &Macro) {
+        let Some(controller) = &self.controller else {
+            self.terminal.add_message("Not connected to any port");
+            return;
+        };
+        match m.bytes(self.encoding) {
+            Ok((raw, text)) => {
+                println!("Session {}: Sending macro '{}'", self.id, m.name);
+                if let Err(e) = controller.push(raw.clone()) {
+                    self.terminal
+                        .add_message(&format!("Error sending data: {}", e));
+                } else {
+                    self.record_sent(&raw, text, Source::Macro);
+                }
+            }
+            Err(e) => {
+                println!("Session {}: Invalid macro '{}': {}", self.id, m.name, e);
+                self.terminal
+                    .add_message(&format!("Macro {}: {}", m.name, e));
+            }
+        }
+    }
+
+    pub fn handle_connection_event(&mut self, event: ConnectionEvent) -> Task<Message> {
+        match event {
+            ConnectionEvent::Ready(transmitter) => {
+                if let Some(controller) = &mut self.controller {
+                    controller.attach(transmitter);
+                }
+            }
+            ConnectionEvent::Connected(connected_at) => {
+                if let ConnectionStatus::Connecting(name) = &self.status {
+                    let name = name.clone();
+                    self.terminal.set_connected_at(connected_at);
+                    println!("Session {}: Successfully connected to {}", self.id, name);
+                    self.terminal.add_message(&format!("Connected to {}", name));
+                    self.status = ConnectionStatus::Connected(name);
+                }
+            }
+            ConnectionEvent::ConnectFailed(e) => {
+                let name = match &self.status {
+                    ConnectionStatus::Connecting(name) => name.clone(),
+                    _ => String::from("port"),
+                };
+                println!("Session {}: Failed to connect to {}: {}", self.id, name, e);
+                self.controller = None;
+                self.terminal
+                    .add_message(&format!("Failed to connect to {}: {}", name, e));
+                self.status = ConnectionStatus::Failed(e);
+            }
+            ConnectionEvent::Data(data, time) => {
+                println!(
+                    "Session {}: Processing received {} bytes",
+                    self.id,
+                    data.len()
+                );
+                if let Some(log) = &mut self.log {
+                    let result = log.received_chunk(&data);
+                    self.check_log(result);
+                }
+                if self.transfer.is_some() {
+                    self.transfer_received(&data);
+                    return Task::none();
+                }
+                if let Some(script) = &self.script {
+                    script.received(&data);
+                }
+
+                // Data up to a ZMODEM header is still shown, the rest is for the transfer
+                let auto_start = self.auto_start.detect(&data);
+                let shown = auto_start.map_or(data.len(), |(_, index)| index);
+                if let Some(controller) = &mut self.controller {
+                    for received in controller.receive(&data[..shown], time) {
+                        self.show_received(received);
+                    }
+                }
+                // Fed after showing the data so replies appear below what they answer
+                if let Some(run) = &mut self.sequence {
+                    let mut out = sequence::Output::default();
+                    run.received(&data[..shown], Instant::now(), &mut out);
+                    self.apply_sequence_output(out);
+                }
+                match auto_start {
+                    Some((AutoStart::Download, index)) => {
+                        println!("Session {}: Remote sz detected", self.id);
+                        if self.can_start_transfer() {
+                            self.start_transfer(file_transfer::receiver(Protocol::Zmodem));
+                            self.transfer_received(&data[index..]);
+                        }
+                    }
+                    Some((AutoStart::Upload, _)) => {
+                        println!("Session {}: Remote rz detected", self.id);
+                        if self.transfer_settings.paths().is_empty() {
+                            self.terminal.add_message(
+                                "Remote rz is waiting, choose files under File transfer and press Send",
+                            );
+                        } else {
+                            return self.send_transfer_files(true);
+                        }
+                    }
+                    None => {}
+                }
+            }
+            ConnectionEvent::Written(write_id) => {
+                self.transfer_writes.remove(&write_id);
+                if let Some(sender) = &mut self.file_sender {
+                    if sender.written(write_id) {
+                        if sender.delay.is_zero() || sender.is_done() {
+                            self.send_next_chunk();
+                        } else {
+                            let id = self.id;
+                            return Task::perform(tokio::time::sleep(sender.delay), move |_| {
+                                Message::FileChunkDue(id)
+                            });
+                        }
+                    }
+                }
+            }
+            ConnectionEvent::WriteError(write_id, e) => {
+                self.terminal.add_message(&format!("Write error: {}", e));
+                // A failed chunk is never confirmed, so the file send would stall
+                if self
+                    .file_sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.is_in_flight(write_id))
+                {
+                    self.stop_file_send("write failed");
+                }
+                // Other writes failing, such as a typed line, leave the transfer alone
+                if self.transfer_writes.remove(&write_id) {
+                    self.stop_transfer("write failed");
+                }
+            }
+            ConnectionEvent::ReadError(e) => {
+                self.terminal.add_message(&format!("Read error: {}", e));
+            }
+            ConnectionEvent::LineSettingsError(e) => {
+                println!("Session {}: Line settings not applied: {}", self.id, e);
+                self.terminal
+                    .add_message(&format!("Line settings not applied: {}", e));
+            }
+            ConnectionEvent::ModemState(modem_state) => {
+                println!("Session {}: Modem state {}", self.id, modem_state);
+                self.modem_state = Some(modem_state);
+            }
+            ConnectionEvent::VirtualPortReady(path) => {
+                println!("Session {}: Virtual port peer at {}", self.id, path);
+                self.terminal.add_message(&format!(
+                    "Virtual port ready, open {} from a script or another session",
+                    path
+                ));
+                self.virtual_peer = Some(path);
+            }
+            ConnectionEvent::DeviceLost(reason) => {
+                let name = match &self.status {
+                    ConnectionStatus::Connected(name) => name.clone(),
+                    _ => String::from("port"),
+                };
+                println!("Session {}: Lost {}: {}", self.id, name, reason);
+                self.terminal.add_message(&format!(
+                    "--- {} lost ({}), waiting for device ---",
+                    name, reason
+                ));
+                self.status = ConnectionStatus::WaitingForDevice(name);
+                self.stop_file_send("device lost");
+                self.stop_transfer("device lost");
+            }
+            ConnectionEvent::Reconnected(name) => {
+                println!("Session {}: Reconnected to {}", self.id, name);
+                self.terminal
+                    .add_message(&format!("--- Reconnected to {} ---", name));
+                self.status = ConnectionStatus::Connected(name);
+            }
+            ConnectionEvent::Disconnected(reason) => {
+                println!("Session {}: Connection closed: {}", self.id, reason);
+                self.flush_line();
+                self.stop_file_send("disconnected");
+                self.stop_transfer("disconnected");
+                self.stop_sequence("disconnected");
+                self.stop_script();
+                self.controller = None;
+                self.status = ConnectionStatus::Disconnected;
+                self.modem_state = None;
+                self.virtual_peer = None;
+                self.terminal
+                    .add_message(&format!("Disconnected: {}", reason));
+            }
+        }
+        Task::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_failed_transfer_writes_stop_the_transfer() {
+        let mut session = Session::new(1);
+        session.transfer = Some(file_transfer::receiver(Protocol::XmodemCrc));
+        session.transfer_writes.extend([7, 8]);
+
+        let _ = session.handle_connection_event(ConnectionEvent::WriteError(
+            3,
+            String::from("typed line failed"),
+        ));
+        assert!(session.transfer.is_some());
+
+        let _ = session.handle_connection_event(ConnectionEvent::Written(7));
+        let _ = session
+            .handle_connection_event(ConnectionEvent::WriteError(7, String::from("late error")));
+        assert!(session.transfer.is_some());
+
+        let _ =
+            session.handle_connection_event(ConnectionEvent::WriteError(8, String::from("gone")));
+        assert!(session.transfer.is_none());
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+
+use crate::config::{LOG_DIRECTORY, LOG_FILENAME_TEMPLATE};
+
+/// What a session log contains
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    /// Bytes exactly as they went over the wire
+    Raw,
+    /// Decoded lines, each with a wall clock timestamp and direction
+    #[default]
+    Text,
+}
+
+impl LogFormat {
+    pub const ALL: [LogFormat; 2] = [LogFormat::Raw, LogFormat::Text];
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Raw => write!(f, "Raw bytes"),
+            LogFormat::Text => write!(f, "Timestamped text"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    pub format: LogFormat,
+    /// Also log sent data, not only received data
+    pub include_tx: bool,
+    pub template: String,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            include_tx: false,
+            template: LOG_FILENAME_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl LogSettings {
+    /// Fills in the file name template for a log of `port` started at `time`
+    pub fn file_name(&self, port: &str, time: SystemTime) -> String {
+        let time = DateTime::<Local>::from(time);
+        // Only the device name of a path such as /dev/ttyUSB0 is used
+        let port = port.rsplit(['/', '\\']).next().unwrap_or(port);
+        let port: String = port
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        self.template
+            .replace("{port}", &port)
+            .replace("{date}", &time.format("%Y-%m-%d").to_string())
+            .replace("{time}", &time.format("%H-%M-%S").to_string())
+    }
+}
+
+/// Open log file of a session, written to as traffic arrives
+#[derive(Debug)]
+pub struct SessionLog {
+    file: File,
+    path: PathBuf,
+    pub format: LogFormat,
+    pub include_tx: bool,
+}
+
+impl SessionLog {
+    /// Creates or appends to the log file for `port` in the log directory
+    pub fn open(settings: &LogSettings, port: &str) -> Result<Self> {
+        Self::open_in(Path::new(LOG_DIRECTORY), settings, port)
+    }
+
+    fn open_in(directory: &Path, settings: &LogSettings, port: &str) -> Result<Self> {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Cannot create {}", directory.display()))?;
+
+        let path = directory.join(settings.file_name(port, SystemTime::now()));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Cannot open {}", path.display()))?;
+
+        Ok(Self {
+            file,
+            path,
+            format: settings.format,
+            include_tx: settings.include_tx,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Logs bytes as read from the connection, before they are assembled into lines
+    pub fn received_chunk(&mut self, data: &[u8]) -> Result<()> {
+        match self.format {
+            LogFormat::Raw => self.file.write_all(data).map_err(Into::into),
+            LogFormat::Text => Ok(()),
+        }
+    }
+
+    /// Logs a received line or chunk decoded for display
+    pub fn received_line(&mut self, text: &str, time: SystemTime) -> Result<()> {
+        match self.format {
+            LogFormat::Raw => Ok(()),
+            LogFormat::Text => self.write_text("RX", text, time),
+        }
+    }
+
+    /// Logs sent bytes, `text` being how they are shown in the terminal
+    pub fn sent(&mut self, raw: &[u8], text: &str, time: SystemTime) -> Result<()> {
+        if !self.include_tx {
+            return Ok(());
+        }
+        match self.format {
+            LogFormat::Raw => self.file.write_all(raw).map_err(Into::into),
+            LogFormat::Text => self.write_text("TX", text, time),
+        }
+    }
+
+    fn write_text(&mut self, direction: &str, text: &str, time: SystemTime) -> Result<()> {
+        let timestamp = DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S%.3f");
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        for line in text.split('\n') {
+            writeln!(self.file, "[{}] {}: {}", timestamp, direction, line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn settings(format: LogFormat, include_tx: bool) -> LogSettings {
+        LogSettings {
+            format,
+            include_tx,
+            template: String::from("{port}.log"),
+        }
+    }
+
+    /// Opens a log in a fresh temporary directory, removed by the caller
+    fn open(name: &str, settings: &LogSettings) -> (SessionLog, PathBuf) {
+        let directory =
+            std::env::temp_dir().join(format!("session_log_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let log = SessionLog::open_in(&directory, settings, "/dev/ttyUSB0").unwrap();
+        (log, directory)
+    }
+
+    fn contents(log: SessionLog, directory: &Path) -> Vec<u8> {
+        let data = fs::read(log.path()).unwrap();
+        fs::remove_dir_all(directory).unwrap();
+        data
+    }
+
+    #[test]
+    fn file_name_fills_in_the_template() {
+        let settings = LogSettings {
+            template: String::from("{port}_{date}_{time}.log"),
+            ..LogSettings::default()
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let local = DateTime::<Local>::from(time);
+        let expected = format!(
+            "ttyUSB0_{}_{}.log",
+            local.format("%Y-%m-%d"),
+            local.format("%H-%M-%S")
+        );
+        assert_eq!(settings.file_name("/dev/ttyUSB0", time), expected);
+    }
+
+    #[test]
+    fn file_name_replaces_unsafe_characters_in_the_port() {
+        let settings = settings(LogFormat::Text, false);
+        let time = SystemTime::now();
+        assert_eq!(settings.file_name(r"\\.\COM3", time), "COM3.log");
+        assert_eq!(settings.file_name("host:23", time), "host_23.log");
+        assert_eq!(settings.file_name("a b*c", time), "a_b_c.log");
+    }
+
+    #[test]
+    fn raw_logs_bytes_as_received_and_sent() {
+        let (mut log, directory) = open("raw", &settings(LogFormat::Raw, true));
+        assert_eq!(log.path(), directory.join("ttyUSB0.log"));
+        let time = SystemTime::now();
+        log.received_chunk(b"\x01ab").unwrap();
+        log.received_line("ab", time).unwrap();
+        log.sent(b"AT\r", "AT", time).unwrap();
+        assert_eq!(contents(log, &directory), b"\x01abAT\r");
+    }
+
+    #[test]
+    fn text_timestamps_every_line() {
+        let (mut log, directory) = open("text", &settings(LogFormat::Text, true));
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let timestamp = DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S%.3f");
+        log.received_chunk(b"ignored").unwrap();
+        log.received_line("one\ntwo\n", time).unwrap();
+        log.sent(b"AT\r", "AT", time).unwrap();
+        let expected = format!("[{0}] RX: one\n[{0}] RX: two\n[{0}] TX: AT\n", timestamp);
+        assert_eq!(
+            String::from_utf8(contents(log, &directory)).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn sent_data_is_left_out_unless_asked_for() {
+        let (mut log, directory) = open("rx_only", &settings(LogFormat::Text, false));
+        log.sent(b"AT\r", "AT", SystemTime::now()).unwrap();
+        assert!(contents(log, &directory).is_empty());
+    }
+}
@@ -1,7 +1,24 @@
-use iced::widget::{button, column, container, pick_list, text};
-use iced::Element;
+use std::time::Duration;
 
-use crate::{myserial::SerialPortInfo, Message};
+use iced::widget::{
+    button, checkbox, column, container, pick_list, progress_bar, row, text, text_input,
+};
+use iced::{Alignment, Element};
+
+use crate::config::{
+    FILE_CHUNK_DELAYS_MS, FILE_CHUNK_SIZES, RECONNECT_BACKOFFS_MS, SERIAL_BAUD_RATES,
+};
+use crate::controller::{ConnectionStatus, ReconnectPolicy};
+use crate::encoding::Encoding;
+use crate::file_sender::SendFileSettings;
+use crate::file_transfer::{Protocol, TransferSettings};
+use crate::line_assembler::ReceiveMode;
+use crate::line_ending::{LineEndings, RxNewline, TxLineEnding};
+use crate::myserial::{DataBits, FlowControl, LineSettings, Parity, SerialPortInfo, StopBits};
+use crate::session::Session;
+use crate::session_log::{LogFormat, LogSettings};
+use crate::transport::TransportKind;
+use crate::Message;
 
 pub struct Sidebar {
     width: u32,
@@ -13,6 +30,25 @@ pub enum SidebarMessage {
     ConnectPressed,
     DisconnectPressed,
     RefreshPressed,
+    ReconnectPolicyChanged(ReconnectPolicy),
+    TransportSelected(TransportKind),
+    TcpAddressChanged(String),
+    LineSettingsChanged(LineSettings),
+    LineEndingsChanged(LineEndings),
+    ReceiveModeSelected(ReceiveMode),
+    EncodingSelected(Encoding),
+    LoggingToggled(bool),
+    LogSettingsChanged(LogSettings),
+    SendFileSettingsChanged(SendFileSettings),
+    SendFilePressed,
+    CancelSendFilePressed,
+    TransferSettingsChanged(TransferSettings),
+    TransferSendPressed,
+    TransferReceivePressed,
+    TransferCancelPressed,
+    ScriptPathChanged(String),
+    RunScriptPressed,
+    StopScriptPressed,
 }
 
 impl Default for Sidebar {
@@ -36,21 +72,338 @@ impl Sidebar {
             SidebarMessage::RefreshPressed => {
                 println!("Refresh button pressed");
             }
+            SidebarMessage::ReconnectPolicyChanged(policy) => {
+                println!("Reconnect policy changed: {:?}", policy);
+            }
+            SidebarMessage::TransportSelected(kind) => {
+                println!("Transport selected: {}", kind);
+            }
+            SidebarMessage::TcpAddressChanged(_) => {}
+            SidebarMessage::LineSettingsChanged(settings) => {
+                println!("Line settings changed: {:?}", settings);
+            }
+            SidebarMessage::LineEndingsChanged(line_endings) => {
+                println!("Line endings changed: {:?}", line_endings);
+            }
+            SidebarMessage::ReceiveModeSelected(receive_mode) => {
+                println!("Receive mode selected: {}", receive_mode);
+            }
+            SidebarMessage::EncodingSelected(encoding) => {
+                println!("Encoding selected: {}", encoding);
+            }
+            SidebarMessage::LoggingToggled(enabled) => {
+                println!("Logging toggled: {}", enabled);
+            }
+            SidebarMessage::LogSettingsChanged(_) => {}
+            SidebarMessage::SendFileSettingsChanged(_) => {}
+            SidebarMessage::SendFilePressed => {
+                println!("Send file button pressed");
+            }
+            SidebarMessage::CancelSendFilePressed => {
+                println!("Cancel send file button pressed");
+            }
+            SidebarMessage::TransferSettingsChanged(settings) => {
+                println!("Transfer protocol: {}", settings.protocol);
+            }
+            SidebarMessage::TransferSendPressed => {
+                println!("Transfer send button pressed");
+            }
+            SidebarMessage::TransferReceivePressed => {
+                println!("Transfer receive button pressed");
+            }
+            SidebarMessage::TransferCancelPressed => {
+                println!("Transfer cancel button pressed");
+            }
+            SidebarMessage::ScriptPathChanged(_) => {}
+            SidebarMessage::RunScriptPressed => {
+                println!("Run script button pressed");
+            }
+            SidebarMessage::StopScriptPressed => {
+                println!("Stop script button pressed");
+            }
         }
     }
 
     pub fn view<'a>(
         &self,
-        available_ports: &'a [SerialPortInfo],
-        selected_port: &'a Option<SerialPortInfo>,
-        is_connected: bool,
+        available_ports: Vec<SerialPortInfo>,
+        session: &'a Session,
     ) -> Element<'a, Message> {
-        let port_dropdown = pick_list(available_ports, selected_port.as_ref(), |port| {
-            Message::PortSelected(port.clone())
-        })
-        .placeholder("Select COM port...");
+        let status = &session.status;
+
+        let transport_dropdown =
+            pick_list(TransportKind::ALL, Some(session.transport_kind), |kind| {
+                Message::SidebarMessage(SidebarMessage::TransportSelected(kind))
+            });
+
+        let endpoint_input: Element<'a, Message> = match session.transport_kind {
+            TransportKind::Serial => {
+                pick_list(available_ports, session.selected_port.as_ref(), |port| {
+                    Message::PortSelected(port.clone())
+                })
+                .placeholder("Select COM port...")
+                .into()
+            }
+            TransportKind::Tcp | TransportKind::Rfc2217 => {
+                text_input("host:port", &session.tcp_address)
+                    .on_input(|address| {
+                        Message::SidebarMessage(SidebarMessage::TcpAddressChanged(address))
+                    })
+                    .into()
+            }
+            TransportKind::Virtual => match &session.virtual_peer {
+                Some(path) => text(format!("Peer: {}", path)).into(),
+                None => text("A pty pair is created on connect").into(),
+            },
+        };
+
+        let mut line_settings = column![].spacing(5);
+        if matches!(
+            session.transport_kind,
+            TransportKind::Serial | TransportKind::Rfc2217
+        ) {
+            let settings = session.line_settings;
+            let changed =
+                |settings| Message::SidebarMessage(SidebarMessage::LineSettingsChanged(settings));
+
+            line_settings = line_settings
+                .push(pick_list(
+                    SERIAL_BAUD_RATES,
+                    Some(settings.speed),
+                    move |speed| changed(LineSettings { speed, ..settings }),
+                ))
+                .push(pick_list(
+                    DataBits::ALL,
+                    Some(settings.data_bits),
+                    move |data_bits| {
+                        changed(LineSettings {
+                            data_bits,
+                            ..settings
+                        })
+                    },
+                ))
+                .push(pick_list(
+                    Parity::ALL,
+                    Some(settings.parity),
+                    move |parity| changed(LineSettings { parity, ..settings }),
+                ))
+                .push(pick_list(
+                    StopBits::ALL,
+                    Some(settings.stop_bits),
+                    move |stop_bits| {
+                        changed(LineSettings {
+                            stop_bits,
+                            ..settings
+                        })
+                    },
+                ))
+                .push(pick_list(
+                    FlowControl::ALL,
+                    Some(settings.flow_control),
+                    move |flow_control| {
+                        changed(LineSettings {
+                            flow_control,
+                            ..settings
+                        })
+                    },
+                ));
+        }
 
-        let connect_button = if is_connected {
+        let line_endings = session.line_endings;
+        let line_endings_changed = |line_endings| {
+            Message::SidebarMessage(SidebarMessage::LineEndingsChanged(line_endings))
+        };
+        let line_ending_settings = column![
+            pick_list(TxLineEnding::ALL, Some(line_endings.tx), move |tx| {
+                line_endings_changed(LineEndings { tx, ..line_endings })
+            }),
+            pick_list(RxNewline::ALL, Some(line_endings.rx), move |rx| {
+                line_endings_changed(LineEndings { rx, ..line_endings })
+            }),
+            pick_list(ReceiveMode::ALL, Some(session.receive_mode), |mode| {
+                Message::SidebarMessage(SidebarMessage::ReceiveModeSelected(mode))
+            }),
+            pick_list(Encoding::ALL, Some(session.encoding), |encoding| {
+                Message::SidebarMessage(SidebarMessage::EncodingSelected(encoding))
+            }),
+        ]
+        .spacing(5);
+
+        let log_settings = &session.log_settings;
+        let log_settings_changed =
+            |settings| Message::SidebarMessage(SidebarMessage::LogSettingsChanged(settings));
+        let logging = session.log.is_some();
+        let mut template_input = text_input("{port}_{date}_{time}.log", &log_settings.template);
+        // The file name is fixed once the log is open
+        if !logging {
+            template_input = template_input.on_input(move |template| {
+                log_settings_changed(LogSettings {
+                    template,
+                    ..log_settings.clone()
+                })
+            });
+        }
+        let mut log_column = column![
+            checkbox("Log to file", logging).on_toggle(|enabled| {
+                Message::SidebarMessage(SidebarMessage::LoggingToggled(enabled))
+            }),
+            pick_list(LogFormat::ALL, Some(log_settings.format), move |format| {
+                log_settings_changed(LogSettings {
+                    format,
+                    ..log_settings.clone()
+                })
+            }),
+            checkbox("Include TX", log_settings.include_tx).on_toggle(move |include_tx| {
+                log_settings_changed(LogSettings {
+                    include_tx,
+                    ..log_settings.clone()
+                })
+            }),
+            template_input,
+        ]
+        .spacing(5);
+        if let Some(log) = &session.log {
+            log_column = log_column
+                .push(text(format!("● Logging to {}", log.path().display())).style(text::success));
+        }
+
+        let file_settings = &session.send_file_settings;
+        let file_settings_changed =
+            |settings| Message::SidebarMessage(SidebarMessage::SendFileSettingsChanged(settings));
+        let mut send_file_column = column![
+            text("Send file:"),
+            text_input("Path to file", &file_settings.path).on_input(move |path| {
+                file_settings_changed(SendFileSettings {
+                    path,
+                    ..file_settings.clone()
+                })
+            }),
+            row![
+                text("Chunk"),
+                pick_list(
+                    FILE_CHUNK_SIZES,
+                    Some(file_settings.chunk_size),
+                    move |chunk_size| {
+                        file_settings_changed(SendFileSettings {
+                            chunk_size,
+                            ..file_settings.clone()
+                        })
+                    }
+                ),
+                text("Delay (ms)"),
+                pick_list(
+                    FILE_CHUNK_DELAYS_MS,
+                    Some(file_settings.delay_ms),
+                    move |delay_ms| {
+                        file_settings_changed(SendFileSettings {
+                            delay_ms,
+                            ..file_settings.clone()
+                        })
+                    }
+                ),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+        ]
+        .spacing(5);
+        send_file_column = match &session.file_sender {
+            Some(sender) => {
+                let (written, total) = sender.progress();
+                send_file_column
+                    .push(progress_bar(0.0..=total as f32, written as f32).height(10))
+                    .push(text(format!("{} / {} bytes", written, total)))
+                    .push(button("Cancel").on_press(Message::SidebarMessage(
+                        SidebarMessage::CancelSendFilePressed,
+                    )))
+            }
+            None => send_file_column.push(
+                button("Send file")
+                    .on_press(Message::SidebarMessage(SidebarMessage::SendFilePressed)),
+            ),
+        };
+
+        let transfer_settings = &session.transfer_settings;
+        let transfer_settings_changed =
+            |settings| Message::SidebarMessage(SidebarMessage::TransferSettingsChanged(settings));
+        let mut transfer_column = column![
+            text("File transfer:"),
+            pick_list(
+                Protocol::ALL,
+                Some(transfer_settings.protocol),
+                move |protocol| {
+                    transfer_settings_changed(TransferSettings {
+                        protocol,
+                        ..transfer_settings.clone()
+                    })
+                }
+            ),
+            text_input("Files to send, separated by ;", &transfer_settings.paths).on_input(
+                move |paths| {
+                    transfer_settings_changed(TransferSettings {
+                        paths,
+                        ..transfer_settings.clone()
+                    })
+                }
+            ),
+        ]
+        .spacing(5);
+        transfer_column = match &session.transfer {
+            Some(transfer) => {
+                let progress = transfer.progress();
+                let bytes = match progress.total {
+                    Some(total) => {
+                        transfer_column = transfer_column.push(
+                            progress_bar(0.0..=total.max(1) as f32, progress.bytes as f32)
+                                .height(10),
+                        );
+                        format!("{} / {} bytes", progress.bytes, total)
+                    }
+                    None => format!("{} bytes", progress.bytes),
+                };
+                transfer_column
+                    .push(text(transfer.name()))
+                    .push(text(format!(
+                        "{} {}, {} retries",
+                        progress.file.unwrap_or_default(),
+                        bytes,
+                        progress.retries
+                    )))
+                    .push(button("Cancel").on_press(Message::SidebarMessage(
+                        SidebarMessage::TransferCancelPressed,
+                    )))
+            }
+            None => transfer_column.push(
+                row![
+                    button("Send")
+                        .on_press(Message::SidebarMessage(SidebarMessage::TransferSendPressed)),
+                    button("Receive").on_press(Message::SidebarMessage(
+                        SidebarMessage::TransferReceivePressed
+                    )),
+                ]
+                .spacing(5),
+            ),
+        };
+
+        let mut script_column = column![
+            text("Script:"),
+            text_input("Path to .rhai script", &session.script_path).on_input(|path| {
+                Message::SidebarMessage(SidebarMessage::ScriptPathChanged(path))
+            }),
+        ]
+        .spacing(5);
+        script_column = match &session.script {
+            Some(script) => script_column
+                .push(text(format!("● Running {}", script.name)).style(text::success))
+                .push(
+                    button("Stop")
+                        .on_press(Message::SidebarMessage(SidebarMessage::StopScriptPressed)),
+                ),
+            None => script_column.push(
+                button("Run").on_press(Message::SidebarMessage(SidebarMessage::RunScriptPressed)),
+            ),
+        };
+
+        let connect_button = if status.is_active() {
             button("-").on_press(Message::SidebarMessage(SidebarMessage::DisconnectPressed))
         } else {
             button("+").on_press(Message::SidebarMessage(SidebarMessage::ConnectPressed))
@@ -59,22 +412,72 @@ impl Sidebar {
         let refresh_button =
             button("Refresh").on_press(Message::SidebarMessage(SidebarMessage::RefreshPressed));
 
-        let status_text = if is_connected {
-            if let Some(port) = selected_port {
-                text(format!("Connected to {}", port.name))
-            } else {
-                text("Connected")
+        let policy = session.reconnect_policy;
+        let policy_changed =
+            |policy| Message::SidebarMessage(SidebarMessage::ReconnectPolicyChanged(policy));
+        let reconnect_settings = column![
+            checkbox("Auto-reconnect", policy.enabled).on_toggle(move |enabled| {
+                policy_changed(ReconnectPolicy { enabled, ..policy })
+            }),
+            row![
+                text("Retry after (ms)"),
+                pick_list(
+                    RECONNECT_BACKOFFS_MS,
+                    Some(policy.initial_backoff.as_millis() as u64),
+                    move |ms| {
+                        policy_changed(ReconnectPolicy {
+                            initial_backoff: Duration::from_millis(ms),
+                            ..policy
+                        })
+                    }
+                ),
+                text("up to"),
+                pick_list(
+                    RECONNECT_BACKOFFS_MS,
+                    Some(policy.max_backoff.as_millis() as u64),
+                    move |ms| {
+                        policy_changed(ReconnectPolicy {
+                            max_backoff: Duration::from_millis(ms),
+                            ..policy
+                        })
+                    }
+                ),
+            ]
+            .spacing(5)
+            .align_y(Alignment::Center),
+        ]
+        .spacing(5);
+
+        let status_text = match status {
+            ConnectionStatus::Disconnected => text("Disconnected"),
+            ConnectionStatus::Connecting(name) => text(format!("Connecting to {}...", name)),
+            ConnectionStatus::Connected(name) => text(format!("Connected to {}", name)),
+            ConnectionStatus::WaitingForDevice(name) => {
+                text(format!("Waiting for {} to return...", name))
             }
-        } else {
-            text("Disconnected")
+            ConnectionStatus::Failed(e) => text(format!("Connection failed: {}", e)),
+        };
+
+        let modem_text = match &session.modem_state {
+            Some(modem_state) => text(modem_state.to_string()),
+            None => text(""),
         };
 
         let left_sidebar = column![
-            text("COM Ports:"),
-            port_dropdown,
+            text("Connection:"),
+            transport_dropdown,
+            endpoint_input,
+            line_settings,
+            line_ending_settings,
+            log_column,
+            send_file_column,
+            transfer_column,
+            script_column,
             connect_button,
             refresh_button,
+            reconnect_settings,
             status_text,
+            modem_text,
         ]
         .padding(10)
         .spacing(10);
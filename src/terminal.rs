@@ -1,29 +1,172 @@
 use iced::border;
-use iced::widget::{button, scrollable, text, text_input, Scrollable};
-use iced::widget::{column, container};
-use iced::{Alignment, Element, Length, Shadow};
+use iced::widget::text::{LineHeight, Wrapping};
+use iced::widget::{
+    button, checkbox, pick_list, rich_text, scrollable, span, text, text_input, Scrollable,
+};
+use iced::widget::{column, container, row, Space};
+use iced::{font, Alignment, Element, Font, Length, Shadow, Task};
 
 // use crate::theme::theme;
 
 use tracing::{event, Level};
 
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use crate::config::{TERMINAL_ROW_HEIGHT, TERMINAL_SCROLLBACK_LIMIT, WINDOW_HEIGHT};
+use crate::emulator::screen::{Cell, CellStyle, Screen};
+use crate::emulator::Emulator;
+use crate::encoding::Encoding;
+use crate::hex;
+use crate::history::History;
+use crate::scrollback::{Content, Direction, Line, Scrollback, Source};
+use crate::search::Search;
+use crate::timestamp::{TimestampFormat, TimestampMode, TimestampResolution};
 use crate::Message;
 
 #[derive(Debug, Clone)]
 pub enum TerminalPaneMessage {
     InputChanged(String),
     InputSubmit,
+    HexModeToggled(bool),
+    DisplayModeSelected(DisplayMode),
+    TimestampsChanged(TimestampFormat),
+    Scrolled(scrollable::Viewport),
+    SearchOpened,
+    SearchClosed,
+    SearchChanged(String),
+    SearchRegexToggled(bool),
+    SearchCaseToggled(bool),
+    SearchNext,
+    SearchPrevious,
+    /// Up and Down in the input line
+    HistoryPrevious,
+    HistoryNext,
+    /// Ctrl+R, pressed again for an older match
+    HistorySearchOpened,
+    HistorySearchChanged(String),
+    HistorySearchAccepted,
+    /// Closes the history search, or the search bar when it is not open
+    EscapePressed,
 }
 
-/// TerminalPane state
+/// How received bytes are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DisplayMode {
+    #[default]
+    Text,
+    /// Offset, hex bytes and ASCII gutter
+    HexDump,
+    /// Text with non-printable bytes shown as hex
+    Mixed,
+    /// VT100/xterm emulation of the received escape sequences, without
+    /// timestamps or search
+    Terminal,
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 4] = [
+        DisplayMode::Text,
+        DisplayMode::HexDump,
+        DisplayMode::Mixed,
+        DisplayMode::Terminal,
+    ];
+}
+
+impl std::fmt::Display for DisplayMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayMode::Text => write!(f, "Text"),
+            DisplayMode::HexDump => write!(f, "Hex dump"),
+            DisplayMode::Mixed => write!(f, "Mixed"),
+            DisplayMode::Terminal => write!(f, "Terminal (VT100)"),
+        }
+    }
+}
+
+/// Reverse incremental search through the command history
 #[derive(Debug, Default)]
+struct HistorySearch {
+    query: String,
+    /// History entry shown for the query
+    matched: Option<usize>,
+}
+
+/// A rendered display row
+#[derive(Debug)]
+struct Row {
+    text: String,
+    direction: Direction,
+}
+
+/// TerminalPane state
+#[derive(Debug)]
 pub struct TerminalPane {
     pub input_value: String,
-    pub display_value: String,
-    line_num: u32,
+    input_id: text_input::Id,
+    history: History,
+    /// Shown while searching the history with Ctrl+R
+    history_search: Option<HistorySearch>,
+    history_search_id: text_input::Id,
+    /// Input is parsed as hex bytes instead of sent as text
+    pub hex_mode: bool,
+    pub display_mode: DisplayMode,
+    pub timestamps: TimestampFormat,
+    /// Used to decode received text and to check typed input
+    pub encoding: Encoding,
+    connected_at: Option<SystemTime>,
+    /// Time of the last rendered received line, for delta timestamps
+    last_line_at: Option<SystemTime>,
+    input_error: Option<String>,
+    scrollback: Scrollback,
+    /// Display rows rendered from the scrollback in the current mode
+    rows: VecDeque<Row>,
+    /// Number of rows each scrollback line rendered to, oldest first
+    row_counts: VecDeque<usize>,
+    /// Distance of the viewport from the bottom, and its height
+    scroll_offset: f32,
+    viewport_height: f32,
+    scroll_id: scrollable::Id,
+    /// Shown while the search bar is open
+    search: Option<Search>,
+    search_input_id: text_input::Id,
+    emulator: Emulator,
+    received_bytes: usize,
+    sent_bytes: usize,
     char_num: u32,
 }
 
+impl Default for TerminalPane {
+    fn default() -> Self {
+        Self {
+            input_value: String::new(),
+            input_id: text_input::Id::unique(),
+            history: History::default(),
+            history_search: None,
+            history_search_id: text_input::Id::unique(),
+            hex_mode: false,
+            display_mode: DisplayMode::default(),
+            timestamps: TimestampFormat::default(),
+            encoding: Encoding::default(),
+            connected_at: None,
+            last_line_at: None,
+            input_error: None,
+            scrollback: Scrollback::new(TERMINAL_SCROLLBACK_LIMIT),
+            rows: VecDeque::new(),
+            row_counts: VecDeque::new(),
+            scroll_offset: 0.0,
+            viewport_height: 0.0,
+            scroll_id: scrollable::Id::unique(),
+            search: None,
+            search_input_id: text_input::Id::unique(),
+            emulator: Emulator::default(),
+            received_bytes: 0,
+            sent_bytes: 0,
+            char_num: 0,
+        }
+    }
+}
+
 impl TerminalPane {
     fn reg_data(&mut self, new_data: &String) {
         // match character amount
@@ -32,70 +175,337 @@ impl TerminalPane {
             _ => {}
         }
 
-        // match line amount and limit it to 30
-        match self.line_num {
-            (0..=30) => {
-                self.display_value.push_str(new_data);
-                self.display_value.push('\n');
-                self.line_num += 1;
+        self.add_message(new_data);
+    }
+
+    /// Stores a line and renders it, dropping the rows of any line it pushed out
+    fn push_line(&mut self, line: Line) {
+        if self.scrollback.push(line).is_some() {
+            let evicted_rows = self.row_counts.pop_front().unwrap_or(0);
+            let evicted_rows = evicted_rows.min(self.rows.len());
+            self.rows.drain(..evicted_rows);
+            if let Some(search) = &mut self.search {
+                search.remove_rows(evicted_rows);
             }
-            _ => {
-                let mut first_newline = self.display_value.chars().position(|c| c == '\n').unwrap();
-                first_newline += 1;
-                self.display_value = self.display_value[first_newline..].to_string();
-                self.display_value.push_str(new_data);
-                self.display_value.push('\n');
+        }
+
+        let first = self.rows.len();
+        self.render(self.scrollback.len() - 1);
+        if let Some(search) = &mut self.search {
+            search.scan(first, self.rows.range(first..).map(|row| row.text.as_str()));
+        }
+    }
+
+    /// Renders scrollback line `index` onto the end of the display
+    fn render(&mut self, index: usize) {
+        let Some(line) = self.scrollback.get(index) else {
+            return;
+        };
+
+        if self.display_mode == DisplayMode::Terminal {
+            // The device echoes what we send, so only its own output is emulated
+            match (&line.content, line.direction) {
+                (Content::Text(message), _) => self.emulator.feed_message(message),
+                (Content::Data { raw, .. }, Direction::Received) => {
+                    self.emulator.feed(&self.encoding.decode(raw))
+                }
+                (Content::Data { .. }, _) => {}
             }
+            self.row_counts.push_back(0);
+            return;
         }
+
+        let prefix = match (line.direction, line.source) {
+            (Direction::Received, _) => "Received: ",
+            (Direction::Sent, Source::Input) => "Sent: ",
+            (Direction::Sent, Source::Macro) => "Sent (macro): ",
+            (Direction::Sent, Source::Script) => "Sent (script): ",
+            (Direction::Sent, Source::Sequence) => "Sent (sequence): ",
+            (Direction::Sent, _) => "Sent (app): ",
+            (Direction::Status, _) => "",
+        };
+
+        let texts: Vec<String> = match &line.content {
+            Content::Text(message) => vec![message.clone()],
+            Content::Data { offset, raw, text } => match self.display_mode {
+                DisplayMode::Text => {
+                    let data_str = self.encoding.decode(text);
+                    let data_str = data_str.strip_suffix('\n').unwrap_or(&data_str);
+                    data_str
+                        .split('\n')
+                        .map(|part| format!("{}{}", prefix, part))
+                        .collect()
+                }
+                DisplayMode::HexDump => hex::dump(*offset, raw)
+                    .into_iter()
+                    .map(|part| format!("{}{}", prefix, part))
+                    .collect(),
+                DisplayMode::Mixed => {
+                    let text = text.strip_suffix(b"\n").unwrap_or(text);
+                    text.split(|&byte| byte == b'\n')
+                        .map(|part| format!("{}{}", prefix, hex::escape_non_printable(part)))
+                        .collect()
+                }
+                DisplayMode::Terminal => unreachable!("fed to the emulator above"),
+            },
+        };
+
+        let timestamp = self
+            .timestamps
+            .format(line.time, line.connected_at, self.last_line_at);
+        // Deltas measure the gaps in received data, sent lines and status
+        // messages in between do not reset them
+        if line.direction == Direction::Received {
+            self.last_line_at = Some(line.time);
+        }
+
+        let direction = line.direction;
+        self.row_counts.push_back(texts.len());
+        self.rows.extend(texts.into_iter().map(|text| Row {
+            text: match &timestamp {
+                Some(timestamp) => format!("[{}] {}", timestamp, text),
+                None => text,
+            },
+            direction,
+        }));
     }
 
     pub fn add_message(&mut self, message: &str) {
         // Add message to display without checking char_num
-        match self.line_num {
-            (0..=30) => {
-                self.display_value.push_str(message);
-                self.display_value.push('\n');
-                self.line_num += 1;
-            }
-            _ => {
-                if let Some(first_newline) = self.display_value.chars().position(|c| c == '\n') {
-                    self.display_value = self.display_value[first_newline + 1..].to_string();
-                    self.display_value.push_str(message);
-                    self.display_value.push('\n');
-                } else {
-                    // If no newline found, clear and add new message
-                    self.display_value = format!("{}\n", message);
-                    self.line_num = 1;
-                }
-            }
+        self.push_line(Line {
+            direction: Direction::Status,
+            source: Source::Application,
+            time: SystemTime::now(),
+            connected_at: self.connected_at,
+            content: Content::Text(message.to_string()),
+        });
+    }
+
+    /// Adds bytes received from the controller, `raw` as read and `text` with
+    /// newlines normalized, rendered one display row per `\n`
+    pub fn add_received(&mut self, raw: &[u8], text: Vec<u8>, time: SystemTime) {
+        let offset = self.received_bytes;
+        self.received_bytes += raw.len();
+        self.push_line(Line {
+            direction: Direction::Received,
+            source: Source::Device,
+            time,
+            connected_at: self.connected_at,
+            content: Content::Data {
+                offset,
+                raw: raw.to_vec(),
+                text,
+            },
+        });
+    }
+
+    /// Adds bytes queued for sending, `raw` as they go out and `text` as shown
+    /// in text mode
+    pub fn add_sent(&mut self, raw: &[u8], text: Vec<u8>, source: Source) {
+        let offset = self.sent_bytes;
+        self.sent_bytes += raw.len();
+        self.push_line(Line {
+            direction: Direction::Sent,
+            source,
+            time: SystemTime::now(),
+            connected_at: self.connected_at,
+            content: Content::Data {
+                offset,
+                raw: raw.to_vec(),
+                text,
+            },
+        });
+    }
+
+    /// Marks when the session connected, for timestamps relative to it
+    pub fn set_connected_at(&mut self, connected_at: SystemTime) {
+        self.connected_at = Some(connected_at);
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+        self.validate_input();
+        self.rerender();
+    }
+
+    /// Rebuilds the display rows from the scrollback after a display setting changed
+    fn rerender(&mut self) {
+        self.rows.clear();
+        self.row_counts.clear();
+        self.last_line_at = None;
+        self.emulator.reset();
+        for index in 0..self.scrollback.len() {
+            self.render(index);
         }
+        // Status queries in old output were answered when they arrived
+        self.emulator.take_responses();
+        self.rescan();
     }
 
-    pub fn view(&self) -> Element<'_, Message> {
-        let input_row = container(
-            text_input(">", &self.input_value)
-                .on_input(|value| {
-                    Message::TerminalPaneMessage(TerminalPaneMessage::InputChanged(value))
-                })
-                .on_submit(Message::TerminalPaneMessage(
-                    TerminalPaneMessage::InputSubmit,
-                ))
-                .width(Length::Fill)
-                .line_height(2.0)
-                .align_x(Alignment::Start),
+    /// Matches the search against all display rows
+    fn rescan(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.rescan(self.rows.iter().map(|row| row.text.as_str()));
+        }
+    }
+
+    /// Scrolls the current search hit into the middle of the viewport
+    fn scroll_to_hit(&mut self) -> Task<Message> {
+        let Some(row) = self.search.as_ref().and_then(|search| search.current_row()) else {
+            return Task::none();
+        };
+
+        let viewport_height = self.viewport_height();
+        let content_height = self.rows.len() as f32 * TERMINAL_ROW_HEIGHT;
+        let top = row as f32 * TERMINAL_ROW_HEIGHT - (viewport_height - TERMINAL_ROW_HEIGHT) / 2.0;
+        // The scrollable is anchored to the bottom, so offsets count from there
+        self.scroll_offset = (content_height - viewport_height - top.max(0.0)).max(0.0);
+        scrollable::scroll_to(
+            self.scroll_id.clone(),
+            scrollable::AbsoluteOffset {
+                x: 0.0,
+                y: self.scroll_offset,
+            },
         )
-        .height(Length::Shrink);
+    }
+
+    fn viewport_height(&self) -> f32 {
+        if self.viewport_height > 0.0 {
+            self.viewport_height
+        } else {
+            WINDOW_HEIGHT
+        }
+    }
+
+    /// Replies the emulator owes the device for status queries it received
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        self.emulator.take_responses()
+    }
+
+    /// Puts a command from the history into the input line
+    fn recall(&mut self, command: String) -> Task<Message> {
+        self.input_value = command;
+        self.char_num = self.input_value.chars().count() as u32;
+        self.validate_input();
+        Task::batch([
+            text_input::focus(self.input_id.clone()),
+            text_input::move_cursor_to_end(self.input_id.clone()),
+        ])
+    }
+
+    /// Checks the input against the current input mode
+    fn validate_input(&mut self) {
+        self.input_error = if self.input_value.trim().is_empty() {
+            None
+        } else if self.hex_mode {
+            hex::parse(&self.input_value).err().map(|e| e.to_string())
+        } else {
+            self.encoding
+                .encode(&self.input_value)
+                .err()
+                .map(|e| e.to_string())
+        };
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let has_error = self.input_error.is_some();
+        let placeholder = if self.hex_mode { "AA 55 01 ..." } else { ">" };
+
+        let input = text_input(placeholder, &self.input_value)
+            .id(self.input_id.clone())
+            .on_input(|value| {
+                Message::TerminalPaneMessage(TerminalPaneMessage::InputChanged(value))
+            })
+            .on_submit(Message::TerminalPaneMessage(
+                TerminalPaneMessage::InputSubmit,
+            ))
+            .width(Length::Fill)
+            .line_height(2.0)
+            .align_x(Alignment::Start)
+            .style(move |theme: &iced::Theme, status| {
+                let mut style = text_input::default(theme, status);
+                if has_error {
+                    style.border = style.border.color(theme.palette().danger);
+                }
+                style
+            });
+
+        let hex_toggle = checkbox("Hex", self.hex_mode).on_toggle(|enabled| {
+            Message::TerminalPaneMessage(TerminalPaneMessage::HexModeToggled(enabled))
+        });
+
+        let display_mode_list = pick_list(DisplayMode::ALL, Some(self.display_mode), |mode| {
+            Message::TerminalPaneMessage(TerminalPaneMessage::DisplayModeSelected(mode))
+        });
+
+        let timestamps = self.timestamps;
+        let timestamps_changed = |timestamps| {
+            Message::TerminalPaneMessage(TerminalPaneMessage::TimestampsChanged(timestamps))
+        };
+        let toolbar = row![
+            display_mode_list,
+            pick_list(TimestampMode::ALL, Some(timestamps.mode), move |mode| {
+                timestamps_changed(TimestampFormat { mode, ..timestamps })
+            }),
+            pick_list(
+                TimestampResolution::ALL,
+                Some(timestamps.resolution),
+                move |resolution| {
+                    timestamps_changed(TimestampFormat {
+                        resolution,
+                        ..timestamps
+                    })
+                }
+            ),
+        ]
+        .spacing(10);
+        let toolbar = if self.display_mode == DisplayMode::Terminal {
+            toolbar
+        } else {
+            toolbar.push(
+                button(text("Search")).on_press(Message::TerminalPaneMessage(
+                    TerminalPaneMessage::SearchOpened,
+                )),
+            )
+        };
+
+        let mut input_row = column![].spacing(5);
+        if let Some(history_search) = &self.history_search {
+            input_row = input_row.push(self.history_search_bar(history_search));
+        }
+        input_row = input_row.push(
+            row![input, hex_toggle]
+                .spacing(10)
+                .align_y(Alignment::Center),
+        );
+        if let Some(error) = &self.input_error {
+            input_row = input_row.push(text(error).style(text::danger));
+        }
+        let input_row = container(input_row).height(Length::Shrink);
+
+        let display: Element<'_, Message> = match self.display_mode {
+            DisplayMode::Terminal => rich_text(screen_spans(self.emulator.screen()))
+                .font(Font::MONOSPACE)
+                .into(),
+            _ => self.visible_rows(),
+        };
 
         let scroll = container(
             scrollable(
-                column![text(&self.display_value)]
+                column![display]
                     .width(Length::Fill)
                     .align_x(Alignment::Start),
             )
+            .id(self.scroll_id.clone())
             .height(Length::Fill)
             .direction(scrollable::Direction::Vertical(
                 scrollable::Scrollbar::default().width(5).scroller_width(5),
-            )),
+            ))
+            // Stays on the newest output unless scrolled away from it
+            .anchor_bottom()
+            .on_scroll(|viewport| {
+                Message::TerminalPaneMessage(TerminalPaneMessage::Scrolled(viewport))
+            }),
         )
         .padding(10)
         .style(|_| container::Style {
@@ -109,21 +519,414 @@ impl TerminalPane {
             },
         });
 
-        column![scroll, input_row].into()
+        let mut content = column![toolbar].spacing(5);
+        if let Some(search) = &self.search {
+            content = content.push(self.search_bar(search));
+        }
+        content.push(scroll).push(input_row).into()
+    }
+
+    fn history_search_bar<'a>(&'a self, history_search: &'a HistorySearch) -> Element<'a, Message> {
+        let matched = match history_search
+            .matched
+            .and_then(|index| self.history.get(index))
+        {
+            Some(command) => command,
+            None if history_search.query.is_empty() => "",
+            None => "No match",
+        };
+        row![
+            text("History:"),
+            text_input("Search history", &history_search.query)
+                .id(self.history_search_id.clone())
+                .on_input(|query| {
+                    Message::TerminalPaneMessage(TerminalPaneMessage::HistorySearchChanged(query))
+                })
+                .on_submit(Message::TerminalPaneMessage(
+                    TerminalPaneMessage::HistorySearchAccepted,
+                ))
+                .width(Length::FillPortion(1)),
+            text(matched)
+                .font(Font::MONOSPACE)
+                .width(Length::FillPortion(2)),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    fn search_bar<'a>(&'a self, search: &'a Search) -> Element<'a, Message> {
+        let has_error = search.error.is_some();
+        let search_input = text_input("Search", &search.query)
+            .id(self.search_input_id.clone())
+            .on_input(|query| {
+                Message::TerminalPaneMessage(TerminalPaneMessage::SearchChanged(query))
+            })
+            .on_submit(Message::TerminalPaneMessage(
+                TerminalPaneMessage::SearchNext,
+            ))
+            .width(Length::Fill)
+            .style(move |theme: &iced::Theme, status| {
+                let mut style = text_input::default(theme, status);
+                if has_error {
+                    style.border = style.border.color(theme.palette().danger);
+                }
+                style
+            });
+
+        let hit_count = match (search.current(), search.hit_count()) {
+            (_, 0) if search.query.is_empty() => String::new(),
+            (_, 0) => String::from("No matches"),
+            (Some(current), count) => format!("{} of {}", current + 1, count),
+            (None, count) => format!("{} matches", count),
+        };
+
+        let mut bar = column![row![
+            search_input,
+            checkbox("Regex", search.regex).on_toggle(|enabled| {
+                Message::TerminalPaneMessage(TerminalPaneMessage::SearchRegexToggled(enabled))
+            }),
+            checkbox("Match case", search.case_sensitive).on_toggle(|enabled| {
+                Message::TerminalPaneMessage(TerminalPaneMessage::SearchCaseToggled(enabled))
+            }),
+            text(hit_count),
+            button(text("Previous")).on_press(Message::TerminalPaneMessage(
+                TerminalPaneMessage::SearchPrevious
+            )),
+            button(text("Next")).on_press(Message::TerminalPaneMessage(
+                TerminalPaneMessage::SearchNext
+            )),
+            button(text("x"))
+                .style(button::secondary)
+                .on_press(Message::TerminalPaneMessage(
+                    TerminalPaneMessage::SearchClosed
+                )),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)]
+        .spacing(5);
+        if let Some(error) = &search.error {
+            bar = bar.push(text(error).style(text::danger));
+        }
+        bar.into()
     }
 
-    pub fn update(&mut self, message: TerminalPaneMessage) {
+    /// Only the rows inside the viewport are laid out, spacers stand in for
+    /// the rest so the scrollbar still covers the whole scrollback
+    fn visible_rows(&self) -> Element<'_, Message> {
+        // Hex dump columns only line up in a monospaced font
+        let font = match self.display_mode {
+            DisplayMode::Text => Font::DEFAULT,
+            _ => Font::MONOSPACE,
+        };
+
+        let total = self.rows.len();
+        let viewport_height = self.viewport_height();
+        let visible = (viewport_height / TERMINAL_ROW_HEIGHT).ceil() as usize + 1;
+        let content_height = total as f32 * TERMINAL_ROW_HEIGHT;
+        let top = (content_height - viewport_height - self.scroll_offset).max(0.0);
+        let first = ((top / TERMINAL_ROW_HEIGHT) as usize).min(total);
+        let last = (first + visible).min(total);
+
+        let mut rows = column![Space::with_height(first as f32 * TERMINAL_ROW_HEIGHT)];
+        for (index, row) in self.rows.range(first..last).enumerate() {
+            let style = match row.direction {
+                Direction::Received => text::default,
+                Direction::Sent => text::primary,
+                Direction::Status => text::secondary,
+            };
+            let line_height = LineHeight::Absolute(TERMINAL_ROW_HEIGHT.into());
+
+            let hits: Vec<_> = self
+                .search
+                .iter()
+                .flat_map(|search| search.hits_in_row(first + index))
+                .collect();
+            if hits.is_empty() {
+                rows = rows.push(
+                    text(row.text.as_str())
+                        .font(font)
+                        .line_height(line_height)
+                        .wrapping(Wrapping::None)
+                        .style(style),
+                );
+                continue;
+            }
+
+            let mut spans: Vec<text::Span<'_, Message>> = Vec::new();
+            let mut end = 0;
+            for (hit, current) in hits {
+                spans.push(span(&row.text[end..hit.range.start]));
+                let background = if current {
+                    iced::Color::from_rgb8(255, 150, 50)
+                } else {
+                    iced::Color::from_rgb8(255, 230, 100)
+                };
+                spans.push(
+                    span(&row.text[hit.range.clone()])
+                        .color(iced::Color::BLACK)
+                        .background(background),
+                );
+                end = hit.range.end;
+            }
+            spans.push(span(&row.text[end..]));
+            rows = rows.push(
+                rich_text(spans)
+                    .font(font)
+                    .line_height(line_height)
+                    .wrapping(Wrapping::None)
+                    .style(style),
+            );
+        }
+        rows.push(Space::with_height(
+            (total - last) as f32 * TERMINAL_ROW_HEIGHT,
+        ))
+        .into()
+    }
+
+    pub fn update(&mut self, message: TerminalPaneMessage) -> Task<Message> {
         match message {
+            TerminalPaneMessage::Scrolled(viewport) => {
+                self.scroll_offset = viewport.absolute_offset().y;
+                self.viewport_height = viewport.bounds().height;
+            }
             TerminalPaneMessage::InputChanged(value) => {
                 self.input_value = value;
                 self.char_num = self.input_value.chars().count() as u32;
+                self.history.stop_browsing();
+                self.validate_input();
+            }
+            TerminalPaneMessage::DisplayModeSelected(display_mode) => {
+                self.display_mode = display_mode;
+                if display_mode == DisplayMode::Terminal {
+                    self.search = None;
+                }
+                self.rerender();
+            }
+            TerminalPaneMessage::TimestampsChanged(timestamps) => {
+                self.timestamps = timestamps;
+                self.rerender();
+            }
+            TerminalPaneMessage::HexModeToggled(enabled) => {
+                self.hex_mode = enabled;
+                self.validate_input();
+            }
+            TerminalPaneMessage::SearchOpened => {
+                if self.display_mode != DisplayMode::Terminal {
+                    if self.search.is_none() {
+                        self.search = Some(Search::default());
+                    }
+                    return Task::batch([
+                        text_input::focus(self.search_input_id.clone()),
+                        text_input::select_all(self.search_input_id.clone()),
+                    ]);
+                }
+            }
+            TerminalPaneMessage::SearchClosed => {
+                self.search = None;
+            }
+            TerminalPaneMessage::SearchChanged(query) => {
+                if let Some(search) = &mut self.search {
+                    search.query = query;
+                    search.compile();
+                    self.rescan();
+                    return self.scroll_to_hit();
+                }
+            }
+            TerminalPaneMessage::SearchRegexToggled(enabled) => {
+                if let Some(search) = &mut self.search {
+                    search.regex = enabled;
+                    search.compile();
+                    self.rescan();
+                    return self.scroll_to_hit();
+                }
+            }
+            TerminalPaneMessage::SearchCaseToggled(enabled) => {
+                if let Some(search) = &mut self.search {
+                    search.case_sensitive = enabled;
+                    search.compile();
+                    self.rescan();
+                    return self.scroll_to_hit();
+                }
+            }
+            TerminalPaneMessage::SearchNext => {
+                if let Some(search) = &mut self.search {
+                    search.next();
+                    return self.scroll_to_hit();
+                }
+            }
+            TerminalPaneMessage::SearchPrevious => {
+                if let Some(search) = &mut self.search {
+                    search.previous();
+                    return self.scroll_to_hit();
+                }
             }
             TerminalPaneMessage::InputSubmit => {
+                // Keep malformed input around so it can be corrected
+                if self.input_error.is_some() {
+                    return Task::none();
+                }
                 event!(Level::INFO, "w");
+                self.history.push(&self.input_value);
                 self.reg_data(&(self.input_value.clone()));
                 self.input_value.clear();
                 self.char_num = 0;
             }
+            TerminalPaneMessage::HistoryPrevious => {
+                if self.history_search.is_none() {
+                    if let Some(command) = self.history.previous(&self.input_value) {
+                        return self.recall(command);
+                    }
+                }
+            }
+            TerminalPaneMessage::HistoryNext => {
+                if self.history_search.is_none() {
+                    if let Some(command) = self.history.next() {
+                        return self.recall(command);
+                    }
+                }
+            }
+            TerminalPaneMessage::HistorySearchOpened => {
+                match &mut self.history_search {
+                    Some(history_search) => {
+                        if let Some(older) = self
+                            .history
+                            .find(&history_search.query, history_search.matched)
+                        {
+                            history_search.matched = Some(older);
+                        }
+                    }
+                    None => self.history_search = Some(HistorySearch::default()),
+                }
+                return text_input::focus(self.history_search_id.clone());
+            }
+            TerminalPaneMessage::HistorySearchChanged(query) => {
+                if let Some(history_search) = &mut self.history_search {
+                    history_search.matched = self.history.find(&query, None);
+                    history_search.query = query;
+                }
+            }
+            TerminalPaneMessage::HistorySearchAccepted => {
+                if let Some(history_search) = self.history_search.take() {
+                    let matched = history_search
+                        .matched
+                        .and_then(|index| self.history.get(index))
+                        .map(str::to_string);
+                    if let Some(command) = matched {
+                        return self.recall(command);
+                    }
+                    return text_input::focus(self.input_id.clone());
+                }
+            }
+            TerminalPaneMessage::EscapePressed => {
+                if self.history_search.take().is_some() {
+                    return text_input::focus(self.input_id.clone());
+                }
+                self.search = None;
+            }
+        }
+        Task::none()
+    }
+}
+
+/// Spans for the emulated screen, one run per style with the cursor drawn inverted
+fn screen_spans(screen: &Screen) -> Vec<text::Span<'static, Message>> {
+    let (cursor_row, cursor_column) = screen.cursor();
+    let cursor_line = screen.scrollback_len() + cursor_row;
+    let line_count = screen.scrollback_len() + screen.used_rows();
+
+    let mut spans = Vec::new();
+    for (index, line) in screen.lines().take(line_count).enumerate() {
+        let cursor = (screen.cursor_visible && index == cursor_line).then_some(cursor_column);
+
+        // Trailing blanks are not drawn, except under the cursor
+        let mut end = line
+            .iter()
+            .rposition(|cell| *cell != Cell::default())
+            .map_or(0, |column| column + 1);
+        if let Some(cursor) = cursor {
+            end = end.max(cursor + 1);
+        }
+
+        let mut run = String::new();
+        let mut run_style = CellStyle::default();
+        for (column, cell) in line[..end].iter().enumerate() {
+            let mut style = cell.style;
+            if cursor == Some(column) {
+                style.inverse = !style.inverse;
+            }
+            if style != run_style && !run.is_empty() {
+                spans.push(cell_span(std::mem::take(&mut run), run_style));
+            }
+            run_style = style;
+            run.push(cell.c);
         }
+        if !run.is_empty() {
+            spans.push(cell_span(run, run_style));
+        }
+        spans.push(span("\n"));
+    }
+
+    spans
+}
+
+fn cell_span(content: String, style: CellStyle) -> text::Span<'static, Message> {
+    let to_color = |(r, g, b)| iced::Color::from_rgb8(r, g, b);
+    let mut foreground = style.foreground.to_rgb().map(to_color);
+    let mut background = style.background.to_rgb().map(to_color);
+    if style.inverse {
+        // The theme colors are unknown here, so inverted defaults use black on light gray
+        (foreground, background) = (
+            Some(background.unwrap_or(iced::Color::BLACK)),
+            Some(foreground.unwrap_or(iced::Color::from_rgb8(229, 229, 229))),
+        );
+    }
+    if style.dim {
+        foreground = foreground.map(|color| color.scale_alpha(0.6));
+    }
+
+    let font = Font {
+        weight: if style.bold {
+            font::Weight::Bold
+        } else {
+            font::Weight::Normal
+        },
+        style: if style.italic {
+            font::Style::Italic
+        } else {
+            font::Style::Normal
+        },
+        ..Font::MONOSPACE
+    };
+
+    span(content)
+        .font(font)
+        .color_maybe(foreground)
+        .background_maybe(background)
+        .underline(style.underline)
+        .strikethrough(style.strikethrough)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::timestamp::TimestampMode;
+
+    #[test]
+    fn delta_timestamps_skip_sent_lines_and_messages() {
+        let mut pane = TerminalPane::default();
+        pane.timestamps.mode = TimestampMode::Delta;
+        // Messages and sent lines are stamped with the current time
+        let now = SystemTime::now();
+
+        pane.add_received(b"first", b"first".to_vec(), now - Duration::from_secs(10));
+        pane.add_message("status");
+        pane.add_sent(b"ping", b"ping".to_vec(), Source::Input);
+        pane.add_received(b"second", b"second".to_vec(), now + Duration::from_secs(5));
+
+        let last = &pane.rows.back().unwrap().text;
+        assert!(last.starts_with("[+15.000] "), "got {}", last);
     }
 }
@@ -0,0 +1,186 @@
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+
+/// What a received line's timestamp is measured against
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimestampMode {
+    #[default]
+    Off,
+    WallClock,
+    /// Time since the session connected
+    SinceConnect,
+    /// Time since the previous received line
+    Delta,
+}
+
+impl TimestampMode {
+    pub const ALL: [TimestampMode; 4] = [
+        TimestampMode::Off,
+        TimestampMode::WallClock,
+        TimestampMode::SinceConnect,
+        TimestampMode::Delta,
+    ];
+}
+
+impl std::fmt::Display for TimestampMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimestampMode::Off => write!(f, "No timestamps"),
+            TimestampMode::WallClock => write!(f, "Wall clock"),
+            TimestampMode::SinceConnect => write!(f, "Since connect"),
+            TimestampMode::Delta => write!(f, "Delta"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimestampResolution {
+    #[default]
+    Millis,
+    Micros,
+}
+
+impl TimestampResolution {
+    pub const ALL: [TimestampResolution; 2] =
+        [TimestampResolution::Millis, TimestampResolution::Micros];
+}
+
+impl std::fmt::Display for TimestampResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimestampResolution::Millis => write!(f, "ms"),
+            TimestampResolution::Micros => write!(f, "µs"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimestampFormat {
+    pub mode: TimestampMode,
+    pub resolution: TimestampResolution,
+}
+
+impl TimestampFormat {
+    /// Formats `time`, captured when the data was read, or returns `None`
+    /// when timestamps are off
+    pub fn format(
+        &self,
+        time: SystemTime,
+        connected_at: Option<SystemTime>,
+        previous: Option<SystemTime>,
+    ) -> Option<String> {
+        match self.mode {
+            TimestampMode::Off => None,
+            TimestampMode::WallClock => {
+                let pattern = match self.resolution {
+                    TimestampResolution::Millis => "%H:%M:%S%.3f",
+                    TimestampResolution::Micros => "%H:%M:%S%.6f",
+                };
+                Some(DateTime::<Local>::from(time).format(pattern).to_string())
+            }
+            TimestampMode::SinceConnect => {
+                let elapsed = elapsed_since(connected_at.unwrap_or(time), time);
+                Some(self.seconds(elapsed))
+            }
+            TimestampMode::Delta => {
+                let elapsed = elapsed_since(previous.unwrap_or(time), time);
+                Some(format!("+{}", self.seconds(elapsed)))
+            }
+        }
+    }
+
+    fn seconds(&self, duration: Duration) -> String {
+        match self.resolution {
+            TimestampResolution::Millis => {
+                format!("{}.{:03}", duration.as_secs(), duration.subsec_millis())
+            }
+            TimestampResolution::Micros => {
+                format!("{}.{:06}", duration.as_secs(), duration.subsec_micros())
+            }
+        }
+    }
+}
+
+/// Clock adjustments can put `time` before `start`, which counts as no time
+fn elapsed_since(start: SystemTime, time: SystemTime) -> Duration {
+    time.duration_since(start).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn format(mode: TimestampMode, resolution: TimestampResolution) -> TimestampFormat {
+        TimestampFormat { mode, resolution }
+    }
+
+    fn at(micros: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn off_has_no_timestamp() {
+        let format = format(TimestampMode::Off, TimestampResolution::Millis);
+        assert_eq!(format.format(at(0), Some(at(0)), Some(at(0))), None);
+    }
+
+    #[test]
+    fn wall_clock_uses_local_time() {
+        let time = at(123_456);
+        let local = DateTime::<Local>::from(time).format("%H:%M:%S").to_string();
+        let millis = format(TimestampMode::WallClock, TimestampResolution::Millis);
+        let micros = format(TimestampMode::WallClock, TimestampResolution::Micros);
+        assert_eq!(
+            millis.format(time, None, None),
+            Some(format!("{}.123", local))
+        );
+        assert_eq!(
+            micros.format(time, None, None),
+            Some(format!("{}.123456", local))
+        );
+    }
+
+    #[test]
+    fn since_connect_counts_from_the_connection() {
+        let millis = format(TimestampMode::SinceConnect, TimestampResolution::Millis);
+        let micros = format(TimestampMode::SinceConnect, TimestampResolution::Micros);
+        let time = at(62_005_042);
+        assert_eq!(
+            millis.format(time, Some(at(0)), None).as_deref(),
+            Some("62.005")
+        );
+        assert_eq!(
+            micros.format(time, Some(at(0)), None).as_deref(),
+            Some("62.005042")
+        );
+        // Not connected yet, or a clock set back, counts as no time
+        assert_eq!(millis.format(time, None, None).as_deref(), Some("0.000"));
+        assert_eq!(
+            millis.format(at(0), Some(time), None).as_deref(),
+            Some("0.000")
+        );
+    }
+
+    #[test]
+    fn delta_counts_from_the_previous_line() {
+        let format = format(TimestampMode::Delta, TimestampResolution::Millis);
+        assert_eq!(
+            format
+                .format(at(1_500_000), Some(at(0)), Some(at(250_000)))
+                .as_deref(),
+            Some("+1.250")
+        );
+    }
+
+    #[test]
+    fn delta_of_the_first_line_is_zero() {
+        let format = format(TimestampMode::Delta, TimestampResolution::Micros);
+        assert_eq!(
+            format.format(at(1_500_000), Some(at(0)), None).as_deref(),
+            Some("+0.000000")
+        );
+    }
+}
@@ -0,0 +1,183 @@
+#[cfg(unix)]
+pub mod pty;
+pub mod rfc2217;
+pub mod serial;
+pub mod tcp;
+
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::Result;
+
+use crate::myserial::{LineSettings, SerialPortInfo};
+
+pub use tcp::TcpTarget;
+
+/// Boxed future returned by `Transport` methods so the trait stays object safe
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Byte stream a `TerminalRunner` reads from and writes to
+pub trait Transport: Send {
+    /// Waits until something arrives, returns `Ok(0)` when only control traffic
+    /// came in and an error once the other end has closed. Must be cancel safe
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, usize>;
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> TransportFuture<'a, ()>;
+
+    fn close(&mut self) -> TransportFuture<'_, ()>;
+
+    /// Whether the other end is still reachable after an I/O error
+    fn is_present(&self) -> bool;
+
+    /// Latest modem line state, if it changed since the last call
+    fn poll_modem_state(&mut self) -> Option<ModemState> {
+        None
+    }
+
+    /// Changes speed, framing and flow control of the open line, endpoints
+    /// without a serial line ignore it
+    fn set_line_settings<'a>(&'a mut self, _settings: &'a LineSettings) -> TransportFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Device path the other end of a virtual port pair can be opened at
+    fn peer_port(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Modem status lines as reported by the remote side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModemState {
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+}
+
+impl ModemState {
+    /// Decodes the RFC 2217 NOTIFY-MODEMSTATE bit layout
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            cd: bits & 0x80 != 0,
+            ri: bits & 0x40 != 0,
+            dsr: bits & 0x20 != 0,
+            cts: bits & 0x10 != 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ModemState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line = |name: &str, on: bool| format!("{}{}", name, if on { "+" } else { "-" });
+        write!(
+            f,
+            "{} {} {} {}",
+            line("CTS", self.cts),
+            line("DSR", self.dsr),
+            line("RI", self.ri),
+            line("CD", self.cd)
+        )
+    }
+}
+
+/// Kinds of endpoint selectable in the sidebar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    Serial,
+    Tcp,
+    Rfc2217,
+    Virtual,
+}
+
+impl TransportKind {
+    pub const ALL: [TransportKind; 4] = [
+        TransportKind::Serial,
+        TransportKind::Tcp,
+        TransportKind::Rfc2217,
+        TransportKind::Virtual,
+    ];
+}
+
+impl std::fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportKind::Serial => write!(f, "Serial"),
+            TransportKind::Tcp => write!(f, "TCP"),
+            TransportKind::Rfc2217 => write!(f, "RFC 2217"),
+            TransportKind::Virtual => write!(f, "Virtual port"),
+        }
+    }
+}
+
+/// Where a session connects to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Serial(SerialPortInfo),
+    Tcp(TcpTarget),
+    /// Remote server plus the line settings to negotiate with it
+    Rfc2217(TcpTarget, SerialPortInfo),
+    /// Pseudo-terminal pair created on connect
+    Virtual,
+}
+
+impl Endpoint {
+    pub fn name(&self) -> String {
+        match self {
+            Endpoint::Serial(sinfo) => sinfo.name.clone(),
+            Endpoint::Tcp(target) => target.to_string(),
+            Endpoint::Rfc2217(target, _) => format!("rfc2217://{}", target),
+            Endpoint::Virtual => String::from("virtual"),
+        }
+    }
+
+    pub async fn open(&self) -> Result<Box<dyn Transport>> {
+        match self {
+            Endpoint::Serial(sinfo) => Ok(Box::new(serial::SerialTransport::open(sinfo)?)),
+            Endpoint::Tcp(target) => Ok(Box::new(tcp::TcpTransport::open(target).await?)),
+            Endpoint::Rfc2217(target, sinfo) => Ok(Box::new(
+                rfc2217::Rfc2217Transport::open(target, sinfo).await?,
+            )),
+            #[cfg(unix)]
+            Endpoint::Virtual => Ok(Box::new(pty::PtyTransport::open()?)),
+            #[cfg(not(unix))]
+            Endpoint::Virtual => Err(anyhow::anyhow!("Virtual ports need a Unix pseudo-terminal")),
+        }
+    }
+
+    /// Records line settings changed while connected, so reopening the
+    /// endpoint uses them
+    pub fn set_line_settings(&mut self, settings: &LineSettings) {
+        match self {
+            Endpoint::Serial(sinfo) | Endpoint::Rfc2217(_, sinfo) => {
+                *sinfo = sinfo.clone().with_settings(settings);
+            }
+            Endpoint::Tcp(_) | Endpoint::Virtual => {}
+        }
+    }
+
+    /// Whether the device can be unplugged and come back, which makes a lost
+    /// connection worth waiting for
+    pub fn is_removable(&self) -> bool {
+        matches!(self, Endpoint::Serial(_))
+    }
+
+    /// Finds a removable endpoint again after it went away, `None` while it is
+    /// still missing
+    pub fn locate(&self) -> Option<Endpoint> {
+        match self {
+            Endpoint::Serial(sinfo) => serial::find_port(sinfo).map(|name| {
+                let mut sinfo = sinfo.clone();
+                sinfo.name = name;
+                Endpoint::Serial(sinfo)
+            }),
+            Endpoint::Tcp(_) | Endpoint::Rfc2217(_, _) | Endpoint::Virtual => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
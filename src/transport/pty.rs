@@ -0,0 +1,76 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+
+use anyhow::{anyhow, Result};
+use serialport::SerialPort;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::SerialStream;
+
+use super::{Transport, TransportFuture};
+
+/// Pseudo-terminal pair, the session owns the master side and the slave
+/// device can be opened by a script or another session like a real port
+pub struct PtyTransport {
+    master: SerialStream,
+    // Held open so reads on the master don't fail while nobody has the slave open
+    _slave: File,
+    slave_name: String,
+}
+
+impl PtyTransport {
+    pub fn open() -> Result<Self> {
+        let (master, mut slave) = SerialStream::pair()?;
+        // Let other processes and sessions open the slave device
+        slave.set_exclusive(false)?;
+        let slave_name = slave
+            .name()
+            .ok_or_else(|| anyhow!("Pseudo-terminal has no device path"))?;
+
+        // serialport keeps a lock on every port it opens, which would stop
+        // sessions using it from opening the slave, so hold it as a plain file
+        let holder = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&slave_name)?;
+        drop(slave);
+
+        Ok(Self {
+            master,
+            _slave: holder,
+            slave_name,
+        })
+    }
+}
+
+impl Transport for PtyTransport {
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            match self.master.read(buf).await? {
+                0 => Err(anyhow!("Virtual port closed")),
+                bytes_read => Ok(bytes_read),
+            }
+        })
+    }
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.master.write_all(data).await?;
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> TransportFuture<'_, ()> {
+        // Both ends are closed when the transport is dropped
+        Box::pin(async { Ok(()) })
+    }
+
+    fn is_present(&self) -> bool {
+        // The pair lives exactly as long as the transport
+        true
+    }
+
+    fn peer_port(&self) -> Option<String> {
+        Some(self.slave_name.clone())
+    }
+}
@@ -0,0 +1,509 @@
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout_at, Instant};
+
+use super::{tcp, ModemState, TcpTarget, Transport, TransportFuture};
+use crate::config::RFC2217_NEGOTIATION_TIMEOUT;
+use crate::myserial::{DataBits, FlowControl, LineSettings, Parity, SerialPortInfo, StopBits};
+
+/* telnet commands */
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/* telnet options */
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+/* com port option subcommands, the server answers with the value + 100 */
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_LINESTATE: u8 = 6;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const SERVER_OFFSET: u8 = 100;
+
+/// Where the telnet parser is within the incoming byte stream
+enum TelnetState {
+    Data,
+    Iac,
+    Command(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Serial port on a remote RFC 2217 (Telnet COM-PORT-OPTION) server
+pub struct Rfc2217Transport {
+    stream: TcpStream,
+    state: TelnetState,
+    subnegotiation: Vec<u8>,
+    replies: Vec<u8>,
+    com_port_accepted: Option<bool>,
+    modem_state: Option<ModemState>,
+}
+
+impl Rfc2217Transport {
+    pub async fn open(target: &TcpTarget, sinfo: &SerialPortInfo) -> Result<Self> {
+        let stream = tcp::connect(target).await?;
+
+        let mut transport = Self {
+            stream,
+            state: TelnetState::Data,
+            subnegotiation: Vec::new(),
+            replies: Vec::new(),
+            com_port_accepted: None,
+            modem_state: None,
+        };
+        transport.negotiate(sinfo).await?;
+
+        Ok(transport)
+    }
+
+    async fn negotiate(&mut self, sinfo: &SerialPortInfo) -> Result<()> {
+        let request = [
+            IAC,
+            WILL,
+            COM_PORT_OPTION,
+            IAC,
+            WILL,
+            BINARY,
+            IAC,
+            DO,
+            BINARY,
+            IAC,
+            WILL,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            DO,
+            SUPPRESS_GO_AHEAD,
+        ];
+        self.stream.write_all(&request).await?;
+
+        // Wait for the server to accept or refuse the COM-PORT-OPTION, any data
+        // arriving meanwhile is dropped as the session is not reported connected yet
+        let deadline = Instant::now() + RFC2217_NEGOTIATION_TIMEOUT;
+        let mut buf = [0; 256];
+        while self.com_port_accepted.is_none() {
+            match timeout_at(deadline, self.receive(&mut buf)).await {
+                Ok(result) => {
+                    result?;
+                }
+                Err(_) => break,
+            }
+        }
+
+        match self.com_port_accepted {
+            Some(false) => Err(anyhow!("Server refused the COM-PORT-OPTION")),
+            Some(true) => {
+                let mut settings = line_settings(&sinfo.line_settings());
+                // Ask for every modem line change
+                settings.extend(subnegotiation(SET_MODEMSTATE_MASK, &[0xFF]));
+                self.stream.write_all(&settings).await?;
+                Ok(())
+            }
+            None => {
+                println!(
+                    "RFC 2217: Server did not answer the COM-PORT-OPTION, continuing without line settings"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs the telnet state machine over `input`, moving payload bytes into `output`
+    fn parse(&mut self, input: &[u8], output: &mut [u8]) -> usize {
+        let mut written = 0;
+
+        for &byte in input {
+            match self.state {
+                TelnetState::Data => {
+                    if byte == IAC {
+                        self.state = TelnetState::Iac;
+                    } else {
+                        output[written] = byte;
+                        written += 1;
+                    }
+                }
+                TelnetState::Iac => {
+                    self.state = match byte {
+                        IAC => {
+                            // Escaped 0xFF in the data stream
+                            output[written] = IAC;
+                            written += 1;
+                            TelnetState::Data
+                        }
+                        DO | DONT | WILL | WONT => TelnetState::Command(byte),
+                        SB => {
+                            self.subnegotiation.clear();
+                            TelnetState::Subnegotiation
+                        }
+                        _ => TelnetState::Data,
+                    };
+                }
+                TelnetState::Command(command) => {
+                    self.handle_command(command, byte);
+                    self.state = TelnetState::Data;
+                }
+                TelnetState::Subnegotiation => {
+                    if byte == IAC {
+                        self.state = TelnetState::SubnegotiationIac;
+                    } else {
+                        self.subnegotiation.push(byte);
+                    }
+                }
+                TelnetState::SubnegotiationIac => {
+                    self.state = match byte {
+                        SE => {
+                            self.handle_subnegotiation();
+                            TelnetState::Data
+                        }
+                        _ => {
+                            self.subnegotiation.push(byte);
+                            TelnetState::Subnegotiation
+                        }
+                    };
+                }
+            }
+        }
+
+        written
+    }
+
+    fn handle_command(&mut self, command: u8, option: u8) {
+        let supported = matches!(option, BINARY | SUPPRESS_GO_AHEAD | COM_PORT_OPTION);
+
+        match command {
+            // Options we support were already offered in `negotiate`
+            DO if option == COM_PORT_OPTION => self.com_port_accepted = Some(true),
+            DONT if option == COM_PORT_OPTION => self.com_port_accepted = Some(false),
+            DO if !supported => self.replies.extend([IAC, WONT, option]),
+            WILL if !supported => self.replies.extend([IAC, DONT, option]),
+            _ => {}
+        }
+    }
+
+    fn handle_subnegotiation(&mut self) {
+        let (option, command, value) = match self.subnegotiation.as_slice() {
+            [option, command, value @ ..] => (*option, *command, value),
+            _ => return,
+        };
+        if option != COM_PORT_OPTION {
+            return;
+        }
+
+        match command.wrapping_sub(SERVER_OFFSET) {
+            NOTIFY_MODEMSTATE => {
+                if let Some(&bits) = value.first() {
+                    self.modem_state = Some(ModemState::from_bits(bits));
+                }
+            }
+            NOTIFY_LINESTATE => {
+                if let Some(&bits) = value.first() {
+                    println!("RFC 2217: Line state changed: {:#04x}", bits);
+                }
+            }
+            SET_BAUDRATE => {
+                if let Ok(speed) = <[u8; 4]>::try_from(value) {
+                    println!(
+                        "RFC 2217: Server baud rate is {}",
+                        u32::from_be_bytes(speed)
+                    );
+                }
+            }
+            other => {
+                println!(
+                    "RFC 2217: Server acknowledged command {} with {:?}",
+                    other, value
+                );
+            }
+        }
+    }
+
+    /// Reads from the socket and strips telnet traffic, returns the payload size
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        // Answer negotiations from the previous read first. A single write is
+        // cancel safe, so only what it reports written is dropped and nothing
+        // is lost or repeated if the read is cancelled in between
+        while !self.replies.is_empty() {
+            let written = self.stream.write(&self.replies).await?;
+            self.replies.drain(..written);
+        }
+
+        let mut raw = vec![0; buf.len()];
+        let bytes_read = match self.stream.read(&mut raw).await? {
+            0 => return Err(anyhow!("Connection closed by remote host")),
+            bytes_read => bytes_read,
+        };
+
+        Ok(self.parse(&raw[..bytes_read], buf))
+    }
+}
+
+impl Transport for Rfc2217Transport {
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, usize> {
+        Box::pin(self.receive(buf))
+    }
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let mut escaped = Vec::with_capacity(data.len());
+            for &byte in data {
+                escaped.push(byte);
+                if byte == IAC {
+                    escaped.push(IAC);
+                }
+            }
+            self.stream.write_all(&escaped).await?;
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> TransportFuture<'_, ()> {
+        Box::pin(async move {
+            self.stream.shutdown().await?;
+            Ok(())
+        })
+    }
+
+    fn is_present(&self) -> bool {
+        // Any socket error means the connection is gone
+        false
+    }
+
+    fn poll_modem_state(&mut self) -> Option<ModemState> {
+        self.modem_state.take()
+    }
+
+    fn set_line_settings<'a>(&'a mut self, settings: &'a LineSettings) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            if self.com_port_accepted != Some(true) {
+                return Err(anyhow!("Server did not accept the COM-PORT-OPTION"));
+            }
+            self.stream.write_all(&line_settings(settings)).await?;
+            Ok(())
+        })
+    }
+}
+
+/// SET_* subnegotiations for `settings`
+fn line_settings(settings: &LineSettings) -> Vec<u8> {
+    let mut bytes = subnegotiation(SET_BAUDRATE, &settings.speed.to_be_bytes());
+    bytes.extend(subnegotiation(
+        SET_DATASIZE,
+        &[data_size(settings.data_bits)],
+    ));
+    bytes.extend(subnegotiation(SET_PARITY, &[parity(settings.parity)]));
+    bytes.extend(subnegotiation(
+        SET_STOPSIZE,
+        &[stop_size(settings.stop_bits)],
+    ));
+    bytes.extend(subnegotiation(
+        SET_CONTROL,
+        &[control(settings.flow_control)],
+    ));
+    bytes
+}
+
+/// Builds `IAC SB COM-PORT-OPTION <command> <value> IAC SE`, escaping IAC in the value
+fn subnegotiation(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![IAC, SB, COM_PORT_OPTION, command];
+    for &byte in value {
+        bytes.push(byte);
+        if byte == IAC {
+            bytes.push(IAC);
+        }
+    }
+    bytes.extend([IAC, SE]);
+    bytes
+}
+
+fn data_size(data_bits: DataBits) -> u8 {
+    data_bits as u8
+}
+
+fn parity(parity: Parity) -> u8 {
+    match parity {
+        Parity::None => 1,
+        Parity::Odd => 2,
+        Parity::Even => 3,
+    }
+}
+
+fn stop_size(stop_bits: StopBits) -> u8 {
+    match stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+fn control(flow_control: FlowControl) -> u8 {
+    match flow_control {
+        FlowControl::None => 1,
+        FlowControl::Software => 2,
+        FlowControl::Hardware => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    async fn listen() -> (TcpListener, TcpTarget) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = TcpTarget {
+            host: String::from("127.0.0.1"),
+            port: listener.local_addr().unwrap().port(),
+        };
+        (listener, target)
+    }
+
+    fn port() -> SerialPortInfo {
+        SerialPortInfo::new(
+            String::from("remote"),
+            115200,
+            DataBits::Eight,
+            Parity::Even,
+            StopBits::Two,
+            FlowControl::Hardware,
+        )
+    }
+
+    /// Asserts the client sends nothing more for a while
+    async fn assert_quiet(stream: &mut TcpStream) {
+        let mut probe = [0; 1];
+        assert!(timeout(Duration::from_millis(100), stream.read(&mut probe))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn sends_settings_once_accepted_and_reports_modem_state() {
+        let (listener, target) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut offer = [0; 15];
+            stream.read_exact(&mut offer).await.unwrap();
+            assert_eq!(offer[..3], [IAC, WILL, COM_PORT_OPTION]);
+            assert_quiet(&mut stream).await;
+
+            stream.write_all(&[IAC, DO, COM_PORT_OPTION]).await.unwrap();
+            let expected = [
+                &[IAC, SB, COM_PORT_OPTION, 1, 0x00, 0x01, 0xC2, 0x00, IAC, SE][..],
+                &[IAC, SB, COM_PORT_OPTION, 2, 8, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 3, 3, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 4, 2, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 5, 3, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 11, IAC, IAC, IAC, SE],
+            ]
+            .concat();
+            let mut settings = vec![0; expected.len()];
+            stream.read_exact(&mut settings).await.unwrap();
+            assert_eq!(settings, expected);
+
+            // CD, DSR and CTS up, then data with an escaped 0xFF
+            stream
+                .write_all(&[IAC, SB, COM_PORT_OPTION, 107, 0xB0, IAC, SE])
+                .await
+                .unwrap();
+            stream.write_all(&[b'o', b'k', IAC, IAC]).await.unwrap();
+
+            let mut written = [0; 3];
+            stream.read_exact(&mut written).await.unwrap();
+            assert_eq!(written, [b'x', IAC, IAC]);
+        });
+
+        let mut transport = Rfc2217Transport::open(&target, &port()).await.unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 64];
+        while received.len() < 3 {
+            let bytes_read = transport.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..bytes_read]);
+        }
+        assert_eq!(received, [b'o', b'k', IAC]);
+        assert_eq!(
+            transport.poll_modem_state(),
+            Some(ModemState {
+                cts: true,
+                dsr: true,
+                ri: false,
+                cd: true,
+            })
+        );
+        assert_eq!(transport.poll_modem_state(), None);
+
+        transport.write(&[b'x', IAC]).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn changes_settings_on_the_open_connection() {
+        let (listener, target) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut offer = [0; 15];
+            stream.read_exact(&mut offer).await.unwrap();
+            stream.write_all(&[IAC, DO, COM_PORT_OPTION]).await.unwrap();
+            let mut initial = vec![0; 46];
+            stream.read_exact(&mut initial).await.unwrap();
+
+            let expected = [
+                &[IAC, SB, COM_PORT_OPTION, 1, 0x00, 0x00, 0x25, 0x80, IAC, SE][..],
+                &[IAC, SB, COM_PORT_OPTION, 2, 7, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 3, 1, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 4, 1, IAC, SE],
+                &[IAC, SB, COM_PORT_OPTION, 5, 1, IAC, SE],
+            ]
+            .concat();
+            let mut settings = vec![0; expected.len()];
+            stream.read_exact(&mut settings).await.unwrap();
+            assert_eq!(settings, expected);
+        });
+
+        let mut transport = Rfc2217Transport::open(&target, &port()).await.unwrap();
+        let settings = LineSettings {
+            speed: 9600,
+            data_bits: DataBits::Seven,
+            ..LineSettings::default()
+        };
+        transport.set_line_settings(&settings).await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refused_option_fails_without_sending_settings() {
+        let (listener, target) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut offer = [0; 15];
+            stream.read_exact(&mut offer).await.unwrap();
+            stream
+                .write_all(&[IAC, DONT, COM_PORT_OPTION])
+                .await
+                .unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty(), "sent {:?} after refusal", rest);
+        });
+
+        let error = Rfc2217Transport::open(&target, &port())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("refused"));
+        server.await.unwrap();
+    }
+}
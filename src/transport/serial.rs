@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+use serialport::{SerialPort, SerialPortBuilder, SerialPortType};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::SerialStream;
+
+use super::{Transport, TransportFuture};
+use crate::myserial::{LineSettings, SerialPortInfo};
+
+/// Local serial port driven by tokio
+pub struct SerialTransport {
+    stream: SerialStream,
+    sinfo: SerialPortInfo,
+}
+
+impl SerialTransport {
+    pub fn open(sinfo: &SerialPortInfo) -> Result<Self> {
+        let sinfo_b: SerialPortBuilder = sinfo.clone().into();
+        let stream = SerialStream::open(&sinfo_b)?;
+
+        Ok(Self {
+            stream,
+            sinfo: sinfo.clone(),
+        })
+    }
+}
+
+impl Transport for SerialTransport {
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            match self.stream.read(buf).await? {
+                0 => Err(anyhow!("Port closed")),
+                bytes_read => Ok(bytes_read),
+            }
+        })
+    }
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.stream.write_all(data).await?;
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> TransportFuture<'_, ()> {
+        // The port is released when the stream is dropped
+        Box::pin(async move { Ok(self.stream.flush().await?) })
+    }
+
+    fn is_present(&self) -> bool {
+        find_port(&self.sinfo).is_some()
+    }
+
+    fn set_line_settings<'a>(&'a mut self, settings: &'a LineSettings) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.stream.set_baud_rate(settings.speed)?;
+            self.stream.set_data_bits(settings.data_bits.into())?;
+            self.stream.set_parity(settings.parity.into())?;
+            self.stream.set_stop_bits(settings.stop_bits.into())?;
+            self.stream.set_flow_control(settings.flow_control.into())?;
+            self.sinfo = self.sinfo.clone().with_settings(settings);
+            Ok(())
+        })
+    }
+}
+
+/// Looks up the current name of a port, matching by USB serial number when
+/// known and by port name otherwise
+pub fn find_port(sinfo: &SerialPortInfo) -> Option<String> {
+    let ports = serialport::available_ports().ok()?;
+    ports
+        .into_iter()
+        .find(|port| match (&sinfo.serial_number, &port.port_type) {
+            (Some(serial_number), SerialPortType::UsbPort(usb)) => {
+                usb.serial_number.as_ref() == Some(serial_number)
+            }
+            _ => port.port_name == sinfo.name,
+        })
+        .map(|port| port.port_name)
+}
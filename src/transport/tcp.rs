@@ -0,0 +1,152 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+use super::{Transport, TransportFuture};
+use crate::config::TCP_CONNECT_TIMEOUT;
+
+/// Remote `host:port`, e.g. a ser2net console
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TcpTarget {
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for TcpTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (host, port) = s
+            .trim()
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("Expected host:port, got '{}'", s))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(anyhow!("Missing host in '{}'", s));
+        }
+        let port = port
+            .parse()
+            .map_err(|_| anyhow!("Invalid port '{}'", port))?;
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for TcpTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Raw TCP client connection
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    pub async fn open(target: &TcpTarget) -> Result<Self> {
+        let stream = connect(target).await?;
+        Ok(Self { stream })
+    }
+}
+
+/// Connects to the first address of `target` that accepts
+pub async fn connect(target: &TcpTarget) -> Result<TcpStream> {
+    let mut last_error = anyhow!("Could not resolve {}", target);
+    for addr in lookup_host((target.host.as_str(), target.port)).await? {
+        match timeout(TCP_CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => {
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Ok(Err(e)) => last_error = anyhow!("{}: {}", addr, e),
+            Err(_) => last_error = anyhow!("{}: Connection timed out", addr),
+        }
+    }
+    Err(last_error)
+}
+
+impl Transport for TcpTransport {
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            match self.stream.read(buf).await? {
+                0 => Err(anyhow!("Connection closed by remote host")),
+                bytes_read => Ok(bytes_read),
+            }
+        })
+    }
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.stream.write_all(data).await?;
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> TransportFuture<'_, ()> {
+        Box::pin(async move {
+            self.stream.shutdown().await?;
+            Ok(())
+        })
+    }
+
+    fn is_present(&self) -> bool {
+        // Any socket error means the connection is gone
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(s: &str) -> String {
+        TcpTarget::from_str(s).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_host_and_port() {
+        let target: TcpTarget = " 192.168.1.10:4001 ".parse().unwrap();
+        assert_eq!(target.host, "192.168.1.10");
+        assert_eq!(target.port, 4001);
+        assert_eq!(target.to_string(), "192.168.1.10:4001");
+    }
+
+    #[test]
+    fn parses_a_bracketed_ipv6_address() {
+        let target: TcpTarget = "[::1]:23".parse().unwrap();
+        assert_eq!(target.host, "::1");
+        assert_eq!(target.port, 23);
+        assert_eq!(target.to_string(), "[::1]:23");
+    }
+
+    #[test]
+    fn rejects_a_host_without_port() {
+        assert_eq!(error("localhost"), "Expected host:port, got 'localhost'");
+        assert_eq!(error(""), "Expected host:port, got ''");
+    }
+
+    #[test]
+    fn rejects_a_missing_or_invalid_port() {
+        assert_eq!(error("localhost:"), "Invalid port ''");
+        assert_eq!(error("localhost:telnet"), "Invalid port 'telnet'");
+        assert_eq!(error("localhost:70000"), "Invalid port '70000'");
+    }
+
+    #[test]
+    fn rejects_a_missing_host() {
+        assert_eq!(error(":23"), "Missing host in ':23'");
+        assert_eq!(error("[]:23"), "Missing host in '[]:23'");
+    }
+}
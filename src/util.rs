@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Reads a saved JSON list, empty when there is no file yet
+pub fn load_json<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let json =
+        fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("Cannot parse {}", path.display()))
+}
+
+pub fn save_json<T: Serialize>(path: &Path, items: &[T]) -> Result<()> {
+    let json = serde_json::to_string_pretty(items)?;
+    fs::write(path, json).with_context(|| format!("Cannot write {}", path.display()))
+}
+
+/// Drops the oldest text once `buffer` grows past `limit` bytes, cutting on a
+/// character boundary
+pub fn keep_newest(buffer: &mut String, limit: usize) {
+    if buffer.len() > limit {
+        let cut = (buffer.len() - limit..buffer.len())
+            .find(|&index| buffer.is_char_boundary(index))
+            .unwrap_or(0);
+        buffer.drain(..cut);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_newest_cuts_on_a_character_boundary() {
+        let mut buffer = String::from("abc");
+        keep_newest(&mut buffer, 3);
+        assert_eq!(buffer, "abc");
+
+        let mut buffer = String::from("abcdé");
+        keep_newest(&mut buffer, 3);
+        assert_eq!(buffer, "dé");
+    }
+
+    #[test]
+    fn json_list_round_trip() {
+        let path = std::env::temp_dir().join(format!("util_test_{}.json", std::process::id()));
+        assert!(load_json::<String>(&path).unwrap().is_empty());
+
+        let items = vec![String::from("one"), String::from("two")];
+        save_json(&path, &items).unwrap();
+        assert_eq!(load_json::<String>(&path).unwrap(), items);
+
+        fs::write(&path, "not json").unwrap();
+        assert!(load_json::<String>(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}